@@ -0,0 +1,58 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use prahadlo::model::loader;
+use prahadlo::model::realtime;
+use prahadlo::server::{self, SharedNetwork};
+
+const WORKER_THREADS: usize = 8;
+// how often the realtime feed is fetched again
+const REALTIME_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Serves the network loaded from data/, or from the feeds in its subdirectories, over HTTP.
+/// Usage: server [address] [realtime feeds, either files or http:// URLs]
+/// With several feeds each realtime feed names the directory of its feed, e.g. pid=<URL>.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let address = args
+        .get(1)
+        .cloned()
+        .unwrap_or_else(|| String::from("127.0.0.1:8080"));
+    let feeds = loader::find_feeds(Path::new("data/"));
+    let sources = match realtime::get_realtime_sources(&feeds, args.get(2..).unwrap_or(&[])) {
+        Ok(sources) => sources,
+        Err(message) => {
+            println!("{}", message);
+            process::exit(1);
+        }
+    };
+    let network = loader::load_merged_transport_network(&feeds);
+    let shared = Arc::new(SharedNetwork::new(network.clone()));
+
+    if !sources.is_empty() {
+        // the updates are applied to a private copy, the workers get a new snapshot afterwards.
+        // The copies share the parts of the network the updates do not change.
+        let shared = shared.clone();
+        let mut network = network;
+        thread::spawn(move || loop {
+            match realtime::load_feeds(&sources) {
+                Ok(feed) => {
+                    network.apply_trip_updates(&feed);
+                    network.apply_alerts(&feed);
+                    shared.replace(network.clone());
+                }
+                Err(message) => println!("Unable to load the realtime feed: {}", message),
+            }
+            thread::sleep(REALTIME_REFRESH_INTERVAL);
+        });
+    }
+
+    println!("Prahadlo listening on {}", address);
+    if let Err(error) = server::serve(&address, shared, WORKER_THREADS) {
+        println!("Unable to start the server: {}", error);
+    }
+}
@@ -1,21 +1,144 @@
-mod model;
-
-use std::path::Path;
+use std::env;
 use std::io;
+use std::path::Path;
+use std::process;
+
+use chrono::NaiveDate;
 
-use crate::model::loader;
+use prahadlo::model::data_structures::{parse_time, NightLines, Place, SearchOptions};
+use prahadlo::model::diff;
+use prahadlo::model::loader;
+use prahadlo::model::realtime;
+
+const BOARD_LENGTH: usize = 10;
 
 fn main() {
+    // diff <old feed> <new feed> [json] reports the changes between two versions of a feed
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("diff") {
+        if args.len() < 4 {
+            println!("Expected the directories of the old and the new feed.");
+            return;
+        }
+        let report = diff::diff_feeds(Path::new(&args[2]), Path::new(&args[3]));
+        if args.get(4).map(String::as_str) == Some("json") {
+            println!("{}", report.to_json());
+        } else {
+            print!("{}", report);
+        }
+        return;
+    }
     println!("Hello, world! Prahadlo here!");
-    let graph = loader::load_transport_network(Path::new("data/"));
-    /*
+    // either a single feed in data/, or several feeds in its subdirectories
+    let feeds = loader::find_feeds(Path::new("data/"));
+    // optional GTFS-Realtime feeds with trip updates and alerts, either files or http:// URLs,
+    // with several feeds each names the directory of its feed, e.g. pid=<URL>
+    let sources = match realtime::get_realtime_sources(&feeds, args.get(1..).unwrap_or(&[])) {
+        Ok(sources) => sources,
+        Err(message) => {
+            println!("{}", message);
+            process::exit(1);
+        }
+    };
+    let mut graph = loader::load_merged_transport_network(&feeds);
+    if !sources.is_empty() {
+        match realtime::load_feeds(&sources) {
+            Ok(feed) => {
+                println!("Applied {} trip updates.", graph.apply_trip_updates(&feed));
+                println!("Loaded {} alerts.", graph.apply_alerts(&feed));
+            }
+            Err(message) => println!("Unable to load the realtime feed: {}", message),
+        }
+    }
     loop {
         let mut input = String::new();
-        println!("Please provide the two stop IDs separated by space!");
-        io::stdin().read_line(&mut input).expect("Unable to read input!");
-        let split: Vec<&str> = input.split(" ").collect();
+        println!("Please provide the two stop IDs or latitude,longitude coordinates, the date (YYYY-MM-DD) and the time (HH:MM:SS) separated by space!");
+        println!("For the departure or arrival board, provide departures, night-departures or arrivals, the stop or station ID, the date and the time.");
+        println!("For the stops of a trip, provide trip, the trip ID and the date.");
+        io::stdin()
+            .read_line(&mut input)
+            .expect("Unable to read input!");
+        let split: Vec<&str> = input.split_whitespace().collect();
+        let is_trip_detail = split.first() == Some(&"trip");
+        if split.len() != 4 && !(is_trip_detail && split.len() == 3) {
+            println!("Expected four values.");
+            continue;
+        }
+        let date = match NaiveDate::parse_from_str(split[2], "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => {
+                println!("Unable to parse the date.");
+                continue;
+            }
+        };
+        if is_trip_detail {
+            match graph.trip_detail(split[1], date) {
+                Ok(detail) => print!("{}", detail),
+                Err(message) => println!("{}", message),
+            }
+            continue;
+        }
+        let time = match parse_time(split[3]) {
+            Some(time) => time,
+            None => {
+                println!("Unable to parse the time.");
+                continue;
+            }
+        };
 
-        println!("{}", graph.find_connection(split[0], split[1], 1100).unwrap().unwrap());
+        if split[0] == "departures" || split[0] == "night-departures" {
+            let options = SearchOptions {
+                night_lines: if split[0] == "night-departures" {
+                    NightLines::Require
+                } else {
+                    NightLines::Any
+                },
+                ..SearchOptions::default()
+            };
+            match graph.departures(split[1], date, time, BOARD_LENGTH, &options) {
+                Ok(departures) => {
+                    for departure in departures {
+                        println!("{}", departure);
+                    }
+                }
+                Err(message) => println!("{}", message),
+            }
+            continue;
+        }
+        if split[0] == "arrivals" {
+            match graph.arrivals(
+                split[1],
+                date,
+                time,
+                BOARD_LENGTH,
+                &SearchOptions::default(),
+            ) {
+                Ok(arrivals) => {
+                    for arrival in arrivals {
+                        println!("{}", arrival);
+                    }
+                }
+                Err(message) => println!("{}", message),
+            }
+            continue;
+        }
+        let (from, to) = match (Place::parse(split[0]), Place::parse(split[1])) {
+            (Ok(from), Ok(to)) => (from, to),
+            (Err(message), _) | (_, Err(message)) => {
+                println!("{}", message);
+                continue;
+            }
+        };
+        match graph.find_connection_between_places(
+            &from,
+            &to,
+            date,
+            time,
+            &SearchOptions::default(),
+        ) {
+            Ok(Some(itinerary)) => print!("{}", itinerary),
+            Ok(None) => println!("No connection found."),
+            Err(message) => println!("{}", message),
+        }
     }
-    */
 }
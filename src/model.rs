@@ -1,2 +1,5 @@
 pub mod data_structures;
+pub mod diff;
 pub mod loader;
+pub mod osm;
+pub mod realtime;
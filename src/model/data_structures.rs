@@ -1,5 +1,9 @@
+pub use self::fares::*;
+pub use self::itinerary::*;
 pub use self::primitive_gtfs::*;
 pub use self::state_representation::*;
 
+mod fares;
+mod itinerary;
 mod primitive_gtfs;
 mod state_representation;
@@ -0,0 +1,952 @@
+use crate::model::data_structures::itinerary::*;
+use crate::model::data_structures::primitive_gtfs::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+// This file contains the calculation of the prices of itineraries.
+
+/// A ride of an itinerary with everything its price depends on
+#[derive(Debug, Clone)]
+pub struct FareRide {
+    // the index of the leg in the itinerary
+    pub leg_index: usize,
+    pub route_id: String,
+    pub agency_id: Option<String>,
+    pub network_id: Option<String>,
+    // the areas of the boarding and the alighting stop, including the areas of their stations
+    pub from_areas: Vec<String>,
+    pub to_areas: Vec<String>,
+    // time of the day in seconds
+    pub departure_time: u32,
+    // time of the day in seconds
+    pub arrival_time: u32,
+    // the zones of the stops passed from the boarding to the alighting one, None for stops
+    // without a zone
+    pub zones: Vec<Option<String>>,
+}
+
+impl FareRide {
+    fn get_origin_zone(&self) -> Option<&str> {
+        self.zones.first().and_then(|zone| zone.as_deref())
+    }
+
+    fn get_destination_zone(&self) -> Option<&str> {
+        self.zones.last().and_then(|zone| zone.as_deref())
+    }
+}
+
+/// Splits the rides into consecutive groups, each paid for together, so that the total price is
+/// the lowest. Returns None if some of the rides cannot be paid for.
+/// # Arguments
+/// * rides - the rides of an itinerary in their order
+/// * get_tickets - returns the cheapest tickets valid for all the given rides together
+fn get_cheapest_combination<F>(rides: &[FareRide], get_tickets: F) -> Option<Fare>
+where
+    F: Fn(&[FareRide]) -> Option<Vec<Ticket>>,
+{
+    if rides.is_empty() {
+        return None;
+    }
+    // the cheapest tickets covering the first i rides
+    let mut cheapest: Vec<Option<(f32, Vec<Ticket>)>> = vec![None; rides.len() + 1];
+    cheapest[0] = Some((0.0, Vec::new()));
+    for end in 1..=rides.len() {
+        for start in 0..end {
+            let (price, tickets) = match &cheapest[start] {
+                Some(covered) => covered,
+                None => continue,
+            };
+            if let Some(group_tickets) = get_tickets(&rides[start..end]) {
+                let total = price + group_tickets.iter().map(|t| t.price).sum::<f32>();
+                if cheapest[end]
+                    .as_ref()
+                    .map_or(true, |(best, _)| total < *best)
+                {
+                    let mut tickets = tickets.clone();
+                    tickets.extend(group_tickets);
+                    cheapest[end] = Some((total, tickets));
+                }
+            }
+        }
+    }
+    cheapest[rides.len()]
+        .take()
+        .map(|(_, tickets)| Fare::new(tickets))
+}
+
+/// The fares of fare_attributes.txt and fare_rules.txt, assigned to the rides by their routes
+/// and zones
+#[derive(Debug, Clone, Default)]
+pub struct FareRules {
+    attributes: Vec<FareAttribute>,
+    // the rules by their fare ids
+    rules: HashMap<String, Vec<FareRule>>,
+}
+
+impl FareRules {
+    pub fn new(attributes: Vec<FareAttribute>, rules: Vec<FareRule>) -> FareRules {
+        let mut fare_rules: HashMap<String, Vec<FareRule>> = HashMap::new();
+        for rule in rules {
+            fare_rules
+                .entry(rule.fare_id.clone())
+                .or_insert_with(Vec::new)
+                .push(rule);
+        }
+        FareRules {
+            attributes: attributes,
+            rules: fare_rules,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    /// Decides whether a fare can be used for all the rides with a single ticket. Each ride has
+    /// to match a rule of the fare by its route and by the first and the last zone of the
+    /// journey, the zones passed have to be exactly the contained zones.
+    fn is_valid_for(&self, attribute: &FareAttribute, rides: &[FareRide]) -> bool {
+        let (first, last) = (&rides[0], &rides[rides.len() - 1]);
+        if attribute
+            .transfers
+            .map_or(false, |transfers| rides.len() > transfers + 1)
+            || attribute.transfer_duration.map_or(false, |duration| {
+                last.departure_time > first.departure_time + duration
+            })
+        {
+            return false;
+        }
+        if let Some(agency_id) = &attribute.agency_id {
+            if rides
+                .iter()
+                .any(|ride| ride.agency_id.as_ref().map_or(false, |id| id != agency_id))
+            {
+                return false;
+            }
+        }
+        let rules = match self.rules.get(&attribute.fare_id) {
+            Some(rules) => rules,
+            None => return true,
+        };
+        // every ride has to be allowed by a single row, its route together with the origin and
+        // the destination of the whole journey
+        let (origin, destination) = (first.get_origin_zone(), last.get_destination_zone());
+        let matches_row = |rule: &FareRule, ride: &FareRide| {
+            rule.route_id
+                .as_ref()
+                .map_or(true, |id| *id == ride.route_id)
+                && rule
+                    .origin_id
+                    .as_ref()
+                    .map_or(true, |id| Some(id.as_str()) == origin)
+                && rule
+                    .destination_id
+                    .as_ref()
+                    .map_or(true, |id| Some(id.as_str()) == destination)
+        };
+        let rows: Vec<&FareRule> = rules
+            .iter()
+            .filter(|rule| {
+                rule.route_id.is_some() || rule.origin_id.is_some() || rule.destination_id.is_some()
+            })
+            .collect();
+        if !rows.is_empty()
+            && !rides
+                .iter()
+                .all(|ride| rows.iter().any(|rule| matches_row(rule, ride)))
+        {
+            return false;
+        }
+        let contained: HashSet<&str> = rules
+            .iter()
+            .filter_map(|rule| rule.contains_id.as_deref())
+            .collect();
+        let passed: HashSet<&str> = rides
+            .iter()
+            .flat_map(|ride| ride.zones.iter().filter_map(|zone| zone.as_deref()))
+            .collect();
+        contained.is_empty() || contained == passed
+    }
+
+    /// Returns the cheapest fare valid for all the rides with a single ticket
+    fn get_ticket(&self, rides: &[FareRide]) -> Option<Ticket> {
+        self.attributes
+            .iter()
+            .filter(|attribute| self.is_valid_for(attribute, rides))
+            .min_by(|a, b| a.price.partial_cmp(&b.price).unwrap())
+            .map(|attribute| Ticket {
+                fare_id: attribute.fare_id.clone(),
+                name: None,
+                price: attribute.price,
+                currency: attribute.currency_type.clone(),
+                legs: rides.iter().map(|ride| ride.leg_index).collect(),
+            })
+    }
+
+    /// Returns the cheapest combination of tickets for the rides of an itinerary
+    pub fn get_fare(&self, rides: &[FareRide]) -> Option<Fare> {
+        get_cheapest_combination(rides, |rides| self.get_ticket(rides).map(|t| vec![t]))
+    }
+}
+
+/// A ticket of a tariff based on zones and time, like the ones of the Prague Integrated
+/// Transport (PID). Loaded from zone_tickets.txt, which is not a part of GTFS.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ZoneTicket {
+    pub ticket_id: String,
+    pub ticket_name: Option<String>,
+    pub price: f32,
+    pub currency_type: String,
+    // seconds from the first departure to the last arrival
+    pub validity: u32,
+    // the zones the ticket can be used in separated by commas, all the zones if missing
+    pub zone_ids: Option<String>,
+    // the number of zones the ticket can be used in, unlimited if missing
+    pub zone_count: Option<usize>,
+}
+
+/// Returns the zones of a stop, stops on the borders of zones have a list separated by commas
+fn split_zones(zone_id: &str) -> Vec<&str> {
+    zone_id.split(',').map(|zone| zone.trim()).collect()
+}
+
+/// Counts the zones passed by the rides. The stops on the borders are counted in the zones
+/// passed anyway if possible, otherwise in their first zone.
+fn count_zones(rides: &[FareRide]) -> usize {
+    let stop_zones: Vec<Vec<&str>> = rides
+        .iter()
+        .flat_map(|ride| ride.zones.iter().filter_map(|zone| zone.as_deref()))
+        .map(split_zones)
+        .collect();
+    let mut passed: HashSet<&str> = stop_zones
+        .iter()
+        .filter(|zones| zones.len() == 1)
+        .map(|zones| zones[0])
+        .collect();
+    for zones in stop_zones.iter().filter(|zones| zones.len() > 1) {
+        if !zones.iter().any(|zone| passed.contains(zone)) {
+            passed.insert(zones[0]);
+        }
+    }
+    passed.len()
+}
+
+/// The tickets valid for a time in some zones, transfers are free while the ticket is valid
+#[derive(Debug, Clone, Default)]
+pub struct ZoneTickets {
+    tickets: Vec<ZoneTicket>,
+}
+
+impl ZoneTickets {
+    pub fn new(tickets: Vec<ZoneTicket>) -> ZoneTickets {
+        ZoneTickets { tickets: tickets }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tickets.is_empty()
+    }
+
+    /// Decides whether the ticket is valid for the whole time of the rides and all the stops
+    /// passed. Stops without a zone are ignored.
+    fn is_valid_for(ticket: &ZoneTicket, rides: &[FareRide]) -> bool {
+        let (first, last) = (&rides[0], &rides[rides.len() - 1]);
+        if last.arrival_time > first.departure_time + ticket.validity {
+            return false;
+        }
+        if let Some(zone_ids) = &ticket.zone_ids {
+            let valid_zones = split_zones(zone_ids);
+            let outside = rides
+                .iter()
+                .flat_map(|ride| ride.zones.iter().filter_map(|zone| zone.as_deref()))
+                .any(|zone| {
+                    !split_zones(zone)
+                        .iter()
+                        .any(|zone| valid_zones.contains(zone))
+                });
+            if outside {
+                return false;
+            }
+        }
+        ticket
+            .zone_count
+            .map_or(true, |zone_count| count_zones(rides) <= zone_count)
+    }
+
+    fn get_ticket(&self, rides: &[FareRide]) -> Option<Ticket> {
+        self.tickets
+            .iter()
+            .filter(|ticket| ZoneTickets::is_valid_for(ticket, rides))
+            .min_by(|a, b| a.price.partial_cmp(&b.price).unwrap())
+            .map(|ticket| Ticket {
+                fare_id: ticket.ticket_id.clone(),
+                name: ticket.ticket_name.clone(),
+                price: ticket.price,
+                currency: ticket.currency_type.clone(),
+                legs: rides.iter().map(|ride| ride.leg_index).collect(),
+            })
+    }
+
+    /// Returns the cheapest combination of tickets for the rides of an itinerary
+    pub fn get_fare(&self, rides: &[FareRide]) -> Option<Fare> {
+        get_cheapest_combination(rides, |rides| self.get_ticket(rides).map(|t| vec![t]))
+    }
+}
+
+/// Decides whether a field of a rule matches the values of a ride. An empty field matches only
+/// when no other rule has one of the values.
+fn matches_field(field: &Option<String>, values: &[&str], value_matched: bool) -> bool {
+    match field {
+        Some(field) => values.contains(&field.as_str()),
+        None => !value_matched,
+    }
+}
+
+/// The fares of GTFS Fares v2. The rides get their fare products by the leg rules and the
+/// transfers between them may be discounted by the transfer rules. Timeframes are not supported,
+/// the rules limited to them are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct FareProducts {
+    // the products by their ids, with a row for each fare media
+    products: HashMap<String, Vec<FareProduct>>,
+    leg_rules: Vec<FareLegRule>,
+    transfer_rules: Vec<FareTransferRule>,
+    areas: HashMap<String, Area>,
+    // the areas of each stop
+    stop_areas: HashMap<String, Vec<String>>,
+    // the networks of the routes listed in route_networks.txt
+    route_networks: HashMap<String, String>,
+}
+
+impl FareProducts {
+    pub fn new(
+        products: Vec<FareProduct>,
+        leg_rules: Vec<FareLegRule>,
+        transfer_rules: Vec<FareTransferRule>,
+        areas: Vec<Area>,
+        stop_areas: Vec<StopArea>,
+        route_networks: Vec<RouteNetwork>,
+    ) -> FareProducts {
+        let mut products_by_id: HashMap<String, Vec<FareProduct>> = HashMap::new();
+        for product in products {
+            products_by_id
+                .entry(product.fare_product_id.clone())
+                .or_insert_with(Vec::new)
+                .push(product);
+        }
+        let mut areas_by_stop: HashMap<String, Vec<String>> = HashMap::new();
+        for stop_area in stop_areas {
+            areas_by_stop
+                .entry(stop_area.stop_id)
+                .or_insert_with(Vec::new)
+                .push(stop_area.area_id);
+        }
+        FareProducts {
+            products: products_by_id,
+            // the timeframes are not supported
+            leg_rules: leg_rules
+                .into_iter()
+                .filter(|rule| {
+                    rule.from_timeframe_group_id.is_none() && rule.to_timeframe_group_id.is_none()
+                })
+                .collect(),
+            transfer_rules: transfer_rules,
+            areas: areas
+                .into_iter()
+                .map(|area| (area.area_id.clone(), area))
+                .collect(),
+            stop_areas: areas_by_stop,
+            route_networks: route_networks
+                .into_iter()
+                .map(|route_network| (route_network.route_id, route_network.network_id))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leg_rules.is_empty()
+    }
+
+    pub fn get_area(&self, area_id: &str) -> Option<&Area> {
+        self.areas.get(area_id)
+    }
+
+    /// Returns the areas a stop is in directly
+    pub fn get_stop_areas(&self, stop_id: &str) -> &[String] {
+        match self.stop_areas.get(stop_id) {
+            Some(areas) => areas,
+            None => &[],
+        }
+    }
+
+    /// Adds the areas of a stop to the areas of another one
+    pub fn copy_stop_areas(&mut self, from_stop_id: &str, to_stop_id: &str) {
+        let areas = self.get_stop_areas(from_stop_id).to_vec();
+        if !areas.is_empty() {
+            self.stop_areas
+                .entry(to_stop_id.to_string())
+                .or_insert_with(Vec::new)
+                .extend(areas);
+        }
+    }
+
+    /// Returns the network of a route assigned by route_networks.txt
+    pub fn get_route_network(&self, route_id: &str) -> Option<&String> {
+        self.route_networks.get(route_id)
+    }
+
+    /// Returns the cheapest variant of a product
+    fn get_product(&self, fare_product_id: &str) -> Option<&FareProduct> {
+        self.products
+            .get(fare_product_id)?
+            .iter()
+            .min_by(|a, b| a.amount.partial_cmp(&b.amount).unwrap())
+    }
+
+    /// Finds the leg rule with the cheapest product among the matching rules with the highest
+    /// priority
+    fn get_leg_fare(&self, ride: &FareRide) -> Option<(&FareLegRule, &FareProduct)> {
+        let networks: Vec<&str> = ride.network_id.iter().map(|id| id.as_str()).collect();
+        let from_areas: Vec<&str> = ride.from_areas.iter().map(|id| id.as_str()).collect();
+        let to_areas: Vec<&str> = ride.to_areas.iter().map(|id| id.as_str()).collect();
+        let matched = |field: fn(&FareLegRule) -> &Option<String>, values: &[&str]| {
+            self.leg_rules.iter().any(|rule| {
+                field(rule)
+                    .as_ref()
+                    .map_or(false, |value| values.contains(&value.as_str()))
+            })
+        };
+        let network_matched = matched(|rule| &rule.network_id, &networks);
+        let from_area_matched = matched(|rule| &rule.from_area_id, &from_areas);
+        let to_area_matched = matched(|rule| &rule.to_area_id, &to_areas);
+        let matching: Vec<&FareLegRule> = self
+            .leg_rules
+            .iter()
+            .filter(|rule| {
+                matches_field(&rule.network_id, &networks, network_matched)
+                    && matches_field(&rule.from_area_id, &from_areas, from_area_matched)
+                    && matches_field(&rule.to_area_id, &to_areas, to_area_matched)
+            })
+            .collect();
+        let priority = matching
+            .iter()
+            .map(|rule| rule.rule_priority.unwrap_or(0))
+            .max()?;
+        matching
+            .into_iter()
+            .filter(|rule| rule.rule_priority.unwrap_or(0) == priority)
+            .filter_map(|rule| Some((rule, self.get_product(&rule.fare_product_id)?)))
+            .min_by(|a, b| a.1.amount.partial_cmp(&b.1.amount).unwrap())
+    }
+
+    /// Returns the transfer rules that apply between rides of two leg groups
+    fn get_transfer_rules(
+        &self,
+        from_leg_group_id: Option<&str>,
+        to_leg_group_id: Option<&str>,
+    ) -> Vec<&FareTransferRule> {
+        let from_groups: Vec<&str> = from_leg_group_id.into_iter().collect();
+        let to_groups: Vec<&str> = to_leg_group_id.into_iter().collect();
+        let from_matched = self.transfer_rules.iter().any(|rule| {
+            rule.from_leg_group_id.is_some()
+                && rule.from_leg_group_id.as_deref() == from_leg_group_id
+        });
+        let to_matched = self.transfer_rules.iter().any(|rule| {
+            rule.to_leg_group_id.is_some() && rule.to_leg_group_id.as_deref() == to_leg_group_id
+        });
+        self.transfer_rules
+            .iter()
+            .filter(|rule| {
+                matches_field(&rule.from_leg_group_id, &from_groups, from_matched)
+                    && matches_field(&rule.to_leg_group_id, &to_groups, to_matched)
+            })
+            .collect()
+    }
+
+    /// Checks the number of transfers and the duration limit of a transfer rule. The duration is
+    /// measured from the first ride of the transfers.
+    fn allows_transfer(rule: &FareTransferRule, rides: &[FareRide]) -> bool {
+        let (first, last) = (&rides[0], &rides[rides.len() - 1]);
+        let transfers = rides.len() as i32 - 1;
+        if rule
+            .transfer_count
+            .map_or(false, |count| count >= 0 && transfers > count)
+        {
+            return false;
+        }
+        rule.duration_limit.map_or(true, |limit| {
+            let (start, end) = match rule.duration_limit_type.unwrap_or(0) {
+                0 => (first.departure_time, last.arrival_time),
+                1 => (first.departure_time, last.departure_time),
+                2 => (first.arrival_time, last.departure_time),
+                _ => (first.arrival_time, last.arrival_time),
+            };
+            end <= start + limit
+        })
+    }
+
+    fn create_ticket(product: &FareProduct, legs: Vec<usize>) -> Ticket {
+        Ticket {
+            fare_id: product.fare_product_id.clone(),
+            name: product.fare_product_name.clone(),
+            price: product.amount,
+            currency: product.currency.clone(),
+            legs: legs,
+        }
+    }
+
+    /// Returns the cheapest tickets for rides connected by transfers, None if some of the rides
+    /// has no fare or some of the transfers is not allowed by the transfer rules
+    fn get_tickets(&self, rides: &[FareRide]) -> Option<Vec<Ticket>> {
+        let leg_fares = rides
+            .iter()
+            .map(|ride| self.get_leg_fare(ride))
+            .collect::<Option<Vec<(&FareLegRule, &FareProduct)>>>()?;
+        let mut tickets = vec![FareProducts::create_ticket(
+            leg_fares[0].1,
+            vec![rides[0].leg_index],
+        )];
+        // the ticket the previous ride is paid with
+        let mut covering = 0;
+        for k in 1..rides.len() {
+            let (previous, current) = (rides[k - 1].leg_index, rides[k].leg_index);
+            let previous_alone = tickets[covering].legs == vec![previous];
+            // the price added by each of the rules, the transfer product, also the ride for the
+            // second type, minus the ticket of the previous ride replaced for the third type
+            let rule = self
+                .get_transfer_rules(
+                    leg_fares[k - 1].0.leg_group_id.as_deref(),
+                    leg_fares[k].0.leg_group_id.as_deref(),
+                )
+                .into_iter()
+                .filter(|rule| FareProducts::allows_transfer(rule, &rides[..k + 1]))
+                .filter_map(|rule| {
+                    let transfer = match &rule.fare_product_id {
+                        Some(fare_product_id) => Some(self.get_product(fare_product_id)?),
+                        None => None,
+                    };
+                    let transfer_price = transfer.map_or(0.0, |product| product.amount);
+                    let added = match rule.fare_transfer_type {
+                        0 => transfer_price,
+                        1 => transfer_price + leg_fares[k].1.amount,
+                        _ if previous_alone && transfer.is_some() => {
+                            transfer_price - tickets[covering].price
+                        }
+                        _ => transfer_price,
+                    };
+                    Some((rule, transfer, added))
+                })
+                .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+            let (rule, transfer) = match rule {
+                Some((rule, transfer, _)) => (rule, transfer),
+                None => return None,
+            };
+            match rule.fare_transfer_type {
+                0 => {
+                    tickets[covering].legs.push(current);
+                    if let Some(transfer) = transfer {
+                        tickets.push(FareProducts::create_ticket(
+                            transfer,
+                            vec![previous, current],
+                        ));
+                    }
+                }
+                1 => {
+                    if let Some(transfer) = transfer {
+                        tickets.push(FareProducts::create_ticket(
+                            transfer,
+                            vec![previous, current],
+                        ));
+                    }
+                    tickets.push(FareProducts::create_ticket(leg_fares[k].1, vec![current]));
+                    covering = tickets.len() - 1;
+                }
+                // the transfer product replaces the ticket of the previous ride
+                _ => match transfer {
+                    Some(transfer) => {
+                        if previous_alone {
+                            tickets.remove(covering);
+                        }
+                        tickets.push(FareProducts::create_ticket(
+                            transfer,
+                            vec![previous, current],
+                        ));
+                        covering = tickets.len() - 1;
+                    }
+                    None => tickets[covering].legs.push(current),
+                },
+            }
+        }
+        Some(tickets)
+    }
+
+    /// Returns the cheapest combination of tickets for the rides of an itinerary
+    pub fn get_fare(&self, rides: &[FareRide]) -> Option<Fare> {
+        get_cheapest_combination(rides, |rides| self.get_tickets(rides))
+    }
+}
+
+/// All the ways of pricing the itineraries of a network
+#[derive(Debug, Clone, Default)]
+pub struct Fares {
+    pub rules: FareRules,
+    pub products: FareProducts,
+    pub zone_tickets: ZoneTickets,
+}
+
+impl Fares {
+    /// Prefixes the ids of fares, fare products, zones, areas, networks, routes and stops with
+    /// the prefix of their feed
+    pub fn add_prefix(&mut self, prefix: &str) {
+        for attribute in &mut self.rules.attributes {
+            add_prefix(prefix, &mut attribute.fare_id);
+            add_prefix_to_option(prefix, &mut attribute.agency_id);
+        }
+        self.rules.rules = rekey(
+            std::mem::take(&mut self.rules.rules),
+            |mut id, mut rules| {
+                add_prefix(prefix, &mut id);
+                for rule in &mut rules {
+                    add_prefix(prefix, &mut rule.fare_id);
+                    add_prefix_to_option(prefix, &mut rule.route_id);
+                    add_prefix_to_option(prefix, &mut rule.origin_id);
+                    add_prefix_to_option(prefix, &mut rule.destination_id);
+                    add_prefix_to_option(prefix, &mut rule.contains_id);
+                }
+                (id, rules)
+            },
+        );
+        let products = &mut self.products;
+        products.products = rekey(
+            std::mem::take(&mut products.products),
+            |mut id, mut rows| {
+                add_prefix(prefix, &mut id);
+                for product in &mut rows {
+                    add_prefix(prefix, &mut product.fare_product_id);
+                }
+                (id, rows)
+            },
+        );
+        for rule in &mut products.leg_rules {
+            add_prefix_to_option(prefix, &mut rule.leg_group_id);
+            add_prefix_to_option(prefix, &mut rule.network_id);
+            add_prefix_to_option(prefix, &mut rule.from_area_id);
+            add_prefix_to_option(prefix, &mut rule.to_area_id);
+            add_prefix(prefix, &mut rule.fare_product_id);
+        }
+        for rule in &mut products.transfer_rules {
+            add_prefix_to_option(prefix, &mut rule.from_leg_group_id);
+            add_prefix_to_option(prefix, &mut rule.to_leg_group_id);
+            add_prefix_to_option(prefix, &mut rule.fare_product_id);
+        }
+        products.areas = rekey(std::mem::take(&mut products.areas), |mut id, mut area| {
+            add_prefix(prefix, &mut id);
+            add_prefix(prefix, &mut area.area_id);
+            (id, area)
+        });
+        products.stop_areas = rekey(
+            std::mem::take(&mut products.stop_areas),
+            |mut id, mut areas| {
+                add_prefix(prefix, &mut id);
+                for area_id in &mut areas {
+                    add_prefix(prefix, area_id);
+                }
+                (id, areas)
+            },
+        );
+        products.route_networks = rekey(
+            std::mem::take(&mut products.route_networks),
+            |mut route_id, mut network_id| {
+                add_prefix(prefix, &mut route_id);
+                add_prefix(prefix, &mut network_id);
+                (route_id, network_id)
+            },
+        );
+        for ticket in &mut self.zone_tickets.tickets {
+            add_prefix(prefix, &mut ticket.ticket_id);
+            add_prefix_to_zones(prefix, &mut ticket.zone_ids);
+        }
+    }
+
+    /// Adds the fares of another feed, their ids must not collide with the ones of this feed
+    pub fn merge(&mut self, other: Fares) {
+        self.rules.attributes.extend(other.rules.attributes);
+        self.rules.rules.extend(other.rules.rules);
+        let products = &mut self.products;
+        products.products.extend(other.products.products);
+        products.leg_rules.extend(other.products.leg_rules);
+        products
+            .transfer_rules
+            .extend(other.products.transfer_rules);
+        products.areas.extend(other.products.areas);
+        products.stop_areas.extend(other.products.stop_areas);
+        products
+            .route_networks
+            .extend(other.products.route_networks);
+        self.zone_tickets.tickets.extend(other.zone_tickets.tickets);
+    }
+
+    /// Returns the price by GTFS Fares v2 if the feed has it, otherwise by the original fares
+    pub fn get_fare(&self, rides: &[FareRide]) -> Option<Fare> {
+        if self.products.is_empty() {
+            self.rules.get_fare(rides)
+        } else {
+            self.products.get_fare(rides)
+        }
+    }
+}
+
+#[cfg(test)]
+fn ride(leg_index: usize, route_id: &str, times: (u32, u32), zones: &[&str]) -> FareRide {
+    FareRide {
+        leg_index: leg_index,
+        route_id: String::from(route_id),
+        agency_id: None,
+        network_id: None,
+        from_areas: Vec::new(),
+        to_areas: Vec::new(),
+        departure_time: times.0,
+        arrival_time: times.1,
+        zones: zones.iter().map(|zone| Some(String::from(*zone))).collect(),
+    }
+}
+
+#[test]
+fn test_fare_rules() {
+    let attribute = |fare_id: &str, price: f32, transfers: Option<usize>| FareAttribute {
+        fare_id: String::from(fare_id),
+        price: price,
+        currency_type: String::from("CZK"),
+        payment_method: 1,
+        transfers: transfers,
+        agency_id: None,
+        transfer_duration: Some(3600),
+    };
+    let rule = |fare_id: &str, route_id: Option<&str>, contains_id: Option<&str>| FareRule {
+        fare_id: String::from(fare_id),
+        route_id: route_id.map(String::from),
+        origin_id: None,
+        destination_id: None,
+        contains_id: contains_id.map(String::from),
+    };
+    let fares = FareRules::new(
+        vec![
+            attribute("CITY", 30.0, None),
+            attribute("BUS", 10.0, Some(0)),
+            attribute("REGION", 35.0, None),
+        ],
+        vec![
+            rule("CITY", None, Some("A")),
+            rule("BUS", Some("B1"), None),
+            rule("REGION", None, Some("A")),
+            rule("REGION", None, Some("B")),
+        ],
+    );
+    // the bus ticket is cheaper than the city one but does not allow transfers
+    let rides = vec![
+        ride(0, "M", (0, 600), &["A", "A"]),
+        ride(2, "B1", (900, 1200), &["A", "A"]),
+    ];
+    let fare = fares.get_fare(&rides).unwrap();
+    assert_eq!(fare.price, 30.0);
+    assert_eq!(fare.tickets[0].legs, vec![0, 2]);
+    // the city ticket is not valid in zone B, the regional one is cheaper than two tickets
+    let rides = vec![
+        ride(0, "M", (0, 600), &["A", "A"]),
+        ride(2, "B1", (900, 1200), &["A", "B"]),
+    ];
+    let fare = fares.get_fare(&rides).unwrap();
+    assert_eq!(fare.tickets.len(), 1);
+    assert_eq!(fare.tickets[0].fare_id, "REGION");
+    // the transfer comes too late for a single ticket
+    let rides = vec![
+        ride(0, "M", (0, 600), &["A", "A"]),
+        ride(2, "B1", (4000, 4300), &["A", "B"]),
+    ];
+    let fare = fares.get_fare(&rides).unwrap();
+    assert_eq!(fare.price, 40.0);
+    let ticket_ids: Vec<&str> = fare.tickets.iter().map(|t| t.fare_id.as_str()).collect();
+    assert_eq!(ticket_ids, vec!["CITY", "BUS"]);
+    assert!(fares.get_fare(&[ride(0, "M", (0, 60), &["C"])]).is_none());
+}
+
+#[test]
+fn test_fare_rule_rows() {
+    let rule = |route_id: &str, origin_id: &str| FareRule {
+        fare_id: String::from("F"),
+        route_id: Some(String::from(route_id)),
+        origin_id: Some(String::from(origin_id)),
+        destination_id: None,
+        contains_id: None,
+    };
+    let fares = FareRules::new(
+        vec![FareAttribute {
+            fare_id: String::from("F"),
+            price: 20.0,
+            currency_type: String::from("CZK"),
+            payment_method: 1,
+            transfers: None,
+            agency_id: None,
+            transfer_duration: None,
+        }],
+        vec![rule("R1", "A"), rule("R2", "B")],
+    );
+    assert!(fares
+        .get_fare(&[ride(0, "R1", (0, 600), &["A", "B"])])
+        .is_some());
+    assert!(fares
+        .get_fare(&[ride(0, "R2", (0, 600), &["B", "A"])])
+        .is_some());
+    // the rows are not combined, R1 does not allow rides from B
+    assert!(fares
+        .get_fare(&[ride(0, "R1", (0, 600), &["B", "A"])])
+        .is_none());
+    assert!(fares
+        .get_fare(&[ride(0, "R2", (0, 600), &["A", "B"])])
+        .is_none());
+}
+
+#[test]
+fn test_zone_tickets() {
+    let ticket =
+        |ticket_id: &str, price, validity, zone_ids: Option<&str>, zone_count| ZoneTicket {
+            ticket_id: String::from(ticket_id),
+            ticket_name: None,
+            price: price,
+            currency_type: String::from("CZK"),
+            validity: validity,
+            zone_ids: zone_ids.map(String::from),
+            zone_count: zone_count,
+        };
+    let tickets = ZoneTickets::new(vec![
+        ticket("P30", 30.0, 1800, Some("P,0,B"), None),
+        ticket("P90", 40.0, 5400, Some("P,0,B"), None),
+        ticket("Z2", 20.0, 1800, Some("0,B,1,2,3"), Some(2)),
+    ]);
+    let fare = tickets
+        .get_fare(&[ride(0, "22", (0, 1200), &["P", "P"])])
+        .unwrap();
+    assert_eq!(fare.tickets[0].fare_id, "P30");
+    let fare = tickets
+        .get_fare(&[
+            ride(0, "22", (0, 1200), &["P", "P"]),
+            ride(1, "A", (1500, 2400), &["P", "P"]),
+        ])
+        .unwrap();
+    assert_eq!(fare.tickets[0].fare_id, "P90");
+    // the stop on the border of zones B and 1 is counted in zone 1
+    let fare = tickets
+        .get_fare(&[ride(0, "300", (0, 900), &["1", "B,1", "2"])])
+        .unwrap();
+    assert_eq!(fare.price, 20.0);
+    // three zones need two tickets
+    let fare = tickets
+        .get_fare(&[
+            ride(0, "300", (0, 900), &["1", "2"]),
+            ride(1, "301", (1000, 1500), &["2", "3"]),
+        ])
+        .unwrap();
+    assert_eq!(fare.price, 40.0);
+    assert!(tickets
+        .get_fare(&[ride(0, "400", (0, 900), &["5"])])
+        .is_none());
+}
+
+#[test]
+fn test_fare_products() {
+    let product = |id: &str, amount: f32| FareProduct {
+        fare_product_id: String::from(id),
+        fare_product_name: None,
+        fare_media_id: None,
+        amount: amount,
+        currency: String::from("CZK"),
+    };
+    let leg_rule =
+        |group: &str, network: Option<&str>, from_area: Option<&str>, id, priority| FareLegRule {
+            leg_group_id: Some(String::from(group)),
+            network_id: network.map(String::from),
+            from_area_id: from_area.map(String::from),
+            to_area_id: None,
+            from_timeframe_group_id: None,
+            to_timeframe_group_id: None,
+            fare_product_id: String::from(id),
+            rule_priority: priority,
+        };
+    let transfer_rule = |from: &str, to: &str, transfer_type, id: Option<&str>| FareTransferRule {
+        from_leg_group_id: Some(String::from(from)),
+        to_leg_group_id: Some(String::from(to)),
+        transfer_count: if from == to { Some(-1) } else { None },
+        duration_limit: Some(1800),
+        duration_limit_type: Some(1),
+        fare_transfer_type: transfer_type,
+        fare_product_id: id.map(String::from),
+    };
+    let fares = FareProducts::new(
+        vec![
+            product("SINGLE", 30.0),
+            product("REGIONAL", 50.0),
+            product("RAIL", 40.0),
+            product("COMBINED", 55.0),
+        ],
+        vec![
+            leg_rule("city", Some("pid"), None, "SINGLE", None),
+            leg_rule("city", Some("pid"), Some("outer"), "SINGLE", None),
+            leg_rule("region", Some("pid"), Some("outer"), "REGIONAL", Some(1)),
+            leg_rule("rail", None, None, "RAIL", None),
+        ],
+        vec![
+            transfer_rule("city", "city", 0, None),
+            transfer_rule("city", "rail", 2, Some("COMBINED")),
+        ],
+        Vec::new(),
+        vec![StopArea {
+            area_id: String::from("outer"),
+            stop_id: String::from("S1"),
+        }],
+        Vec::new(),
+    );
+    assert_eq!(fares.get_stop_areas("S1"), &[String::from("outer")]);
+    let ride = |leg_index, network: &str, from_area: Option<&str>, times| FareRide {
+        network_id: Some(String::from(network)),
+        from_areas: from_area.into_iter().map(String::from).collect(),
+        ..ride(leg_index, "R", times, &[])
+    };
+    // the transfers are free for thirty minutes
+    let fare = fares
+        .get_fare(&[
+            ride(0, "pid", None, (0, 600)),
+            ride(2, "pid", None, (900, 1200)),
+        ])
+        .unwrap();
+    assert_eq!(fare.price, 30.0);
+    assert_eq!(fare.tickets[0].legs, vec![0, 2]);
+    let fare = fares
+        .get_fare(&[
+            ride(0, "pid", None, (0, 600)),
+            ride(2, "pid", None, (2400, 3000)),
+        ])
+        .unwrap();
+    assert_eq!(fare.price, 60.0);
+    // the rule with the higher priority wins even though it is more expensive
+    let fare = fares
+        .get_fare(&[ride(0, "pid", Some("outer"), (0, 600))])
+        .unwrap();
+    assert_eq!(fare.tickets[0].fare_id, "REGIONAL");
+    // the rail has no rule for its network, the combined ticket replaces the single one
+    let fare = fares
+        .get_fare(&[
+            ride(0, "pid", None, (0, 600)),
+            ride(1, "cd", None, (900, 2400)),
+        ])
+        .unwrap();
+    assert_eq!(fare.price, 55.0);
+    assert_eq!(fare.tickets.len(), 1);
+    assert_eq!(fare.tickets[0].fare_id, "COMBINED");
+    // there is no transfer rule from the rail to the city
+    let fare = fares
+        .get_fare(&[
+            ride(0, "cd", None, (0, 600)),
+            ride(1, "pid", None, (900, 1200)),
+        ])
+        .unwrap();
+    assert_eq!(fare.price, 70.0);
+}
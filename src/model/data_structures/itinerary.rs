@@ -0,0 +1,656 @@
+use crate::model::data_structures::primitive_gtfs::Route;
+use geo_types::{LineString, Point};
+use serde_json::{json, Value};
+use std::fmt;
+
+// This file contains the structures describing a found connection as it is presented to the user.
+
+/// Formats time of the day in seconds as HH:MM:SS
+pub fn format_time(time: u32) -> String {
+    format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60)
+}
+
+/// Parses time of the day in the HH:MM:SS format into seconds
+pub fn parse_time(time: &str) -> Option<u32> {
+    let hms: Vec<&str> = time.split(":").collect();
+    if hms.len() != 3 {
+        return None;
+    }
+    let mut seconds = 0;
+    for part in hms {
+        seconds = seconds * 60 + part.parse::<u32>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Encodes a line in WGS84 coordinates using the Google encoded polyline algorithm with
+/// precision of five decimal places
+pub fn encode_polyline(line: &LineString<f32>) -> String {
+    let mut encoded = String::new();
+    let mut last_lat = 0;
+    let mut last_lon = 0;
+    for point in line.points_iter() {
+        let lat = (point.y() as f64 * 1e5).round() as i64;
+        let lon = (point.x() as f64 * 1e5).round() as i64;
+        encode_polyline_value(lat - last_lat, &mut encoded);
+        encode_polyline_value(lon - last_lon, &mut encoded);
+        last_lat = lat;
+        last_lon = lon;
+    }
+    encoded
+}
+
+fn encode_polyline_value(value: i64, encoded: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+    while value >= 0x20 {
+        encoded.push((((value & 0x1f) | 0x20) + 63) as u8 as char);
+        value >>= 5;
+    }
+    encoded.push((value + 63) as u8 as char);
+}
+
+#[test]
+fn test_polyline_encoding() {
+    let line = LineString::from(vec![(-120.2, 38.5), (-120.95, 40.7), (-126.453, 43.252)]);
+    assert_eq!(encode_polyline(&line), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+}
+
+/// Converts a line in WGS84 coordinates into a GeoJSON LineString geometry
+pub fn to_geojson(line: &LineString<f32>) -> Value {
+    let coordinates: Vec<[f32; 2]> = line.points_iter().map(|p| [p.x(), p.y()]).collect();
+    json!({
+        "type": "LineString",
+        "coordinates": coordinates,
+    })
+}
+
+const EARTH_RADIUS: f32 = 6_371_000.0;
+
+/// Calculates the distance in meters between two points in WGS84 coordinates using the haversine
+/// formula
+pub fn get_distance(from: Point<f32>, to: Point<f32>) -> f32 {
+    let d_lat = (to.y() - from.y()).to_radians();
+    let d_lon = (to.x() - from.x()).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + from.y().to_radians().cos() * to.y().to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Calculates the length in meters of a line in WGS84 coordinates
+pub fn get_length(line: &LineString<f32>) -> f32 {
+    line.0
+        .windows(2)
+        .map(|pair| get_distance(Point(pair[0]), Point(pair[1])))
+        .sum()
+}
+
+#[test]
+fn test_distance() {
+    // one degree of latitude is roughly 111 km
+    let distance = get_distance(Point::new(14.4, 50.0), Point::new(14.4, 51.0));
+    assert!((distance - 111_195.0).abs() < 10.0);
+    let line = LineString::from(vec![(14.4, 50.0), (14.4, 50.5), (14.4, 51.0)]);
+    assert!((get_length(&line) - 111_195.0).abs() < 10.0);
+}
+
+/// Selects the entities an alert applies to. All the fields that are set have to match, an
+/// entity without any field set matches nothing.
+#[derive(Debug, Clone, Default)]
+pub struct InformedEntity {
+    pub agency_id: Option<String>,
+    pub route_id: Option<String>,
+    pub route_type: Option<u32>,
+    pub trip_id: Option<String>,
+    pub stop_id: Option<String>,
+}
+
+impl InformedEntity {
+    /// Decides whether the entity selects the route, the trip and the stop, None is given for
+    /// walks which have no route and trip
+    pub fn matches(
+        &self,
+        route: Option<&Route>,
+        trip_id: Option<&str>,
+        stop_id: Option<&str>,
+    ) -> bool {
+        fn field_matches<T: PartialEq>(selector: &Option<T>, value: Option<T>) -> bool {
+            match selector {
+                Some(selector) => value.as_ref() == Some(selector),
+                None => true,
+            }
+        }
+        if self.agency_id.is_none()
+            && self.route_id.is_none()
+            && self.route_type.is_none()
+            && self.trip_id.is_none()
+            && self.stop_id.is_none()
+        {
+            return false;
+        }
+        // feeds with a single agency may leave out its id, it is stored under an empty id then
+        field_matches(
+            &self.agency_id.as_deref(),
+            route.map(|route| route.agency_id.as_deref().unwrap_or("")),
+        ) && field_matches(
+            &self.route_id.as_deref(),
+            route.map(|route| route.route_id.as_str()),
+        ) && field_matches(&self.route_type, route.map(|route| route.route_type))
+            && field_matches(&self.trip_id.as_deref(), trip_id)
+            && field_matches(&self.stop_id.as_deref(), stop_id)
+    }
+}
+
+/// A service alert from a GTFS-Realtime feed
+#[derive(Debug, Clone)]
+pub struct ServiceAlert {
+    pub alert_id: String,
+    // POSIX timestamps of the start and the end of the periods, None is unbounded. The alert is
+    // always active if there are no periods.
+    pub active_periods: Vec<(Option<u64>, Option<u64>)>,
+    pub informed_entities: Vec<InformedEntity>,
+    // the informed entities cannot be used
+    pub no_service: bool,
+    pub header_text: Option<String>,
+    pub description_text: Option<String>,
+    pub url: Option<String>,
+}
+
+impl ServiceAlert {
+    /// Decides whether any of the active periods overlaps the interval between two POSIX
+    /// timestamps
+    pub fn is_active_between(&self, from: i64, to: i64) -> bool {
+        self.active_periods.is_empty()
+            || self.active_periods.iter().any(|(start, end)| {
+                start.map_or(true, |start| (start as i64) <= to)
+                    && end.map_or(true, |end| (end as i64) > from)
+            })
+    }
+
+    pub fn is_active_at(&self, timestamp: i64) -> bool {
+        self.is_active_between(timestamp, timestamp)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "alert_id": self.alert_id,
+            "no_service": self.no_service,
+            "header_text": self.header_text,
+            "description_text": self.description_text,
+            "url": self.url,
+        })
+    }
+}
+
+/// One part of an itinerary, either a ride in a single vehicle or a transfer between two stops,
+/// walked or ridden on a bicycle
+#[derive(Debug, Clone)]
+pub struct Leg {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    // time of the day in seconds
+    pub departure_time: u32,
+    // time of the day in seconds
+    pub arrival_time: u32,
+    // difference between the predicted and the scheduled time in seconds, None without realtime
+    // data
+    pub departure_delay: Option<i32>,
+    pub arrival_delay: Option<i32>,
+    // None for walking legs
+    pub trip_id: Option<String>,
+    pub route_id: Option<String>,
+    // for frequency based trips without exact times, the times are only estimates and the vehicle
+    // comes every headway seconds
+    pub headway: Option<u32>,
+    // in meters
+    pub distance: f32,
+    // the path of the leg in WGS84 coordinates, None if the trip has no shape
+    pub geometry: Option<LineString<f32>>,
+    // alerts concerning the stops, the route or the trip of the leg
+    pub alerts: Vec<ServiceAlert>,
+    // the vehicle only stops at the boarding stop if the rider signals the driver or calls ahead
+    pub pickup_on_request: bool,
+    // the vehicle only stops at the alighting stop if the rider asks the driver
+    pub drop_off_on_request: bool,
+    // the transfer is ridden on a bicycle instead of walking
+    pub cycling: bool,
+    // the ride uses a night line
+    pub night: bool,
+    // whether the vehicle and the stops can be used with a wheelchair, None if the feed does not
+    // tell
+    pub wheelchair_accessible: Option<bool>,
+    // the steps of a walk through the pathways of a station, empty for other legs
+    pub directions: Vec<String>,
+}
+
+impl Leg {
+    pub fn is_walk(&self) -> bool {
+        self.trip_id.is_none()
+    }
+
+    /// True if the vehicle does not stop at the boarding or alighting stop unless asked to
+    pub fn is_request_stop(&self) -> bool {
+        self.pickup_on_request || self.drop_off_on_request
+    }
+}
+
+impl Leg {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "from_stop_id": self.from_stop_id,
+            "to_stop_id": self.to_stop_id,
+            "departure_time": format_time(self.departure_time),
+            "arrival_time": format_time(self.arrival_time),
+            "departure_delay": self.departure_delay,
+            "arrival_delay": self.arrival_delay,
+            "trip_id": self.trip_id,
+            "route_id": self.route_id,
+            "headway": self.headway,
+            "distance": self.distance,
+            "geometry": self.geometry.as_ref().map(encode_polyline),
+            "alerts": self.alerts.iter().map(ServiceAlert::to_json).collect::<Vec<Value>>(),
+            "pickup_on_request": self.pickup_on_request,
+            "drop_off_on_request": self.drop_off_on_request,
+            "cycling": self.cycling,
+            "night": self.night,
+            "wheelchair_accessible": self.wheelchair_accessible,
+            "directions": self.directions,
+        })
+    }
+}
+
+/// A ticket bought for some of the rides of an itinerary
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ticket {
+    // the id of the fare or the fare product
+    pub fare_id: String,
+    pub name: Option<String>,
+    pub price: f32,
+    pub currency: String,
+    // the indices of the legs of the itinerary the ticket is used for
+    pub legs: Vec<usize>,
+}
+
+impl Ticket {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "fare_id": self.fare_id,
+            "name": self.name,
+            "price": self.price,
+            "currency": self.currency,
+            "legs": self.legs,
+        })
+    }
+}
+
+/// The price of an itinerary with the tickets to buy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fare {
+    pub price: f32,
+    // the currency of the first ticket, the feeds are expected to use a single one
+    pub currency: String,
+    pub tickets: Vec<Ticket>,
+}
+
+impl Fare {
+    pub fn new(tickets: Vec<Ticket>) -> Fare {
+        Fare {
+            price: tickets.iter().map(|ticket| ticket.price).sum(),
+            currency: tickets
+                .first()
+                .map_or(String::new(), |ticket| ticket.currency.clone()),
+            tickets: tickets,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "price": self.price,
+            "currency": self.currency,
+            "tickets": self.tickets.iter().map(Ticket::to_json).collect::<Vec<Value>>(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Itinerary {
+    pub legs: Vec<Leg>,
+    // the price by the fares of the feed, None if the feed has none matching the rides
+    pub fare: Option<Fare>,
+    // the price by the zone and time tickets, None without them
+    pub zone_fare: Option<Fare>,
+}
+
+impl Itinerary {
+    pub fn new(legs: Vec<Leg>) -> Itinerary {
+        Itinerary {
+            legs: legs,
+            fare: None,
+            zone_fare: None,
+        }
+    }
+
+    pub fn departure_time(&self) -> Option<u32> {
+        self.legs.first().map(|leg| leg.departure_time)
+    }
+
+    pub fn arrival_time(&self) -> Option<u32> {
+        self.legs.last().map(|leg| leg.arrival_time)
+    }
+
+    /// Total distance travelled in meters
+    pub fn distance(&self) -> f32 {
+        self.legs.iter().map(|leg| leg.distance).sum()
+    }
+
+    /// Distance walked in meters
+    pub fn walking_distance(&self) -> f32 {
+        self.legs
+            .iter()
+            .filter(|leg| leg.is_walk() && !leg.cycling)
+            .map(|leg| leg.distance)
+            .sum()
+    }
+
+    pub fn transfer_count(&self) -> usize {
+        let rides = self.legs.iter().filter(|leg| !leg.is_walk()).count();
+        if rides > 0 {
+            rides - 1
+        } else {
+            0
+        }
+    }
+
+    /// True if the feed lacks the accessibility information for some of the legs
+    pub fn is_accessibility_uncertain(&self) -> bool {
+        self.legs
+            .iter()
+            .any(|leg| leg.wheelchair_accessible.is_none())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "departure_time": self.departure_time().map(format_time),
+            "arrival_time": self.arrival_time().map(format_time),
+            "distance": self.distance(),
+            "walking_distance": self.walking_distance(),
+            "transfer_count": self.transfer_count(),
+            "accessibility_uncertain": self.is_accessibility_uncertain(),
+            "legs": self.legs.iter().map(Leg::to_json).collect::<Vec<Value>>(),
+            "fare": self.fare.as_ref().map(Fare::to_json),
+            "zone_fare": self.zone_fare.as_ref().map(Fare::to_json),
+        })
+    }
+}
+
+/// A vehicle leaving a stop, as shown on a departure board
+#[derive(Debug, Clone)]
+pub struct Departure {
+    pub stop_id: String,
+    pub trip_id: String,
+    pub route_id: String,
+    pub route_short_name: String,
+    // the headsign of the stop time, falling back to the headsign of the trip
+    pub headsign: Option<String>,
+    pub platform_code: Option<String>,
+    // time of the day in seconds
+    pub scheduled_departure_time: u32,
+    // predicted time of the day in seconds, the scheduled one without realtime data
+    pub departure_time: u32,
+    pub departure_delay: Option<i32>,
+}
+
+impl Departure {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "stop_id": self.stop_id,
+            "trip_id": self.trip_id,
+            "route_id": self.route_id,
+            "route_short_name": self.route_short_name,
+            "headsign": self.headsign,
+            "platform_code": self.platform_code,
+            "scheduled_departure_time": format_time(self.scheduled_departure_time),
+            "departure_time": format_time(self.departure_time),
+            "departure_delay": self.departure_delay,
+        })
+    }
+}
+
+/// A vehicle arriving to a stop, as shown on an arrival board
+#[derive(Debug, Clone)]
+pub struct Arrival {
+    pub stop_id: String,
+    pub trip_id: String,
+    pub route_id: String,
+    pub route_short_name: String,
+    // the name of the first stop of the trip
+    pub origin: String,
+    pub platform_code: Option<String>,
+    // time of the day in seconds
+    pub scheduled_arrival_time: u32,
+    // predicted time of the day in seconds, the scheduled one without realtime data
+    pub arrival_time: u32,
+    pub arrival_delay: Option<i32>,
+}
+
+impl Arrival {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "stop_id": self.stop_id,
+            "trip_id": self.trip_id,
+            "route_id": self.route_id,
+            "route_short_name": self.route_short_name,
+            "origin": self.origin,
+            "platform_code": self.platform_code,
+            "scheduled_arrival_time": format_time(self.scheduled_arrival_time),
+            "arrival_time": format_time(self.arrival_time),
+            "arrival_delay": self.arrival_delay,
+        })
+    }
+}
+
+/// One stop of a trip in the trip detail
+#[derive(Debug, Clone)]
+pub struct TripStop {
+    pub stop_id: String,
+    pub stop_name: String,
+    pub stop_sequence: u32,
+    pub platform_code: Option<String>,
+    // times of the day in seconds
+    pub scheduled_arrival_time: u32,
+    pub scheduled_departure_time: u32,
+    // predicted times of the day in seconds, the scheduled ones without realtime data
+    pub arrival_time: u32,
+    pub departure_time: u32,
+    pub arrival_delay: Option<i32>,
+    pub departure_delay: Option<i32>,
+    // the vehicle does not stop here according to a realtime update
+    pub skipped: bool,
+}
+
+impl TripStop {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "stop_id": self.stop_id,
+            "stop_name": self.stop_name,
+            "stop_sequence": self.stop_sequence,
+            "platform_code": self.platform_code,
+            "scheduled_arrival_time": format_time(self.scheduled_arrival_time),
+            "scheduled_departure_time": format_time(self.scheduled_departure_time),
+            "arrival_time": format_time(self.arrival_time),
+            "departure_time": format_time(self.departure_time),
+            "arrival_delay": self.arrival_delay,
+            "departure_delay": self.departure_delay,
+            "skipped": self.skipped,
+        })
+    }
+}
+
+/// All the stops of a single trip on a given day
+#[derive(Debug, Clone)]
+pub struct TripDetail {
+    pub trip_id: String,
+    pub route_id: String,
+    pub route_short_name: String,
+    pub headsign: Option<String>,
+    // false if the service of the trip does not run on the day or the trip is cancelled
+    pub runs: bool,
+    pub cancelled: bool,
+    pub stops: Vec<TripStop>,
+}
+
+impl TripDetail {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "trip_id": self.trip_id,
+            "route_id": self.route_id,
+            "route_short_name": self.route_short_name,
+            "headsign": self.headsign,
+            "runs": self.runs,
+            "cancelled": self.cancelled,
+            "stops": self.stops.iter().map(TripStop::to_json).collect::<Vec<Value>>(),
+        })
+    }
+}
+
+impl fmt::Display for Leg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.trip_id {
+            Some(trip_id) => write!(f, "{} ride {}", format_time(self.departure_time), trip_id)?,
+            None if self.cycling => write!(f, "{} cycle", format_time(self.departure_time))?,
+            None => write!(f, "{} walk", format_time(self.departure_time))?,
+        }
+        write!(
+            f,
+            " from {} to {}, arriving {} ({:.1} km)",
+            self.from_stop_id,
+            self.to_stop_id,
+            format_time(self.arrival_time),
+            self.distance / 1000.0
+        )?;
+        if let Some(delay) = self.arrival_delay {
+            write!(f, " (delay {:+} min)", delay / 60)?;
+        }
+        if let Some(headway) = self.headway {
+            write!(f, " (every {} min)", headway / 60)?;
+        }
+        if self.is_request_stop() {
+            write!(f, " (request stop)")?;
+        }
+        if self.night {
+            write!(f, " (night line)")?;
+        }
+        for direction in &self.directions {
+            write!(f, "\n  - {}", direction)?;
+        }
+        for alert in &self.alerts {
+            if let Some(header_text) = &alert.header_text {
+                write!(f, "\n  ! {}", header_text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Fare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tickets: Vec<&str> = self
+            .tickets
+            .iter()
+            .map(|ticket| ticket.name.as_ref().unwrap_or(&ticket.fare_id).as_str())
+            .collect();
+        write!(
+            f,
+            "{:.2} {} ({})",
+            self.price,
+            self.currency,
+            tickets.join(" + ")
+        )
+    }
+}
+
+impl fmt::Display for Itinerary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for leg in &self.legs {
+            writeln!(f, "{}", leg)?;
+        }
+        if let Some(fare) = &self.fare {
+            writeln!(f, "Fare: {}", fare)?;
+        }
+        if let Some(zone_fare) = &self.zone_fare {
+            writeln!(f, "Zone fare: {}", zone_fare)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Departure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:>4} {}",
+            format_time(self.scheduled_departure_time),
+            self.route_short_name,
+            self.headsign.as_deref().unwrap_or("")
+        )?;
+        if let Some(platform_code) = &self.platform_code {
+            write!(f, " (platform {})", platform_code)?;
+        }
+        if let Some(delay) = self.departure_delay {
+            write!(f, " (delay {:+} min)", delay / 60)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Arrival {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:>4} from {}",
+            format_time(self.scheduled_arrival_time),
+            self.route_short_name,
+            self.origin
+        )?;
+        if let Some(platform_code) = &self.platform_code {
+            write!(f, " (platform {})", platform_code)?;
+        }
+        if let Some(delay) = self.arrival_delay {
+            write!(f, " (delay {:+} min)", delay / 60)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TripDetail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.route_short_name,
+            self.headsign.as_deref().unwrap_or(""),
+            self.trip_id
+        )?;
+        if self.cancelled {
+            write!(f, " (cancelled)")?;
+        } else if !self.runs {
+            write!(f, " (does not run on this day)")?;
+        }
+        writeln!(f)?;
+        for stop in &self.stops {
+            write!(
+                f,
+                "{} {} {}",
+                format_time(stop.scheduled_arrival_time),
+                format_time(stop.scheduled_departure_time),
+                stop.stop_name
+            )?;
+            if stop.skipped {
+                write!(f, " (skipped)")?;
+            } else if let Some(delay) = stop.departure_delay.or(stop.arrival_delay) {
+                write!(f, " (delay {:+} min)", delay / 60)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -1,65 +1,454 @@
-use chrono::NaiveDate;
+use chrono::{Datelike, NaiveDate, Weekday};
 use serde::{de, de::Unexpected, Deserialize, Deserializer};
+use std::collections::HashMap;
 
 // This file contains primitive GTFS structures to be loaded using Serde.
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Agency {
-    pub agency_id: String,
+    pub agency_id: Option<String>,
     pub agency_name: String,
     pub agency_url: String,
     pub agency_timezone: String,
-    pub agency_lang: String,
-    pub agency_phone: String,
+    pub agency_lang: Option<String>,
+    pub agency_phone: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Route {
     pub route_id: String,
-    pub agency_id: String,
+    pub agency_id: Option<String>,
+    // at least one of the names is present, the other one may be left empty
+    #[serde(default)]
     pub route_short_name: String,
+    #[serde(default)]
     pub route_long_name: String,
     pub route_type: u32,
     pub route_url: Option<String>,
     pub route_color: Option<String>,
     pub route_text_color: Option<String>,
-    #[serde(deserialize_with = "bool_from_int")]
+    // PID extension, false for feeds that do not provide it
+    #[serde(default, deserialize_with = "bool_from_optional_int")]
     pub is_night: bool,
+    // the group of routes the fares of GTFS Fares v2 apply to
+    pub network_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Route {
+    pub fn get_mode(&self) -> Mode {
+        Mode::from_route_type(self.route_type)
+    }
+}
+
+/// The kind of vehicles serving a route, grouping the basic and the extended route types. The
+/// route types without a matching mode, e.g. taxis or miscellaneous services, are Other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Tram,
+    Subway,
+    Rail,
+    Bus,
+    Ferry,
+    CableTram,
+    AerialLift,
+    Funicular,
+    Trolleybus,
+    Monorail,
+    Air,
+    Other,
+}
+
+impl Mode {
+    pub fn from_route_type(route_type: u32) -> Mode {
+        match route_type {
+            0 | 900..=999 => Mode::Tram,
+            12 | 405 => Mode::Monorail,
+            1 | 400..=699 => Mode::Subway,
+            2 | 100..=199 | 300..=399 => Mode::Rail,
+            3 | 200..=299 | 700..=799 => Mode::Bus,
+            4 | 1000..=1099 | 1200..=1299 => Mode::Ferry,
+            5 => Mode::CableTram,
+            6 | 1300..=1399 => Mode::AerialLift,
+            7 | 1400..=1499 => Mode::Funicular,
+            11 | 800..=899 => Mode::Trolleybus,
+            1100..=1199 => Mode::Air,
+            _ => Mode::Other,
+        }
+    }
+
+    /// Returns the name of the mode, the same as used by OpenTripPlanner
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Mode::Tram => "TRAM",
+            Mode::Subway => "SUBWAY",
+            Mode::Rail => "RAIL",
+            Mode::Bus => "BUS",
+            Mode::Ferry => "FERRY",
+            Mode::CableTram => "CABLE_CAR",
+            Mode::AerialLift => "GONDOLA",
+            Mode::Funicular => "FUNICULAR",
+            Mode::Trolleybus => "TROLLEYBUS",
+            Mode::Monorail => "MONORAIL",
+            Mode::Air => "AIRPLANE",
+            Mode::Other => "OTHER",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        let modes = [
+            Mode::Tram,
+            Mode::Subway,
+            Mode::Rail,
+            Mode::Bus,
+            Mode::Ferry,
+            Mode::CableTram,
+            Mode::AerialLift,
+            Mode::Funicular,
+            Mode::Trolleybus,
+            Mode::Monorail,
+            Mode::Air,
+            Mode::Other,
+        ];
+        let name = name.to_uppercase();
+        modes.iter().cloned().find(|mode| mode.get_name() == name)
+    }
+}
+
+#[test]
+fn test_mode_mapping() {
+    assert_eq!(Mode::from_route_type(0), Mode::Tram);
+    assert_eq!(Mode::from_route_type(1), Mode::Subway);
+    assert_eq!(Mode::from_route_type(3), Mode::Bus);
+    assert_eq!(Mode::from_route_type(109), Mode::Rail);
+    assert_eq!(Mode::from_route_type(300), Mode::Rail);
+    assert_eq!(Mode::from_route_type(401), Mode::Subway);
+    assert_eq!(Mode::from_route_type(500), Mode::Subway);
+    assert_eq!(Mode::from_route_type(600), Mode::Subway);
+    assert_eq!(Mode::from_route_type(800), Mode::Trolleybus);
+    assert_eq!(Mode::from_route_type(1400), Mode::Funicular);
+    assert_eq!(Mode::from_route_type(12), Mode::Monorail);
+    assert_eq!(Mode::from_route_type(405), Mode::Monorail);
+    assert_eq!(Mode::from_route_type(1501), Mode::Other);
+    assert_eq!(Mode::from_route_type(1700), Mode::Other);
+    assert_eq!(Mode::from_route_type(8), Mode::Other);
+    assert_eq!(Mode::from_name("other"), Some(Mode::Other));
+    assert_eq!(Mode::from_name("subway"), Some(Mode::Subway));
+    assert_eq!(Mode::from_name("HOVERCRAFT"), None);
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Trip {
     pub route_id: String,
     pub service_id: String,
     pub trip_id: String,
     pub trip_headsign: Option<String>,
     pub trip_short_name: Option<String>,
-    pub direction_id: u8,
+    pub direction_id: Option<u8>,
     pub block_id: Option<String>,
     pub shape_id: Option<String>,
     pub wheelchair_accessible: Option<u8>,
     pub bikes_allowed: Option<u8>,
+    // PID extensions
     pub exceptional: Option<u8>,
     pub trip_operation_type: Option<u8>,
     #[serde(default = "Vec::new", skip_deserializing)]
     pub stop_times: Vec<StopTime>,
+    // set for trips expanded from a frequencies.txt template
+    #[serde(skip_deserializing)]
+    pub template_trip_id: Option<String>,
+    // set for frequency based trips that do not run at exact times (exact_times=0)
+    #[serde(skip_deserializing)]
+    pub headway: Option<u32>,
+    // set by realtime updates
+    #[serde(skip_deserializing)]
+    pub cancelled: bool,
 }
 
-#[derive(Debug, Deserialize)]
+impl Trip {
+    /// Tells whether the vehicle can carry a wheelchair, None if unknown
+    pub fn is_wheelchair_accessible(&self) -> Option<bool> {
+        match self.wheelchair_accessible {
+            Some(1) => Some(true),
+            Some(2) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Tells whether the vehicle can carry bicycles, None if unknown
+    pub fn allows_bikes(&self) -> Option<bool> {
+        match self.bikes_allowed {
+            Some(1) => Some(true),
+            Some(2) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct StopTime {
     pub trip_id: String,
-    #[serde(deserialize_with = "deserialize_time")]
-    // time of the day in seconds
+    #[serde(deserialize_with = "deserialize_stop_time")]
+    // time of the day in seconds, interpolated for the stops that are not timepoints
     pub arrival_time: u32,
-    #[serde(deserialize_with = "deserialize_time")]
-    // time of the day in seconds
+    #[serde(deserialize_with = "deserialize_stop_time")]
+    // time of the day in seconds, interpolated for the stops that are not timepoints
     pub departure_time: u32,
     pub stop_id: String,
     pub stop_sequence: u32,
     pub stop_headsign: Option<String>,
+    // 0 (regularly scheduled) when missing or empty
+    #[serde(default, deserialize_with = "default_if_empty")]
     pub pickup_type: u8,
+    #[serde(default, deserialize_with = "default_if_empty")]
     pub drop_off_type: u8,
-    pub shape_dist_travelled: Option<f32>,
+    // in the units used by shapes.txt, which differ between feeds
+    pub shape_dist_traveled: Option<f32>,
+    // predicted times from realtime updates
+    #[serde(skip_deserializing)]
+    pub realtime_arrival_time: Option<u32>,
+    #[serde(skip_deserializing)]
+    pub realtime_departure_time: Option<u32>,
+    // set by realtime updates when the vehicle does not stop here
+    #[serde(skip_deserializing)]
+    pub skipped: bool,
+}
+
+impl StopTime {
+    /// The predicted arrival time if known, the scheduled one otherwise
+    pub fn get_arrival_time(&self) -> u32 {
+        self.realtime_arrival_time.unwrap_or(self.arrival_time)
+    }
+
+    /// The predicted departure time if known, the scheduled one otherwise
+    pub fn get_departure_time(&self) -> u32 {
+        self.realtime_departure_time.unwrap_or(self.departure_time)
+    }
+
+    pub fn get_arrival_delay(&self) -> Option<i32> {
+        self.realtime_arrival_time
+            .map(|time| time as i32 - self.arrival_time as i32)
+    }
+
+    pub fn get_departure_delay(&self) -> Option<i32> {
+        self.realtime_departure_time
+            .map(|time| time as i32 - self.departure_time as i32)
+    }
+
+    /// Pickup type 1 means passengers cannot board at this stop
+    pub fn allows_pickup(&self) -> bool {
+        self.pickup_type != 1
+    }
+
+    /// Drop off type 1 means passengers cannot alight at this stop
+    pub fn allows_drop_off(&self) -> bool {
+        self.drop_off_type != 1
+    }
+
+    /// Types 2 and 3 mean the passenger has to phone the agency or signal the driver
+    pub fn pickup_on_request(&self) -> bool {
+        self.pickup_type == 2 || self.pickup_type == 3
+    }
+
+    pub fn drop_off_on_request(&self) -> bool {
+        self.drop_off_type == 2 || self.drop_off_type == 3
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Frequency {
+    pub trip_id: String,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub start_time: u32,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub end_time: u32,
+    pub headway_secs: u32,
+    // 0 - the trips are only known to run with the given headway, 1 - the trips run at exact times
+    #[serde(default, deserialize_with = "default_if_empty")]
+    pub exact_times: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Level {
+    pub level_id: String,
+    // the floor relative to the street, 0 is the ground level, negative below the ground
+    pub level_index: f32,
+    pub level_name: Option<String>,
+}
+
+/// A walkway, stairs, an elevator or another way between two locations inside a station
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pathway {
+    pub pathway_id: String,
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    // 1 walkway, 2 stairs, 3 moving sidewalk, 4 escalator, 5 elevator, 6 fare gate, 7 exit gate
+    pub pathway_mode: u8,
+    #[serde(deserialize_with = "bool_from_int")]
+    pub is_bidirectional: bool,
+    // in meters
+    pub length: Option<f32>,
+    // in seconds
+    pub traversal_time: Option<u32>,
+    pub stair_count: Option<i32>,
+    pub signposted_as: Option<String>,
+    pub reversed_signposted_as: Option<String>,
+}
+
+/// Time in seconds assumed for a pathway with neither its traversal time nor its length given
+pub static DEFAULT_PATHWAY_TRAVERSAL_TIME: u32 = 30;
+
+impl Pathway {
+    /// True if the pathway can be used with a wheelchair, stairs and escalators cannot
+    pub fn is_step_free(&self) -> bool {
+        self.pathway_mode != 2
+            && self.pathway_mode != 4
+            && self
+                .stair_count
+                .map_or(true, |stair_count| stair_count == 0)
+    }
+
+    /// Returns the time in seconds needed to walk through the pathway at the given speed in
+    /// meters per second
+    pub fn get_traversal_time(&self, walking_speed: f32) -> u32 {
+        match (self.traversal_time, self.length) {
+            (Some(traversal_time), _) => traversal_time,
+            (None, Some(length)) => (length / walking_speed).round() as u32,
+            (None, None) => DEFAULT_PATHWAY_TRAVERSAL_TIME,
+        }
+    }
+
+    /// Describes how to go through the pathway, e.g. "Take the stairs"
+    pub fn get_action(&self) -> &'static str {
+        match self.pathway_mode {
+            2 => "Take the stairs",
+            3 => "Take the moving sidewalk",
+            4 => "Take the escalator",
+            5 => "Take the elevator",
+            6 => "Pass the fare gate",
+            7 => "Pass the exit gate",
+            _ => "Walk",
+        }
+    }
+}
+
+#[test]
+fn test_pathway_properties() {
+    let mut pathway = Pathway {
+        pathway_id: String::from("P1"),
+        from_stop_id: String::from("A"),
+        to_stop_id: String::from("B"),
+        pathway_mode: 1,
+        is_bidirectional: true,
+        length: Some(36.0),
+        traversal_time: None,
+        stair_count: None,
+        signposted_as: None,
+        reversed_signposted_as: None,
+    };
+    assert!(pathway.is_step_free());
+    assert_eq!(pathway.get_traversal_time(1.2), 30);
+    pathway.pathway_mode = 4;
+    pathway.traversal_time = Some(45);
+    assert!(!pathway.is_step_free());
+    assert_eq!(pathway.get_traversal_time(1.2), 45);
+    assert_eq!(pathway.get_action(), "Take the escalator");
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FareAttribute {
+    pub fare_id: String,
+    pub price: f32,
+    pub currency_type: String,
+    // 0 paid on board, 1 paid before boarding
+    pub payment_method: u8,
+    // the number of transfers allowed, unlimited if missing
+    pub transfers: Option<usize>,
+    pub agency_id: Option<String>,
+    // seconds from the first boarding the ticket can be used for transfers, unlimited if missing
+    pub transfer_duration: Option<u32>,
+}
+
+/// Assigns a fare to the rides of a route or between and through zones. All the fields that are
+/// set have to match.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FareRule {
+    pub fare_id: String,
+    pub route_id: Option<String>,
+    pub origin_id: Option<String>,
+    pub destination_id: Option<String>,
+    pub contains_id: Option<String>,
+}
+
+/// A ticket or a transfer that can be bought, from fare_products.txt of GTFS Fares v2
+#[derive(Debug, Clone, Deserialize)]
+pub struct FareProduct {
+    pub fare_product_id: String,
+    pub fare_product_name: Option<String>,
+    // the same product may be listed once for each fare media with a different amount
+    pub fare_media_id: Option<String>,
+    pub amount: f32,
+    pub currency: String,
+}
+
+/// Assigns a fare product to the rides of a network between areas
+#[derive(Debug, Clone, Deserialize)]
+pub struct FareLegRule {
+    pub leg_group_id: Option<String>,
+    pub network_id: Option<String>,
+    pub from_area_id: Option<String>,
+    pub to_area_id: Option<String>,
+    pub from_timeframe_group_id: Option<String>,
+    pub to_timeframe_group_id: Option<String>,
+    pub fare_product_id: String,
+    // only the matching rules with the highest priority are used, 0 if missing
+    pub rule_priority: Option<i32>,
+}
+
+/// The price of a transfer between the rides of two leg groups
+#[derive(Debug, Clone, Deserialize)]
+pub struct FareTransferRule {
+    pub from_leg_group_id: Option<String>,
+    pub to_leg_group_id: Option<String>,
+    // the number of consecutive transfers allowed, -1 is unlimited
+    pub transfer_count: Option<i32>,
+    // in seconds
+    pub duration_limit: Option<u32>,
+    // 0 departure to arrival, 1 departure to departure, 2 arrival to departure, 3 arrival to
+    // arrival
+    pub duration_limit_type: Option<u8>,
+    // 0 the first ride and the transfer, 1 both rides and the transfer, 2 only the transfer
+    pub fare_transfer_type: u8,
+    // the transfer is free if missing
+    pub fare_product_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Area {
+    pub area_id: String,
+    pub area_name: Option<String>,
+}
+
+/// Puts a stop into an area, the platforms of a station are in the areas of the station
+#[derive(Debug, Clone, Deserialize)]
+pub struct StopArea {
+    pub area_id: String,
+    pub stop_id: String,
+}
+
+/// Assigns a route to a network, an alternative to the network_id of routes.txt
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteNetwork {
+    pub network_id: String,
+    pub route_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShapePoint {
+    pub shape_id: String,
+    pub shape_pt_lat: f32,
+    pub shape_pt_lon: f32,
+    pub shape_pt_sequence: u32,
+    pub shape_dist_traveled: Option<f32>,
 }
 
 fn deserialize_ymd<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
@@ -70,13 +459,99 @@ where
     Ok(NaiveDate::parse_from_str(&s, "%Y%m%d").unwrap())
 }
 
+/// The time of a stop that is not a timepoint, replaced by an interpolated time after loading
+pub const UNKNOWN_TIME: u32 = u32::MAX;
+
+/// Parses a time of the day given as H:MM:SS into seconds, the hours may exceed 24
+fn parse_time(time: &str) -> Option<u32> {
+    let hms: Vec<&str> = time.trim().split(':').collect();
+    if hms.len() != 3 {
+        return None;
+    }
+    let hours: u32 = hms[0].parse().ok()?;
+    let minutes: u32 = hms[1].parse().ok()?;
+    let seconds: u32 = hms[2].parse().ok()?;
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    hours.checked_mul(3600)?.checked_add(60 * minutes + seconds)
+}
+
 fn deserialize_time<'de, D>(deserializer: D) -> Result<u32, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s: String = Deserialize::deserialize(deserializer)?;
-    let hms: Vec<u32> = s.split(":").map(|x| x.parse::<u32>().unwrap()).collect();
-    return Ok(3600 * hms[0] + 60 * hms[1] + hms[2]);
+    parse_time(&s).ok_or_else(|| de::Error::invalid_value(Unexpected::Str(&s), &"a time H:MM:SS"))
+}
+
+/// Same as deserialize_time, but an empty time is read as UNKNOWN_TIME
+fn deserialize_stop_time<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    if s.trim().is_empty() {
+        return Ok(UNKNOWN_TIME);
+    }
+    parse_time(&s).ok_or_else(|| de::Error::invalid_value(Unexpected::Str(&s), &"a time H:MM:SS"))
+}
+
+#[test]
+fn test_time_parsing() {
+    assert_eq!(parse_time("08:05:30"), Some(8 * 3600 + 5 * 60 + 30));
+    assert_eq!(parse_time("7:00:00"), Some(7 * 3600));
+    assert_eq!(parse_time("25:10:00"), Some(25 * 3600 + 10 * 60));
+    assert_eq!(parse_time(""), None);
+    assert_eq!(parse_time("08:05"), None);
+    assert_eq!(parse_time("08:65:00"), None);
+    assert_eq!(parse_time("eight"), None);
+    assert_eq!(parse_time("4294967295:00:00"), None);
+}
+
+/// Prepends the prefix of a feed to an id, which keeps the ids of merged feeds apart
+pub fn add_prefix(prefix: &str, id: &mut String) {
+    id.insert_str(0, prefix);
+}
+
+pub fn add_prefix_to_option(prefix: &str, id: &mut Option<String>) {
+    if let Some(id) = id {
+        add_prefix(prefix, id);
+    }
+}
+
+/// Prepends the prefix to each of the zones of a list separated by commas
+pub fn add_prefix_to_zones(prefix: &str, zone_ids: &mut Option<String>) {
+    if let Some(zone_ids) = zone_ids {
+        *zone_ids = zone_ids
+            .split(',')
+            .map(|zone_id| format!("{}{}", prefix, zone_id.trim()))
+            .collect::<Vec<String>>()
+            .join(",");
+    }
+}
+
+/// Rebuilds a map after the ids of its values changed
+pub fn rekey<T, F>(map: HashMap<String, T>, mut update: F) -> HashMap<String, T>
+where
+    F: FnMut(String, T) -> (String, T),
+{
+    map.into_iter()
+        .map(|(key, value)| update(key, value))
+        .collect()
+}
+
+#[test]
+fn test_id_prefixes() {
+    let mut id = String::from("MA1");
+    add_prefix("pid:", &mut id);
+    assert_eq!(id, "pid:MA1");
+    let mut zone_ids = Some(String::from("B, 1"));
+    add_prefix_to_zones("pid:", &mut zone_ids);
+    assert_eq!(zone_ids, Some(String::from("pid:B,pid:1")));
+    let mut missing = None;
+    add_prefix_to_option("pid:", &mut missing);
+    assert_eq!(missing, None);
 }
 
 fn bool_from_int<'de, D>(deserializer: D) -> Result<bool, D::Error>
@@ -93,7 +568,32 @@ where
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// Same as bool_from_int, but an empty field is read as false
+fn bool_from_optional_int<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<u8>::deserialize(deserializer)? {
+        None | Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        Some(other) => Err(de::Error::invalid_value(
+            Unexpected::Unsigned(other as u64),
+            &"zero or one",
+        )),
+    }
+}
+
+/// Reads an empty field as the default value of the type. Combined with #[serde(default)], this
+/// gives the spec-defined default both for missing columns and for empty values.
+pub(crate) fn default_if_empty<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Service {
     pub service_id: String,
     #[serde(deserialize_with = "bool_from_int")]
@@ -118,7 +618,48 @@ pub struct Service {
     pub exceptions: Vec<ServiceException>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Service {
+    /// Creates a service that is only defined by its exceptions in calendar_dates.txt
+    pub fn without_calendar(service_id: &str, date: NaiveDate) -> Service {
+        Service {
+            service_id: String::from(service_id),
+            monday: false,
+            tuesday: false,
+            wednesday: false,
+            thursday: false,
+            friday: false,
+            saturday: false,
+            sunday: false,
+            start_date: date,
+            end_date: date,
+            exceptions: Vec::new(),
+        }
+    }
+
+    /// Decides whether the service runs on the given date, taking exceptions into account
+    pub fn runs_on(&self, date: NaiveDate) -> bool {
+        for exception in &self.exceptions {
+            if exception.date == date {
+                // 1 - service added for the date, 2 - service removed for the date
+                return exception.exception_type == 1;
+            }
+        }
+        if date < self.start_date || date > self.end_date {
+            return false;
+        }
+        match date.weekday() {
+            Weekday::Mon => self.monday,
+            Weekday::Tue => self.tuesday,
+            Weekday::Wed => self.wednesday,
+            Weekday::Thu => self.thursday,
+            Weekday::Fri => self.friday,
+            Weekday::Sat => self.saturday,
+            Weekday::Sun => self.sunday,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct ServiceException {
     pub service_id: String,
     #[serde(deserialize_with = "deserialize_ymd")]
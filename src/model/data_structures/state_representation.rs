@@ -1,29 +1,46 @@
+use crate::model::data_structures::fares::*;
+use crate::model::data_structures::itinerary::*;
 use crate::model::data_structures::primitive_gtfs::*;
-use core::cmp::Ordering;
+use crate::model::loader;
+use chrono::{NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use core::cmp::{Ordering, Reverse};
+use geo_types::{LineString, Point};
 use serde::Deserialize;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
 // TODO should contain the data structures used for actual searching in the graph and the mechanisms to construct them
 
 pub static MINIMAL_TRANSFER_TIME: u32 = 0;
 
-
 // TODO move stop to primitive_gtfs, it logically doesn't really belong here
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Stop {
     pub stop_id: String,
+    #[serde(default)]
     pub stop_name: String,
-    pub stop_lat: f32,
-    pub stop_lon: f32,
-    pub zone_id: String,
+    // required for stops, stations and entrances, optional for generic nodes and boarding areas
+    pub stop_lat: Option<f32>,
+    pub stop_lon: Option<f32>,
+    pub zone_id: Option<String>,
     pub stop_url: Option<String>,
+    // 0 (stop or platform) when missing or empty
+    #[serde(default, deserialize_with = "default_if_empty")]
     pub location_type: u8,
     pub parent_station: Option<String>,
     pub wheelchair_boarding: Option<i32>,
     pub level_id: Option<String>,
     pub platform_code: Option<String>,
+    // moved to a station of another feed, the stop keeps the zone and the fare areas of its
+    // original station instead of taking the ones of the new station
+    #[serde(default, skip_deserializing)]
+    pub merged: bool,
     #[serde(default = "Vec::new", skip_deserializing)]
     departure_nodes: Vec<usize>,
+    #[serde(default = "Vec::new", skip_deserializing)]
+    arrival_nodes: Vec<usize>,
     #[serde(default = "Stop::default_state", skip_deserializing)]
     finalized: bool,
 }
@@ -33,19 +50,50 @@ impl Stop {
         false
     }
 
+    /// The position of the stop in WGS84 coordinates, None for the generic nodes and the
+    /// boarding areas without one
+    pub fn get_coords(&self) -> Option<Point<f32>> {
+        match (self.stop_lon, self.stop_lat) {
+            (Some(lon), Some(lat)) => Some(Point::new(lon, lat)),
+            _ => None,
+        }
+    }
+
     pub fn get_dep_node(&self, index: usize) -> usize {
         self.departure_nodes[index]
     }
 
     pub fn add_dep_node(&mut self, dep_node: usize) -> Result<(), &str> {
         if !self.finalized {
-            &self.departure_nodes.push(dep_node);
+            self.departure_nodes.push(dep_node);
             Ok(())
         } else {
             Err("Tried to add a new departure node to an already finalized Stop.")
         }
     }
 
+    pub fn add_arr_node(&mut self, arr_node: usize) {
+        self.arrival_nodes.push(arr_node);
+    }
+
+    /// Returns the departure nodes sorted by their times
+    pub fn get_dep_nodes(&self) -> &Vec<usize> {
+        &self.departure_nodes
+    }
+
+    /// Returns the arrival nodes in no particular order
+    pub fn get_arr_nodes(&self) -> &Vec<usize> {
+        &self.arrival_nodes
+    }
+
+    /// Forgets the departure and arrival nodes, so that the stop can be filled again for a
+    /// rebuilt graph
+    pub fn reset(&mut self) {
+        self.departure_nodes.clear();
+        self.arrival_nodes.clear();
+        self.finalized = false;
+    }
+
     pub fn dep_node_count(&self) -> usize {
         self.departure_nodes.len()
     }
@@ -55,21 +103,59 @@ impl Stop {
         // sort nodes by departure times
         self.departure_nodes
             .sort_by(|a, b| nodes[*a].get_time().cmp(&nodes[*b].get_time()));
-        // add edges between them
+        // add edges between them, so that a passenger can wait for a later departure
         if self.dep_node_count() >= 2 {
-            for index in 0..self.dep_node_count() - 2 {
+            for index in 0..self.dep_node_count() - 1 {
                 let dep = self.get_dep_node(index);
-                nodes[dep].add_edge(dep);
+                let next_dep = self.get_dep_node(index + 1);
+                nodes[dep].add_edge(next_dep);
             }
         }
         self.finalized = true;
     }
 
-    pub fn get_earliest_dep(
+    /// Moves a departure node whose time has changed to its new place in the sorted list and
+    /// fixes the departure chain at both the old and the new place
+    pub fn reposition_dep_node(&mut self, dep_node: usize, nodes: &mut Vec<Node>) {
+        let old_index = match self.departure_nodes.iter().position(|&d| d == dep_node) {
+            Some(old_index) => old_index,
+            None => return,
+        };
+        self.departure_nodes.remove(old_index);
+        if old_index > 0 {
+            let next = self.departure_nodes.get(old_index).cloned();
+            set_next_departure(nodes, self.departure_nodes[old_index - 1], next);
+        }
+        let time = nodes[dep_node].get_time();
+        let new_index = self
+            .departure_nodes
+            .iter()
+            .position(|&d| nodes[d].get_time() > time)
+            .unwrap_or(self.departure_nodes.len());
+        self.departure_nodes.insert(new_index, dep_node);
+        if new_index > 0 {
+            set_next_departure(nodes, self.departure_nodes[new_index - 1], Some(dep_node));
+        }
+        let next = self.departure_nodes.get(new_index + 1).cloned();
+        set_next_departure(nodes, dep_node, next);
+    }
+
+    /// Returns the time of the latest departure before the given time, leaving out one node
+    pub fn get_latest_dep_time_before(
         &self,
         time: u32,
+        excluded: usize,
         nodes: &Vec<Node>,
-    ) -> Result<Option<usize>, &str> {
+    ) -> Option<u32> {
+        self.departure_nodes
+            .iter()
+            .filter(|&&d| d != excluded)
+            .map(|&d| nodes[d].get_time())
+            .take_while(|&dep_time| dep_time < time)
+            .last()
+    }
+
+    pub fn get_earliest_dep(&self, time: u32, nodes: &Vec<Node>) -> Result<Option<usize>, &str> {
         if self.finalized {
             let mut l: i32 = 0;
             let mut r = self.dep_node_count() as i32 - 1;
@@ -92,19 +178,57 @@ impl Stop {
     }
 }
 
+/// Replaces the departure chain edge of a departure node
+fn set_next_departure(nodes: &mut Vec<Node>, dep_node: usize, next: Option<usize>) {
+    let chain_edges: Vec<usize> = nodes[dep_node]
+        .get_edges()
+        .iter()
+        .cloned()
+        .filter(|&e| nodes[e].kind == NodeKind::Departure)
+        .collect();
+    nodes[dep_node].edges.retain(|e| !chain_edges.contains(e));
+    if let Some(next) = next {
+        nodes[dep_node].add_edge(next);
+    }
+}
+
+/// The role of a node in the time-expanded graph. Every stop time of a trip is represented by
+/// a transport node (the passenger is in the vehicle), an arrival node (the passenger got off)
+/// and a departure node (the passenger is waiting for the vehicle).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Arrival,
+    Departure,
+    Transport,
+}
+
 #[derive(Debug, Clone)]
 pub struct Node {
     // TODO Does node need to know its ID?
     pub node_id: usize,
+    pub kind: NodeKind,
     time: u32,
+    // index into Network::trip_ids
+    trip_index: usize,
+    // index into Trip::stop_times
+    stop_index: usize,
     edges: Vec<usize>, // neighbour list, neighbours represented by ID
 }
 
 impl Node {
-    pub fn new(node_id: usize, time: u32) -> Node {
+    pub fn new(
+        node_id: usize,
+        kind: NodeKind,
+        time: u32,
+        trip_index: usize,
+        stop_index: usize,
+    ) -> Node {
         Node {
             node_id: node_id,
+            kind: kind,
             time: time,
+            trip_index: trip_index,
+            stop_index: stop_index,
             edges: Vec::new(),
         }
     }
@@ -113,12 +237,32 @@ impl Node {
         self.time
     }
 
+    pub fn get_trip_index(&self) -> usize {
+        self.trip_index
+    }
+
+    pub fn get_stop_index(&self) -> usize {
+        self.stop_index
+    }
+
     pub fn get_edges(&self) -> &Vec<usize> {
         &self.edges
     }
 
     pub fn add_edge(&mut self, node_id: usize) {
-        &self.edges.push(node_id);
+        self.edges.push(node_id);
+    }
+
+    /// Adds or removes an edge
+    pub fn set_edge(&mut self, node_id: usize, present: bool) {
+        let position = self.edges.iter().position(|&e| e == node_id);
+        match (position, present) {
+            (None, true) => self.edges.push(node_id),
+            (Some(position), false) => {
+                self.edges.remove(position);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -142,71 +286,2123 @@ impl PartialOrd for Node {
     }
 }
 
-#[derive(Debug)]
+/// A walking connection from a stop to a nearby stop
+#[derive(Debug, Clone)]
+pub struct Footpath {
+    pub target_stop_id: String,
+    // in meters
+    pub distance: f32,
+    // in seconds
+    pub duration: u32,
+    // the pathways walked through inside a station, with true for those walked against their
+    // direction, empty for walks outside
+    pub pathways: Vec<(String, bool)>,
+    // the walk avoids stairs and escalators. A stop may have a faster footpath with stairs and a
+    // slower step-free one to the same target.
+    pub step_free: bool,
+}
+
+impl Footpath {
+    pub fn new(target_stop_id: String, distance: f32, duration: u32) -> Footpath {
+        Footpath {
+            target_stop_id: target_stop_id,
+            distance: distance,
+            duration: duration,
+            pathways: Vec::new(),
+            step_free: true,
+        }
+    }
+}
+
+/// Combines the accessibility of the parts of a leg, the leg is inaccessible if any part is and
+/// unknown if any part is unknown
+fn combine_accessibility(parts: &[Option<bool>]) -> Option<bool> {
+    if parts.contains(&Some(false)) {
+        Some(false)
+    } else if parts.contains(&None) {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+/// Stops divided into squares by their UTM coordinates, used to find the stops near a stop or a
+/// point
+#[derive(Debug, Clone)]
+pub struct ProximityIndex {
+    utm_coords: HashMap<String, Point<f32>>,
+    squares: HashMap<(i32, i32), Vec<String>>,
+    // in meters
+    square_size: f32,
+}
+
+impl ProximityIndex {
+    pub fn new(
+        utm_coords: HashMap<String, Point<f32>>,
+        squares: HashMap<(i32, i32), Vec<String>>,
+        square_size: f32,
+    ) -> ProximityIndex {
+        ProximityIndex {
+            utm_coords: utm_coords,
+            squares: squares,
+            square_size: square_size,
+        }
+    }
+
+    /// Returns the distance in meters between two stops, measured the same way as the
+    /// pedestrian connections (the sum of the differences of the UTM coordinates)
+    pub fn get_distance(&self, from_stop_id: &str, to_stop_id: &str) -> Option<f32> {
+        let from = self.utm_coords.get(from_stop_id)?;
+        let to = self.utm_coords.get(to_stop_id)?;
+        Some((from.x() - to.x()).abs() + (from.y() - to.y()).abs())
+    }
+
+    pub fn get_coords(&self, stop_id: &str) -> Option<Point<f32>> {
+        self.utm_coords.get(stop_id).cloned()
+    }
+
+    /// Finds the other stops at most max_distance meters away, together with their distances
+    pub fn get_stops_within(&self, stop_id: &str, max_distance: f32) -> Vec<(String, f32)> {
+        match self.utm_coords.get(stop_id) {
+            Some(&coord) => self
+                .get_stops_near(coord, max_distance)
+                .into_iter()
+                .filter(|(near_id, _)| near_id != stop_id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Finds the stops at most max_distance meters away from a point in UTM coordinates,
+    /// together with their distances
+    pub fn get_stops_near(&self, coord: Point<f32>, max_distance: f32) -> Vec<(String, f32)> {
+        let (x, y) = (
+            (coord.x() / self.square_size) as i32,
+            (coord.y() / self.square_size) as i32,
+        );
+        let reach = (max_distance / self.square_size).ceil() as i32;
+        let mut near_stops = Vec::new();
+        for dx in -reach..reach + 1 {
+            for dy in -reach..reach + 1 {
+                for near_id in self.squares.get(&(x + dx, y + dy)).into_iter().flatten() {
+                    let near = self.utm_coords[near_id];
+                    let distance = (coord.x() - near.x()).abs() + (coord.y() - near.y()).abs();
+                    if distance <= max_distance {
+                        near_stops.push((near_id.clone(), distance));
+                    }
+                }
+            }
+        }
+        near_stops
+    }
+}
+
+/// Time needed to get the bicycle on and off a vehicle in seconds
+pub static BASE_CYCLING_TRANSFER_TIME: u32 = 120;
+/// The longest cycling transfer that can be requested in meters
+pub static MAX_CYCLING_DISTANCE: f32 = 10000.0;
+
+/// Settings of the transfers ridden on a bicycle instead of walking
+#[derive(Debug, Clone)]
+pub struct Cycling {
+    // in meters per second
+    pub speed: f32,
+    // the longest transfer in meters
+    pub max_distance: f32,
+}
+
+impl Cycling {
+    /// Returns the time in seconds needed to transfer the given distance in meters
+    pub fn get_duration(&self, distance: f32) -> u32 {
+        BASE_CYCLING_TRANSFER_TIME + (distance / self.speed).round() as u32
+    }
+}
+
+impl Default for Cycling {
+    fn default() -> Cycling {
+        Cycling {
+            // 15 km/h
+            speed: 4.2,
+            max_distance: 3000.0,
+        }
+    }
+}
+
+/// How the search treats the night lines (Route::is_night)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NightLines {
+    // the night lines are used like any other
+    #[default]
+    Any,
+    // a connection using only night lines is chosen if it does not arrive much later
+    Prefer,
+    // a connection without night lines is chosen if it does not arrive much later
+    Avoid,
+    // only the night lines are used
+    Require,
+    // the night lines are not used
+    Exclude,
+}
+
+/// How much later in seconds a connection preferred because of its night lines may arrive
+pub static NIGHT_LINE_PREFERENCE_SLACK: u32 = 15 * 60;
+
+/// A stop or station the connection has to pass through
+#[derive(Debug, Clone)]
+pub struct Via {
+    pub stop_id: String,
+    // how long in seconds the rider stays at the stop before continuing
+    pub min_dwell_time: u32,
+}
+
+/// Where a connection starts or ends
+#[derive(Debug, Clone, PartialEq)]
+pub enum Place {
+    // a stop, or a station left and reached at any of its platforms
+    Stop(String),
+    // WGS84 coordinates of the rider's position, x is the longitude
+    Coordinates(Point<f32>),
+}
+
+impl Place {
+    /// Parses a place given either as latitude,longitude or as a stop id. The name in the
+    /// OpenTripPlanner form name::latitude,longitude is left out. Fails for coordinates out of
+    /// the range of latitudes and longitudes.
+    pub fn parse(place: &str) -> Result<Place, &'static str> {
+        let place = place.rsplit("::").next().unwrap_or(place);
+        let mut parts = place.splitn(2, ',').map(|part| part.trim().parse::<f32>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(lat)), Some(Ok(lon))) => {
+                if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+                    Ok(Place::Coordinates(Point::new(lon, lat)))
+                } else {
+                    Err("Coordinates out of range.")
+                }
+            }
+            _ => Ok(Place::Stop(String::from(place))),
+        }
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Place::Stop(stop_id) => write!(f, "{}", stop_id),
+            Place::Coordinates(point) => write!(f, "{:.5},{:.5}", point.y(), point.x()),
+        }
+    }
+}
+
+/// The farthest stop in meters walked to from coordinates at the start or the end of a connection
+pub static MAX_ACCESS_DISTANCE: f32 = 1000.0;
+/// The longest walk in meters offered instead of a connection between coordinates
+pub static MAX_DIRECT_WALKING_DISTANCE: f32 = 3000.0;
+
+/// A walk between a place and a stop at the start or the end of a connection
+struct Access {
+    stop_id: String,
+    // in meters
+    distance: f32,
+    // in seconds
+    duration: u32,
+}
+
+impl Access {
+    fn walk(stop_id: String, distance: f32) -> Access {
+        Access {
+            stop_id: stop_id,
+            distance: distance,
+            duration: (distance / loader::PEDESTRIAN_SPEED).round() as u32,
+        }
+    }
+}
+
+/// Restrictions of a search requested by the rider
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    // only trips and stops known to be accessible with a wheelchair are used
+    pub wheelchair: bool,
+    // only trips known to carry bicycles are used
+    pub bikes: bool,
+    // replaces the walking transfers with cycling, implies bikes
+    pub cycling: Option<Cycling>,
+    // only the routes of these modes are used, all if empty
+    pub allowed_modes: Vec<Mode>,
+    pub denied_modes: Vec<Mode>,
+    // only these routes are used, all if empty
+    pub allowed_routes: Vec<String>,
+    pub denied_routes: Vec<String>,
+    // only the routes of these agencies are used, all if empty
+    pub allowed_agencies: Vec<String>,
+    pub denied_agencies: Vec<String>,
+    pub night_lines: NightLines,
+    // stops or stations the connection passes through in this order
+    pub via: Vec<Via>,
+    // stops or stations the connection must not use, not even passing through in a vehicle
+    pub avoided_stops: Vec<String>,
+    pub max_transfers: Option<usize>,
+    // meters walked between stops in the whole connection and in a single walk
+    pub max_walking_distance: Option<f32>,
+    pub max_walking_leg_distance: Option<f32>,
+}
+
+impl SearchOptions {
+    /// Decides whether the mode, route and agency lists and the night line setting let the
+    /// search use the route
+    pub fn allows_route(&self, route: &Route) -> bool {
+        fn is_allowed<T: PartialEq>(allowed: &[T], denied: &[T], value: &T) -> bool {
+            (allowed.is_empty() || allowed.contains(value)) && !denied.contains(value)
+        }
+        // routes of feeds with a single agency may leave out the agency id
+        let agency_id = route.agency_id.clone().unwrap_or_default();
+        let night_line_allowed = match self.night_lines {
+            NightLines::Require => route.is_night,
+            NightLines::Exclude => !route.is_night,
+            _ => true,
+        };
+        night_line_allowed
+            && is_allowed(&self.allowed_modes, &self.denied_modes, &route.get_mode())
+            && is_allowed(&self.allowed_routes, &self.denied_routes, &route.route_id)
+            && is_allowed(&self.allowed_agencies, &self.denied_agencies, &agency_id)
+    }
+
+    fn has_limits(&self) -> bool {
+        self.max_transfers.is_some()
+            || self.max_walking_distance.is_some()
+            || self.max_walking_leg_distance.is_some()
+    }
+}
+
+/// Returned when connections exist, but none of them keeps to the transfer and walking limits
+pub static NO_JOURNEY_WITHIN_LIMITS: &str = "No journey within the transfer and walking limits.";
+
+/// Data prepared for a single search
+struct SearchContext<'a> {
+    options: &'a SearchOptions,
+    // indexed by Node::trip_index
+    active_trips: Vec<bool>,
+    // POSIX timestamp of the start of the service day
+    service_day_start: i64,
+    // alerts that make stops, routes or trips unusable
+    closures: Vec<&'a ServiceAlert>,
+    // the avoided stops with the stations replaced by their platforms
+    avoided_stops: HashSet<&'a str>,
+    // the platforms of the via stops in their order, with the dwell times
+    via: Vec<(HashSet<&'a str>, u32)>,
+    // whether some of the active trips only run with a known headway
+    has_headway_trips: bool,
+}
+
+/// A way of reaching a node found by the search, with the resources used on the way. Without
+/// the limits the resources are not counted, so only the time decides.
+struct Label {
+    node: usize,
+    time: u32,
+    // since when the passenger waits at the stop, tracked only when boarding trips that run with
+    // a known headway needs it
+    ready: u32,
+    // the vehicles boarded
+    rides: usize,
+    // meters walked between stops
+    walking: f32,
+    parent: Option<usize>,
+    // the index of the origin the path starts at
+    origin: usize,
+    // the number of via stops passed
+    stage: usize,
+    // a label reaching the node sooner with less resources has been found
+    dominated: bool,
+}
+
+impl Label {
+    fn dominates(&self, other: &Label) -> bool {
+        self.time <= other.time
+            && self.ready <= other.ready
+            && self.rides <= other.rides
+            && self.walking <= other.walking
+    }
+}
+
+/// The path found by the search
+struct SearchPath {
+    // the nodes with the times they are reached at
+    nodes: Vec<(usize, u32)>,
+    // the index of the origin the path starts at
+    origin: usize,
+    // the index of the destination the path ends at
+    destination: usize,
+}
+
+/// Adds the label to the labels of its node and stage unless it is dominated by one of them, the
+/// labels it dominates are marked. Returns the index of the label if added.
+/// The bags of the stages follow each other, node_count bags each.
+fn add_label(
+    labels: &mut Vec<Label>,
+    bags: &mut [Vec<usize>],
+    node_count: usize,
+    label: Label,
+) -> Option<usize> {
+    let bag = &mut bags[label.stage * node_count + label.node];
+    if bag.iter().any(|&old| labels[old].dominates(&label)) {
+        return None;
+    }
+    bag.retain(|&old| {
+        let dominated = label.dominates(&labels[old]);
+        labels[old].dominated |= dominated;
+        !dominated
+    });
+    bag.push(labels.len());
+    labels.push(label);
+    Some(labels.len() - 1)
+}
+
+/// The timetable with the graph searched for the connections. The clones of a network share its
+/// parts, the realtime updates copy only the parts they change.
+#[derive(Debug, Clone)]
 pub struct Network {
-    stops: HashMap<String, Stop>,
-    routes: HashMap<String, Route>,
-    trips: HashMap<String, Trip>,
-    services: HashMap<String, Service>,
-    nodes: Vec<Node>,
+    agencies: Arc<HashMap<String, Agency>>,
+    // the timezone the times of the feed are in, shared by all the agencies
+    timezone: Tz,
+    stops: Arc<HashMap<String, Stop>>,
+    routes: Arc<HashMap<String, Route>>,
+    trips: Arc<HashMap<String, Trip>>,
+    services: Arc<HashMap<String, Service>>,
+    // trip ids in the order used by Node::trip_index, sorted
+    trip_ids: Arc<Vec<String>>,
+    // the range of node ids created for each trip, indexed by Node::trip_index
+    trip_nodes: Arc<Vec<(usize, usize)>>,
+    footpaths: Arc<HashMap<String, Vec<Footpath>>>,
+    // the stops with footpaths leading to each stop, each listed once
+    walking_sources: Arc<HashMap<String, Vec<String>>>,
+    // the ways between the locations inside stations, used for the directions of the transfers
+    pathways: Arc<HashMap<String, Pathway>>,
+    levels: Arc<HashMap<String, Level>>,
+    proximity_index: Arc<ProximityIndex>,
+    // shape points sorted by their sequence
+    shapes: Arc<HashMap<String, Vec<ShapePoint>>>,
+    alerts: Arc<Vec<ServiceAlert>>,
+    fares: Arc<Fares>,
+    nodes: Arc<Vec<Node>>,
+    // the service day the realtime predictions and cancellations are for
+    realtime_date: Option<NaiveDate>,
+    // the network without the realtime data, searched on the other days
+    scheduled: Option<Arc<Network>>,
 }
 
 impl Network {
     pub fn new(
+        agencies: HashMap<String, Agency>,
         stops: HashMap<String, Stop>,
         routes: HashMap<String, Route>,
         trips: HashMap<String, Trip>,
         services: HashMap<String, Service>,
+        trip_ids: Vec<String>,
+        trip_nodes: Vec<(usize, usize)>,
+        footpaths: HashMap<String, Vec<Footpath>>,
+        pathways: HashMap<String, Pathway>,
+        levels: HashMap<String, Level>,
+        proximity_index: ProximityIndex,
+        shapes: HashMap<String, Vec<ShapePoint>>,
         nodes: Vec<Node>,
     ) -> Network {
+        let timezone = agencies
+            .values()
+            .next()
+            .and_then(|agency| agency.agency_timezone.parse::<Tz>().ok())
+            .unwrap_or(Tz::UTC);
+        let mut walking_sources: HashMap<String, Vec<String>> = HashMap::new();
+        for (stop_id, stop_footpaths) in &footpaths {
+            for footpath in stop_footpaths {
+                let sources = walking_sources
+                    .entry(footpath.target_stop_id.clone())
+                    .or_insert_with(Vec::new);
+                if !sources.contains(stop_id) {
+                    sources.push(stop_id.clone());
+                }
+            }
+        }
         Network {
-            stops: stops,
-            routes: routes,
-            trips: trips,
-            services: services,
-            nodes: nodes,
+            agencies: Arc::new(agencies),
+            timezone: timezone,
+            stops: Arc::new(stops),
+            routes: Arc::new(routes),
+            trips: Arc::new(trips),
+            services: Arc::new(services),
+            trip_ids: Arc::new(trip_ids),
+            trip_nodes: Arc::new(trip_nodes),
+            footpaths: Arc::new(footpaths),
+            walking_sources: Arc::new(walking_sources),
+            pathways: Arc::new(pathways),
+            levels: Arc::new(levels),
+            proximity_index: Arc::new(proximity_index),
+            shapes: Arc::new(shapes),
+            alerts: Arc::new(Vec::new()),
+            fares: Arc::new(Fares::default()),
+            nodes: Arc::new(nodes),
+            realtime_date: None,
+            scheduled: None,
+        }
+    }
+
+    pub fn get_agency(&self, agency_id: &str) -> Option<&Agency> {
+        self.agencies.get(agency_id)
+    }
+
+    pub fn get_timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    /// Returns the POSIX timestamp of the time the times of the given service day are measured
+    /// from, which is noon minus twelve hours in the timezone of the feed
+    pub fn get_service_day_start(&self, date: NaiveDate) -> i64 {
+        let noon = self
+            .timezone
+            .from_local_datetime(&date.and_hms_opt(12, 0, 0).unwrap())
+            .earliest()
+            .unwrap();
+        noon.timestamp() - 12 * 3600
+    }
+
+    pub fn get_alerts(&self) -> &Vec<ServiceAlert> {
+        &self.alerts
+    }
+
+    /// Replaces the service alerts, they apply to all the days
+    pub fn set_alerts(&mut self, alerts: Vec<ServiceAlert>) {
+        self.alerts = Arc::new(alerts);
+        if let Some(scheduled) = &mut self.scheduled {
+            Arc::make_mut(scheduled).alerts = self.alerts.clone();
+        }
+    }
+
+    pub fn set_fares(&mut self, fares: Fares) {
+        self.fares = Arc::new(fares);
+    }
+
+    pub fn get_stop(&self, stop_id: &str) -> Option<&Stop> {
+        self.stops.get(stop_id)
+    }
+
+    pub fn get_route(&self, route_id: &str) -> Option<&Route> {
+        self.routes.get(route_id)
+    }
+
+    pub fn get_trip(&self, trip_id: &str) -> Option<&Trip> {
+        self.trips.get(trip_id)
+    }
+
+    pub(crate) fn get_trip_mut(&mut self, trip_id: &str) -> Option<&mut Trip> {
+        Arc::make_mut(&mut self.trips).get_mut(trip_id)
+    }
+
+    /// Forgets the predictions and cancellations made by realtime updates, the data applied next
+    /// are for the given service day. The scheduled state is kept for the searches on the other
+    /// days.
+    pub(crate) fn reset_realtime_data(&mut self, date: NaiveDate) {
+        match &self.scheduled {
+            Some(scheduled) => {
+                self.stops = scheduled.stops.clone();
+                self.trips = scheduled.trips.clone();
+                self.trip_ids = scheduled.trip_ids.clone();
+                self.trip_nodes = scheduled.trip_nodes.clone();
+                self.nodes = scheduled.nodes.clone();
+            }
+            None => self.scheduled = Some(Arc::new(self.clone())),
+        }
+        self.realtime_date = Some(date);
+    }
+
+    /// Returns the network without the realtime data for the searches on another day than the
+    /// service day of the realtime data, None when this network is to be searched
+    fn get_scheduled_network(&self, date: NaiveDate) -> Option<&Network> {
+        match &self.scheduled {
+            Some(scheduled) if self.realtime_date != Some(date) => Some(scheduled),
+            _ => None,
+        }
+    }
+
+    /// Creates the nodes again from the current state of the trips, keeping the pedestrian
+    /// connections
+    pub fn rebuild_graph(&mut self) {
+        let stops = Arc::make_mut(&mut self.stops);
+        for stop in stops.values_mut() {
+            stop.reset();
+        }
+        let (nodes, trip_ids, trip_nodes) =
+            loader::build_graph(stops, &self.trips, &self.footpaths);
+        self.nodes = Arc::new(nodes);
+        self.trip_ids = Arc::new(trip_ids);
+        self.trip_nodes = Arc::new(trip_nodes);
+    }
+
+    /// Replaces the edges of an arrival node leading to the departures of the given stop with an
+    /// edge to the earliest departure reachable there
+    fn relink_transfer(&mut self, arr_node: usize, target_stop_id: &str, transfer_times: &[u32]) {
+        let old_edges: Vec<usize> = self.nodes[arr_node]
+            .get_edges()
+            .iter()
+            .cloned()
+            .filter(|&e| self.get_node_stop_time(e).stop_id == target_stop_id)
+            .collect();
+        let time = self.nodes[arr_node].get_time();
+        let deps: Vec<usize> = transfer_times
+            .iter()
+            .filter_map(|transfer_time| {
+                self.stops[target_stop_id]
+                    .get_earliest_dep(time + transfer_time, &self.nodes)
+                    .unwrap()
+            })
+            .collect();
+        let node = &mut Arc::make_mut(&mut self.nodes)[arr_node];
+        node.edges.retain(|e| !old_edges.contains(e));
+        for dep in deps {
+            if !node.get_edges().contains(&dep) {
+                node.add_edge(dep);
+            }
+        }
+    }
+
+    /// Shifts the nodes of a trip to the current times of its stop times, without rebuilding the
+    /// whole graph. The moved departures are put to their new places in the departure chains of
+    /// their stops and the transfer edges that could have been affected are linked again. The
+    /// edges for getting on and off at stops skipped by realtime updates are removed.
+    pub fn update_trip_nodes(&mut self, trip_id: &str) {
+        let trip_index = match self
+            .trip_ids
+            .binary_search_by(|id| id.as_str().cmp(trip_id))
+        {
+            Ok(trip_index) => trip_index,
+            Err(_) => return,
+        };
+        let (first, end) = self.trip_nodes[trip_index];
+        let trip = &self.trips[trip_id];
+        let nodes = Arc::make_mut(&mut self.nodes);
+        let mut transport_node = first;
+        // departure nodes with their stops and old times
+        let mut moved_departures = Vec::new();
+        let mut arrivals = Vec::new();
+        for node_id in first..end {
+            let stop_time = &trip.stop_times[nodes[node_id].stop_index];
+            let node = &mut nodes[node_id];
+            let old_time = node.time;
+            match node.kind {
+                NodeKind::Transport => {
+                    node.time = stop_time.get_arrival_time();
+                    transport_node = node_id;
+                }
+                NodeKind::Arrival => {
+                    node.time = stop_time.get_arrival_time() + MINIMAL_TRANSFER_TIME;
+                    nodes[transport_node].set_edge(node_id, !stop_time.skipped);
+                    arrivals.push((node_id, stop_time.stop_id.clone()));
+                }
+                NodeKind::Departure => {
+                    node.time = stop_time.get_departure_time();
+                    node.set_edge(transport_node, !stop_time.skipped);
+                    if node.time != old_time {
+                        moved_departures.push((node_id, stop_time.stop_id.clone(), old_time));
+                    }
+                }
+            }
+        }
+
+        for (dep_node, stop_id, _) in &moved_departures {
+            Arc::make_mut(&mut self.stops)
+                .get_mut(stop_id)
+                .unwrap()
+                .reposition_dep_node(*dep_node, Arc::make_mut(&mut self.nodes));
+        }
+        for (dep_node, stop_id, old_time) in &moved_departures {
+            let new_time = self.nodes[*dep_node].get_time();
+            // arrivals that reach the stop before the previous departure keep their edges
+            let lower_bound = self.stops[stop_id].get_latest_dep_time_before(
+                *old_time.min(&new_time),
+                *dep_node,
+                &self.nodes,
+            );
+            let upper_bound = *old_time.max(&new_time);
+            let mut sources = vec![(stop_id.clone(), vec![0])];
+            for source_stop_id in self.get_walking_sources(stop_id) {
+                let transfer_times = self.get_transfer_times(&source_stop_id, stop_id);
+                sources.push((source_stop_id, transfer_times));
+            }
+            for (source_stop_id, transfer_times) in sources {
+                let affected: Vec<usize> = self.stops[&source_stop_id]
+                    .get_arr_nodes()
+                    .iter()
+                    .cloned()
+                    .filter(|&arr_node| {
+                        transfer_times.iter().any(|transfer_time| {
+                            let time = self.nodes[arr_node].get_time() + transfer_time;
+                            time <= upper_bound && lower_bound.map_or(true, |lower| time > lower)
+                        })
+                    })
+                    .collect();
+                for arr_node in affected {
+                    self.relink_transfer(arr_node, stop_id, &transfer_times);
+                }
+            }
+        }
+        for (arr_node, stop_id) in arrivals {
+            self.relink_transfer(arr_node, &stop_id, &[0]);
+            for target_stop_id in self.get_walking_targets(&stop_id) {
+                let transfer_times = self.get_transfer_times(&stop_id, &target_stop_id);
+                self.relink_transfer(arr_node, &target_stop_id, &transfer_times);
+            }
+        }
+    }
+
+    /// The stops with footpaths leading to them from a stop, each listed once
+    fn get_walking_targets(&self, stop_id: &str) -> Vec<String> {
+        let mut targets: Vec<String> = self
+            .get_footpaths(stop_id)
+            .iter()
+            .map(|footpath| footpath.target_stop_id.clone())
+            .collect();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    /// The stops with footpaths leading from them to a stop. The footpaths of one-way pathways
+    /// and of the walks measured along the streets may lead in one direction only.
+    fn get_walking_sources(&self, stop_id: &str) -> Vec<String> {
+        self.walking_sources
+            .get(stop_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The durations of the footpaths between two stops
+    fn get_transfer_times(&self, from_stop_id: &str, to_stop_id: &str) -> Vec<u32> {
+        self.get_footpaths(from_stop_id)
+            .iter()
+            .filter(|footpath| footpath.target_stop_id == to_stop_id)
+            .map(|footpath| footpath.duration)
+            .collect()
+    }
+
+    pub fn get_node(&self, node_id: usize) -> &Node {
+        &self.nodes[node_id]
+    }
+
+    /// Returns the trip a node was created for
+    pub fn get_node_trip(&self, node_id: usize) -> &Trip {
+        &self.trips[&self.trip_ids[self.nodes[node_id].trip_index]]
+    }
+
+    /// Returns the stop time a node was created for
+    pub fn get_node_stop_time(&self, node_id: usize) -> &StopTime {
+        &self.get_node_trip(node_id).stop_times[self.nodes[node_id].stop_index]
+    }
+
+    pub fn get_footpaths(&self, stop_id: &str) -> &[Footpath] {
+        match self.footpaths.get(stop_id) {
+            Some(footpaths) => footpaths,
+            None => &[],
+        }
+    }
+
+    /// Returns the fastest footpath between two stops, or the fastest step-free one when the
+    /// options require wheelchair accessibility
+    fn get_footpath(
+        &self,
+        from_stop_id: &str,
+        to_stop_id: &str,
+        options: &SearchOptions,
+    ) -> Option<&Footpath> {
+        self.get_footpaths(from_stop_id)
+            .iter()
+            .filter(|footpath| {
+                footpath.target_stop_id == to_stop_id && (!options.wheelchair || footpath.step_free)
+            })
+            .min_by_key(|footpath| footpath.duration)
+    }
+
+    /// Distance in meters of the walk between two stops, zero for the same stop
+    fn get_walking_distance(
+        &self,
+        from_stop_id: &str,
+        to_stop_id: &str,
+        options: &SearchOptions,
+    ) -> f32 {
+        self.get_footpath(from_stop_id, to_stop_id, options)
+            .map_or(0.0, |footpath| footpath.distance)
+    }
+
+    /// Marks the trips whose service runs on the given date, that are not cancelled and whose
+    /// routes are allowed by the options
+    fn get_active_trips(&self, date: NaiveDate, options: &SearchOptions) -> Vec<bool> {
+        self.trip_ids
+            .iter()
+            .map(|trip_id| {
+                let trip = &self.trips[trip_id];
+                !trip.cancelled
+                    && self
+                        .services
+                        .get(&trip.service_id)
+                        .map_or(false, |service| service.runs_on(date))
+                    && self
+                        .routes
+                        .get(&trip.route_id)
+                        .map_or(true, |route| options.allows_route(route))
+            })
+            .collect()
+    }
+
+    fn create_search_context<'a>(
+        &'a self,
+        date: NaiveDate,
+        options: &'a SearchOptions,
+    ) -> SearchContext<'a> {
+        let active_trips = self.get_active_trips(date, options);
+        let has_headway_trips = self
+            .trip_ids
+            .iter()
+            .zip(active_trips.iter())
+            .any(|(trip_id, &active)| active && self.trips[trip_id].headway.is_some());
+        SearchContext {
+            options: options,
+            active_trips: active_trips,
+            has_headway_trips: has_headway_trips,
+            service_day_start: self.get_service_day_start(date),
+            closures: self
+                .alerts
+                .iter()
+                .filter(|alert| alert.no_service)
+                .collect(),
+            avoided_stops: options
+                .avoided_stops
+                .iter()
+                .flat_map(|stop_id| self.get_station_stops(stop_id))
+                .map(|stop| stop.stop_id.as_str())
+                .collect(),
+            via: options
+                .via
+                .iter()
+                .map(|via| {
+                    let platforms = self
+                        .get_station_stops(&via.stop_id)
+                        .into_iter()
+                        .map(|stop| stop.stop_id.as_str())
+                        .collect();
+                    (platforms, via.min_dwell_time)
+                })
+                .collect(),
+        }
+    }
+
+    /// Decides whether an alert closes the stop of the node at the time of the node
+    fn is_closed(&self, node_id: usize, context: &SearchContext) -> bool {
+        if context.closures.is_empty() {
+            return false;
+        }
+        let trip = self.get_node_trip(node_id);
+        let route = self.routes.get(&trip.route_id);
+        let stop_time = self.get_node_stop_time(node_id);
+        let timestamp = context.service_day_start + self.nodes[node_id].get_time() as i64;
+        context.closures.iter().any(|alert| {
+            alert.is_active_at(timestamp)
+                && alert.informed_entities.iter().any(|entity| {
+                    entity.matches(route, Some(&trip.trip_id), Some(&stop_time.stop_id))
+                })
+        })
+    }
+
+    /// Tells whether a stop can be used with a wheelchair, None if unknown. Stops without the
+    /// information inherit it from their parent station.
+    pub fn get_wheelchair_boarding(&self, stop_id: &str) -> Option<bool> {
+        let stop = self.stops.get(stop_id)?;
+        match stop.wheelchair_boarding {
+            Some(1) => Some(true),
+            Some(2) => Some(false),
+            _ => stop
+                .parent_station
+                .as_ref()
+                .and_then(|parent_station| self.stops.get(parent_station))
+                .and_then(|parent_station| match parent_station.wheelchair_boarding {
+                    Some(1) => Some(true),
+                    Some(2) => Some(false),
+                    _ => None,
+                }),
+        }
+    }
+
+    /// Decides whether the search may use the edge between two nodes. Getting on and off
+    /// vehicles and walking between stops is restricted, waiting in the departure chain is
+    /// possible even at departures that cannot be used. Avoided stops cannot be entered at all.
+    fn can_traverse(&self, from: usize, to: usize, context: &SearchContext) -> bool {
+        let options = context.options;
+        // waiting is the only way to stay at an avoided stop, which is never reached
+        let is_waiting = self.nodes[from].kind == NodeKind::Departure
+            && self.nodes[to].kind == NodeKind::Departure;
+        if !is_waiting
+            && !context.avoided_stops.is_empty()
+            && context
+                .avoided_stops
+                .contains(self.get_node_stop_time(to).stop_id.as_str())
+        {
+            return false;
         }
+        match (self.nodes[from].kind, self.nodes[to].kind) {
+            (NodeKind::Departure, NodeKind::Transport) => {
+                let trip = self.get_node_trip(to);
+                context.active_trips[self.nodes[to].trip_index]
+                    && !self.is_closed(from, context)
+                    && (!(options.bikes || options.cycling.is_some())
+                        || trip.allows_bikes() == Some(true))
+                    && (!options.wheelchair
+                        || (trip.is_wheelchair_accessible() == Some(true)
+                            && self
+                                .get_wheelchair_boarding(&self.get_node_stop_time(from).stop_id)
+                                == Some(true)))
+            }
+            (NodeKind::Transport, NodeKind::Arrival) => {
+                !self.is_closed(to, context)
+                    && (!options.wheelchair
+                        || self.get_wheelchair_boarding(&self.get_node_stop_time(to).stop_id)
+                            == Some(true))
+            }
+            (NodeKind::Arrival, NodeKind::Departure) => {
+                let to_stop_id = &self.get_node_stop_time(to).stop_id;
+                // cycling replaces the walking transfers, the search adds the cycling ones
+                let is_walk = self.get_node_stop_time(from).stop_id != *to_stop_id;
+                // the stop of the arrival has been checked when getting off
+                !(is_walk && options.cycling.is_some())
+                    && options
+                        .max_walking_leg_distance
+                        .map_or(true, |max_distance| {
+                            !is_walk
+                                || self.get_walking_distance(
+                                    &self.get_node_stop_time(from).stop_id,
+                                    to_stop_id,
+                                    options,
+                                ) <= max_distance
+                        })
+                    && (!options.wheelchair
+                        || (self.get_wheelchair_boarding(to_stop_id) == Some(true)
+                            && (!is_walk || self.is_step_free_walk(from, to))))
+            }
+            _ => true,
+        }
+    }
+
+    /// Checks that the departure of a walking transfer can be reached from the arrival without
+    /// stairs and escalators
+    fn is_step_free_walk(&self, arr_node: usize, dep_node: usize) -> bool {
+        let time = self.nodes[arr_node].get_time();
+        let to_stop_id = &self.get_node_stop_time(dep_node).stop_id;
+        self.get_footpaths(&self.get_node_stop_time(arr_node).stop_id)
+            .iter()
+            .any(|footpath| {
+                footpath.target_stop_id == *to_stop_id
+                    && footpath.step_free
+                    && time + footpath.duration <= self.nodes[dep_node].get_time()
+            })
     }
-    /*
+
+    /// Finds the earliest arriving connection between two stops or stations. Fails with
+    /// NO_JOURNEY_WITHIN_LIMITS if only connections breaking the limits of the options exist.
+    /// # Arguments
+    /// * dep_stop_id - the stop to depart from
+    /// * target_stop_id - the stop to arrive to
+    /// * date - the date of the journey, used to select the trips that run
+    /// * time - the earliest departure time in seconds since midnight
+    /// * options - the restrictions of the search
     pub fn find_connection(
         &self,
         dep_stop_id: &str,
         target_stop_id: &str,
+        date: NaiveDate,
+        time: u32,
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        let from = Place::Stop(String::from(dep_stop_id));
+        let to = Place::Stop(String::from(target_stop_id));
+        self.find_connection_between_places(&from, &to, date, time, options)
+    }
+
+    /// Finds the earliest arriving connection between two places, which are stops, stations or
+    /// positions given by coordinates. The positions are connected to the stops within
+    /// MAX_ACCESS_DISTANCE by walking, and walking the whole way is returned if it is faster.
+    pub fn find_connection_between_places(
+        &self,
+        from: &Place,
+        to: &Place,
+        date: NaiveDate,
         time: u32,
-    ) -> Result<Option<u32>, &str> {
-        let mut dists = vec![-1; self.nodes.len()];
-        let mut came_from: Vec<i32> = vec![-1; self.nodes.len()];
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        if let Some(scheduled) = self.get_scheduled_network(date) {
+            return scheduled.find_connection_between_places(from, to, date, time, options);
+        }
+        let found = self
+            .find_earliest_connection(from, to, date, time, options)?
+            .map(|itinerary| self.add_fares(itinerary));
+        self.check_limits(found, options, |unlimited| {
+            self.find_earliest_connection(from, to, date, time, unlimited)
+        })
+    }
+
+    /// Returns the zone of a stop, inherited from its station if the stop has none
+    pub(crate) fn get_zone_id(&self, stop_id: &str) -> Option<&String> {
+        let stop = self.stops.get(stop_id)?;
+        if stop.merged {
+            return stop.zone_id.as_ref();
+        }
+        stop.zone_id.as_ref().or_else(|| {
+            stop.parent_station
+                .as_ref()
+                .and_then(|parent| self.stops.get(parent))
+                .and_then(|parent| parent.zone_id.as_ref())
+        })
+    }
+
+    /// Returns the fare areas of a stop, the platforms are also in the areas of their station
+    pub(crate) fn get_fare_areas(&self, stop_id: &str) -> Vec<String> {
+        let mut areas = self.fares.products.get_stop_areas(stop_id).to_vec();
+        if let Some(parent) = self
+            .stops
+            .get(stop_id)
+            .filter(|stop| !stop.merged)
+            .and_then(|stop| stop.parent_station.as_ref())
+        {
+            areas.extend_from_slice(self.fares.products.get_stop_areas(parent));
+        }
+        areas
+    }
+
+    /// Describes the rides of an itinerary for the fare calculation
+    fn get_fare_rides(&self, itinerary: &Itinerary) -> Vec<FareRide> {
+        let mut rides = Vec::new();
+        for (leg_index, leg) in itinerary.legs.iter().enumerate() {
+            let trip = match leg
+                .trip_id
+                .as_ref()
+                .and_then(|trip_id| self.trips.get(trip_id))
+            {
+                Some(trip) => trip,
+                None => continue,
+            };
+            // trips visiting a stop twice are told apart by the time
+            let from = trip
+                .stop_times
+                .iter()
+                .position(|stop_time| {
+                    stop_time.stop_id == leg.from_stop_id
+                        && stop_time.get_departure_time() == leg.departure_time
+                })
+                .or_else(|| {
+                    trip.stop_times
+                        .iter()
+                        .position(|stop_time| stop_time.stop_id == leg.from_stop_id)
+                })
+                .unwrap_or(0);
+            let to = trip.stop_times[from..]
+                .iter()
+                .position(|stop_time| stop_time.stop_id == leg.to_stop_id)
+                .map_or(trip.stop_times.len() - 1, |offset| from + offset);
+            let route = self.routes.get(&trip.route_id);
+            rides.push(FareRide {
+                leg_index: leg_index,
+                route_id: trip.route_id.clone(),
+                agency_id: route.and_then(|route| route.agency_id.clone()),
+                network_id: route
+                    .and_then(|route| route.network_id.as_ref())
+                    .or_else(|| self.fares.products.get_route_network(&trip.route_id))
+                    .cloned(),
+                from_areas: self.get_fare_areas(&leg.from_stop_id),
+                to_areas: self.get_fare_areas(&leg.to_stop_id),
+                departure_time: leg.departure_time,
+                arrival_time: leg.arrival_time,
+                zones: trip.stop_times[from..to + 1]
+                    .iter()
+                    .map(|stop_time| self.get_zone_id(&stop_time.stop_id).cloned())
+                    .collect(),
+            });
+        }
+        rides
+    }
+
+    /// Prices an itinerary by the fares of the feed and by the zone tickets
+    fn add_fares(&self, mut itinerary: Itinerary) -> Itinerary {
+        let rides = self.get_fare_rides(&itinerary);
+        itinerary.fare = self.fares.get_fare(&rides);
+        itinerary.zone_fare = self.fares.zone_tickets.get_fare(&rides);
+        itinerary
+    }
+
+    /// Tells apart a missing connection from connections breaking the limits by searching
+    /// again without the limits
+    fn check_limits<'a, F>(
+        &'a self,
+        found: Option<Itinerary>,
+        options: &SearchOptions,
+        search: F,
+    ) -> Result<Option<Itinerary>, &'a str>
+    where
+        F: FnOnce(&SearchOptions) -> Result<Option<Itinerary>, &'a str>,
+    {
+        if found.is_some() || !options.has_limits() {
+            return Ok(found);
+        }
+        let unlimited = SearchOptions {
+            max_transfers: None,
+            max_walking_distance: None,
+            max_walking_leg_distance: None,
+            ..options.clone()
+        };
+        match search(&unlimited)? {
+            Some(_) => Err(NO_JOURNEY_WITHIN_LIMITS),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the earliest arriving connection keeping to the options, None if there is none
+    fn find_earliest_connection(
+        &self,
+        from: &Place,
+        to: &Place,
+        date: NaiveDate,
+        time: u32,
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        let strict = match options.night_lines {
+            NightLines::Prefer => NightLines::Require,
+            NightLines::Avoid => NightLines::Exclude,
+            _ => return self.search_connection(from, to, date, time, options),
+        };
+        // the preference is decided by comparing with the fastest connection
+        let any = SearchOptions {
+            night_lines: NightLines::Any,
+            ..options.clone()
+        };
+        let fastest = self.search_connection(from, to, date, time, &any)?;
+        let strict = SearchOptions {
+            night_lines: strict,
+            ..options.clone()
+        };
+        let preferred = self.search_connection(from, to, date, time, &strict)?;
+        Ok(match (preferred, fastest) {
+            (Some(preferred), Some(fastest))
+                if preferred.arrival_time().unwrap_or(0)
+                    > fastest.arrival_time().unwrap_or(0) + NIGHT_LINE_PREFERENCE_SLACK =>
+            {
+                Some(fastest)
+            }
+            (Some(preferred), _) => Some(preferred),
+            (None, fastest) => fastest,
+        })
+    }
+
+    /// Returns the stops a connection can start or end at together with the walks to them. A
+    /// stop is reached directly, a station at any of its platforms.
+    fn get_accesses(&self, place: &Place, options: &SearchOptions) -> Vec<Access> {
+        match place {
+            Place::Stop(stop_id) => self
+                .get_station_stops(stop_id)
+                .iter()
+                .map(|stop| Access {
+                    stop_id: stop.stop_id.clone(),
+                    distance: 0.0,
+                    duration: 0,
+                })
+                .collect(),
+            Place::Coordinates(point) => {
+                let max_distance = options
+                    .max_walking_leg_distance
+                    .map_or(MAX_ACCESS_DISTANCE, |max| max.min(MAX_ACCESS_DISTANCE));
+                let coords = match loader::wgs84_to_utm(*point) {
+                    Some(coords) => coords,
+                    None => return Vec::new(),
+                };
+                self.proximity_index
+                    .get_stops_near(coords, max_distance)
+                    .into_iter()
+                    .filter(|(stop_id, _)| self.stops[stop_id].location_type == 0)
+                    .map(|(stop_id, distance)| Access::walk(stop_id, distance))
+                    .collect()
+            }
+        }
+    }
+
+    /// Returns the position of a place in UTM coordinates
+    fn get_utm_coords(&self, place: &Place) -> Option<Point<f32>> {
+        match place {
+            Place::Stop(stop_id) => self.proximity_index.get_coords(stop_id),
+            Place::Coordinates(point) => loader::wgs84_to_utm(*point),
+        }
+    }
+
+    /// Finds the earliest arriving connection between two places through the via stops, the night
+    /// line preferences are not taken into account
+    fn search_connection(
+        &self,
+        from: &Place,
+        to: &Place,
+        date: NaiveDate,
+        time: u32,
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        if let Place::Stop(stop_id) = from {
+            if !self.stops.contains_key(stop_id) {
+                return Err("Stop not found.");
+            }
+        }
+        if let Place::Stop(stop_id) = to {
+            if !self.stops.contains_key(stop_id) {
+                return Err("Target stop not found.");
+            }
+        }
+        if options
+            .via
+            .iter()
+            .any(|via| !self.stops.contains_key(&via.stop_id))
+        {
+            return Err("Via stop not found.");
+        }
+        let origins = self.get_accesses(from, options);
+        let destinations = self.get_accesses(to, options);
+        let context = self.create_search_context(date, options);
+        let found = match self.search(&origins, &destinations, time, &context)? {
+            Some(path) => {
+                let mut legs = self.build_itinerary(&path.nodes, &context).legs;
+                let departure_time = legs.first().map_or(time, |leg| leg.departure_time);
+                let arrival_time = legs.last().map_or(time, |leg| leg.arrival_time);
+                let (origin, destination) =
+                    (&origins[path.origin], &destinations[path.destination]);
+                if let Place::Coordinates(_) = from {
+                    let stop = Place::Stop(origin.stop_id.clone());
+                    let start = departure_time.saturating_sub(origin.duration);
+                    legs.insert(0, self.build_place_walk_leg(from, &stop, start, origin));
+                }
+                if let Place::Coordinates(_) = to {
+                    let stop = Place::Stop(destination.stop_id.clone());
+                    legs.push(self.build_place_walk_leg(&stop, to, arrival_time, destination));
+                }
+                Some(Itinerary::new(legs))
+            }
+            None => None,
+        };
+        if let (Place::Stop(_), Place::Stop(_)) = (from, to) {
+            return Ok(found);
+        }
+        if !options.via.is_empty() {
+            return Ok(found);
+        }
+        // walking the whole way may be faster, e.g. for places close to each other
+        let direct = match (self.get_utm_coords(from), self.get_utm_coords(to)) {
+            (Some(from_coords), Some(to_coords)) => Access::walk(
+                String::new(),
+                (from_coords.x() - to_coords.x()).abs() + (from_coords.y() - to_coords.y()).abs(),
+            ),
+            _ => return Ok(found),
+        };
+        let within_limits = direct.distance <= MAX_DIRECT_WALKING_DISTANCE
+            && options
+                .max_walking_distance
+                .map_or(true, |max| direct.distance <= max)
+            && options
+                .max_walking_leg_distance
+                .map_or(true, |max| direct.distance <= max);
+        let is_faster = found.as_ref().map_or(true, |found| {
+            time + direct.duration < found.arrival_time().unwrap_or(0)
+        });
+        if within_limits && is_faster {
+            let leg = self.build_place_walk_leg(from, to, time, &direct);
+            return Ok(Some(Itinerary::new(vec![leg])));
+        }
+        Ok(found)
+    }
+
+    /// Runs the search in the graph from the origin stops to the destination stops. The walks
+    /// to the origins delay the start, the walks from the destinations the arrival. The via stops
+    /// split the search into stages, a stage ends by getting off at its via stop and the next one
+    /// continues from there after the dwell time, so the limits hold for the whole journey.
+    /// Returns the path of nodes with the times they are reached at and the origin and the
+    /// destination used.
+    fn search(
+        &self,
+        origins: &[Access],
+        destinations: &[Access],
+        time: u32,
+        context: &SearchContext,
+    ) -> Result<Option<SearchPath>, &str> {
+        let options = context.options;
+        let destination_indices: HashMap<&str, usize> = destinations
+            .iter()
+            .enumerate()
+            .map(|(index, destination)| (destination.stop_id.as_str(), index))
+            .collect();
+        // the resources are counted only when limited, so that only the time decides otherwise
+        let max_rides = options.max_transfers.map(|max_transfers| max_transfers + 1);
+        let max_walking = options.max_walking_distance;
+        let mut labels: Vec<Label> = Vec::new();
+        // the labels of each node and stage not dominated by other ones
+        let node_count = self.nodes.len();
+        let mut bags: Vec<Vec<usize>> = vec![Vec::new(); node_count * (context.via.len() + 1)];
+        // the stops reachable by cycling from the stops arrived at
+        let mut cycling_cache: HashMap<String, Vec<(String, f32)>> = HashMap::new();
         let mut heap = BinaryHeap::new();
-        let start = self
+        // the label arriving at a destination, the destination and the arrival after the walk
+        let mut best: Option<(usize, usize, u32)> = None;
+        for (origin_index, origin) in origins.iter().enumerate() {
+            let start_time = time + origin.duration;
+            if let Some(start) =
+                self.stops[&origin.stop_id].get_earliest_dep(start_time, &self.nodes)?
+            {
+                let time = self.nodes[start].get_time();
+                let label = Label {
+                    node: start,
+                    time: time,
+                    ready: if context.has_headway_trips {
+                        start_time
+                    } else {
+                        time
+                    },
+                    rides: 0,
+                    walking: if max_walking.is_some() {
+                        origin.distance
+                    } else {
+                        0.0
+                    },
+                    parent: None,
+                    origin: origin_index,
+                    stage: 0,
+                    dominated: false,
+                };
+                if let Some(index) = add_label(&mut labels, &mut bags, node_count, label) {
+                    heap.push(Reverse((labels[index].time, start, index)));
+                }
+            }
+        }
+
+        while let Some(Reverse((time, popped, index))) = heap.pop() {
+            if best.map_or(false, |(_, _, arrival_time)| time >= arrival_time) {
+                break;
+            }
+            if labels[index].dominated {
+                continue;
+            }
+            let node = &self.nodes[popped];
+            let stage = labels[index].stage;
+            if node.kind == NodeKind::Arrival && stage < context.via.len() {
+                // getting off at the via stop, the journey continues from it after the dwell time
+                let (via_stops, min_dwell_time) = &context.via[stage];
+                let stop_id = self.get_node_stop_time(popped).stop_id.as_str();
+                let ready = time + min_dwell_time;
+                if via_stops.contains(stop_id) {
+                    if let Some(dep) = self.stops[stop_id].get_earliest_dep(ready, &self.nodes)? {
+                        let dep_time = self.nodes[dep].get_time();
+                        let label = Label {
+                            node: dep,
+                            time: dep_time,
+                            ready: if context.has_headway_trips {
+                                ready
+                            } else {
+                                dep_time
+                            },
+                            stage: stage + 1,
+                            parent: Some(index),
+                            dominated: false,
+                            ..labels[index]
+                        };
+                        if let Some(added) = add_label(&mut labels, &mut bags, node_count, label) {
+                            heap.push(Reverse((dep_time, dep, added)));
+                        }
+                    }
+                }
+            }
+            if node.kind == NodeKind::Arrival && stage == context.via.len() {
+                let stop_id = self.get_node_stop_time(popped).stop_id.as_str();
+                if let Some(&destination_index) = destination_indices.get(stop_id) {
+                    let destination = &destinations[destination_index];
+                    let arrival_time = time + destination.duration;
+                    let walking = labels[index].walking
+                        + if max_walking.is_some() {
+                            destination.distance
+                        } else {
+                            0.0
+                        };
+                    if max_walking.map_or(true, |max_walking| walking <= max_walking)
+                        && best.map_or(true, |(_, _, best_time)| arrival_time < best_time)
+                    {
+                        best = Some((index, destination_index, arrival_time));
+                    }
+                }
+            }
+            // the edges of the graph, and the departures reached by cycling which are not edges
+            // together with the time the cyclist is ready there
+            let mut next_nodes: Vec<(usize, Option<u32>)> =
+                node.get_edges().iter().map(|&next| (next, None)).collect();
+            if let (NodeKind::Arrival, Some(cycling)) = (node.kind, &options.cycling) {
+                let stop_id = &self.get_node_stop_time(popped).stop_id;
+                let cycling_paths = cycling_cache.entry(stop_id.clone()).or_insert_with(|| {
+                    self.proximity_index
+                        .get_stops_within(stop_id, cycling.max_distance)
+                });
+                for (near_id, distance) in cycling_paths.iter() {
+                    if context.avoided_stops.contains(near_id.as_str()) {
+                        continue;
+                    }
+                    let time = time + cycling.get_duration(*distance);
+                    if let Some(dep) = self.stops[near_id].get_earliest_dep(time, &self.nodes)? {
+                        next_nodes.push((dep, Some(time)));
+                    }
+                }
+            }
+            for (next, cycled) in next_nodes {
+                let is_cycling = cycled.is_some();
+                if !(is_cycling || self.can_traverse(popped, next, context)) {
+                    continue;
+                }
+                let next_node = &self.nodes[next];
+                let next_time = match (node.kind, next_node.kind) {
+                    (NodeKind::Departure, NodeKind::Transport) => {
+                        // the vehicle has left before a passenger late behind the timetable came
+                        if next_node.get_time() < time {
+                            continue;
+                        }
+                        // the runs of trips without exact times only mark the headway, the
+                        // passenger is expected to wait for half of it and to stay that late
+                        // behind the timetable of the run
+                        match self.get_node_trip(next).headway {
+                            Some(headway) => {
+                                next_node.get_time().max(labels[index].ready + headway / 2)
+                            }
+                            None => next_node.get_time(),
+                        }
+                    }
+                    (NodeKind::Transport, _) => {
+                        time + next_node.get_time().saturating_sub(node.get_time())
+                    }
+                    _ => time.max(next_node.get_time()),
+                };
+                let ready = if !context.has_headway_trips {
+                    next_time
+                } else {
+                    match (node.kind, self.nodes[next].kind) {
+                        (NodeKind::Departure, NodeKind::Departure) => labels[index].ready,
+                        (NodeKind::Arrival, NodeKind::Departure) => cycled.unwrap_or_else(|| {
+                            time + self
+                                .get_footpath(
+                                    &self.get_node_stop_time(popped).stop_id,
+                                    &self.get_node_stop_time(next).stop_id,
+                                    options,
+                                )
+                                .map_or(0, |footpath| footpath.duration)
+                        }),
+                        _ => next_time,
+                    }
+                };
+                let (mut rides, mut walking) = (labels[index].rides, labels[index].walking);
+                match (node.kind, self.nodes[next].kind) {
+                    (NodeKind::Departure, NodeKind::Transport) if max_rides.is_some() => {
+                        rides += 1;
+                    }
+                    (NodeKind::Arrival, NodeKind::Departure)
+                        if max_walking.is_some() && !is_cycling =>
+                    {
+                        walking += self.get_walking_distance(
+                            &self.get_node_stop_time(popped).stop_id,
+                            &self.get_node_stop_time(next).stop_id,
+                            context.options,
+                        );
+                    }
+                    _ => {}
+                }
+                if max_rides.map_or(false, |max_rides| rides > max_rides)
+                    || max_walking.map_or(false, |max_walking| walking > max_walking)
+                {
+                    continue;
+                }
+                let label = Label {
+                    node: next,
+                    // boarding a vehicle happens at its departure, not at its arrival
+                    time: next_time,
+                    ready: ready,
+                    rides: rides,
+                    walking: walking,
+                    parent: Some(index),
+                    origin: labels[index].origin,
+                    stage: stage,
+                    dominated: false,
+                };
+                if let Some(added) = add_label(&mut labels, &mut bags, node_count, label) {
+                    heap.push(Reverse((labels[added].time, next, added)));
+                }
+            }
+        }
+        Ok(best.map(|(index, destination, _)| {
+            let mut path = vec![(labels[index].node, labels[index].time)];
+            let mut parent = labels[index].parent;
+            while let Some(prev) = parent {
+                path.push((labels[prev].node, labels[prev].time));
+                parent = labels[prev].parent;
+            }
+            path.reverse();
+            SearchPath {
+                nodes: path,
+                origin: labels[index].origin,
+                destination: destination,
+            }
+        }))
+    }
+
+    /// Finds the latest departing connection between two stops that arrives in time. As the
+    /// arrival of the earliest arriving connection does not decrease with the departure time,
+    /// the latest suitable departure from the stop is found by a binary search.
+    /// # Arguments
+    /// * dep_stop_id - the stop to depart from
+    /// * target_stop_id - the stop to arrive to
+    /// * date - the date of the journey, used to select the trips that run
+    /// * time - the latest arrival time in seconds since midnight
+    /// * options - the restrictions of the search
+    pub fn find_connection_arriving_by(
+        &self,
+        dep_stop_id: &str,
+        target_stop_id: &str,
+        date: NaiveDate,
+        time: u32,
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        let from = Place::Stop(String::from(dep_stop_id));
+        let to = Place::Stop(String::from(target_stop_id));
+        self.find_connection_between_places_arriving_by(&from, &to, date, time, options)
+    }
+
+    /// Finds the latest departing connection between two places that arrives in time
+    pub fn find_connection_between_places_arriving_by(
+        &self,
+        from: &Place,
+        to: &Place,
+        date: NaiveDate,
+        time: u32,
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        if let Some(scheduled) = self.get_scheduled_network(date) {
+            return scheduled
+                .find_connection_between_places_arriving_by(from, to, date, time, options);
+        }
+        let found = self
+            .find_latest_connection(from, to, date, time, options)?
+            .map(|itinerary| self.add_fares(itinerary));
+        self.check_limits(found, options, |unlimited| {
+            self.find_latest_connection(from, to, date, time, unlimited)
+        })
+    }
+
+    fn find_latest_connection(
+        &self,
+        from: &Place,
+        to: &Place,
+        date: NaiveDate,
+        time: u32,
+        options: &SearchOptions,
+    ) -> Result<Option<Itinerary>, &str> {
+        if let Place::Stop(stop_id) = from {
+            if !self.stops.contains_key(stop_id) {
+                return Err("Stop not found.");
+            }
+        }
+        // leaving the place in time for the departures from the origin stops
+        let mut departure_times: Vec<u32> = self
+            .get_accesses(from, options)
+            .iter()
+            .flat_map(|origin| {
+                self.stops[&origin.stop_id]
+                    .get_dep_nodes()
+                    .iter()
+                    .map(move |&dep| self.nodes[dep].get_time().saturating_sub(origin.duration))
+            })
+            .filter(|&dep_time| dep_time <= time)
+            .collect();
+        departure_times.sort_unstable();
+        departure_times.dedup();
+        let mut best = None;
+        let (mut low, mut high) = (0, departure_times.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.find_earliest_connection(from, to, date, departure_times[middle], options)? {
+                Some(itinerary) if itinerary.arrival_time().map_or(false, |arr| arr <= time) => {
+                    best = Some(itinerary);
+                    low = middle + 1;
+                }
+                _ => high = middle,
+            }
+        }
+        Ok(best)
+    }
+
+    /// Finds the stops whose names contain the query, ignoring case, sorted by their names
+    pub fn find_stops(&self, query: &str) -> Vec<&Stop> {
+        let query = query.to_lowercase();
+        let mut stops: Vec<&Stop> = self
             .stops
-            .get(dep_stop_id)
-            .ok_or("Stop not found.")?
-            .get_earliest_dep(time, &self.nodes)?
-            .ok_or("There is no departure from the stop after the selected time")?;
-        dists[start] = time as i32;
-        heap.push(start);
+            .values()
+            .filter(|stop| stop.stop_name.to_lowercase().contains(&query))
+            .collect();
+        stops.sort_by(|a, b| (&a.stop_name, &a.stop_id).cmp(&(&b.stop_name, &b.stop_id)));
+        stops
+    }
+
+    /// Returns the stops served by vehicles at the given stop, which are the stop itself, or the
+    /// platforms of a station (location_type 1)
+    pub fn get_station_stops(&self, stop_id: &str) -> Vec<&Stop> {
+        match self.stops.get(stop_id) {
+            Some(stop) if stop.location_type == 1 => self
+                .stops
+                .values()
+                .filter(|child| child.parent_station.as_deref() == Some(stop_id))
+                .collect(),
+            Some(stop) => vec![stop],
+            None => Vec::new(),
+        }
+    }
+
+    /// Lists the next departures from a stop or from all the platforms of a station, sorted by
+    /// the predicted times. Trips ending at the stop are left out.
+    /// # Arguments
+    /// * stop_id - the stop or the station
+    /// * date - the service day, used to select the trips that run
+    /// * from_time - the earliest departure time in seconds since midnight
+    /// * limit - the maximal number of departures returned
+    /// * options - the restrictions of the search, the transfer settings are not used
+    pub fn departures(
+        &self,
+        stop_id: &str,
+        date: NaiveDate,
+        from_time: u32,
+        limit: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<Departure>, &str> {
+        if let Some(scheduled) = self.get_scheduled_network(date) {
+            return scheduled.departures(stop_id, date, from_time, limit, options);
+        }
+        if !self.stops.contains_key(stop_id) {
+            return Err("Stop not found.");
+        }
+        let context = self.create_search_context(date, options);
+        let mut departures = Vec::new();
+        for stop in self.get_station_stops(stop_id) {
+            let usable = stop
+                .get_dep_nodes()
+                .iter()
+                .cloned()
+                .filter(|&dep| self.nodes[dep].get_time() >= from_time)
+                .filter(|&dep| {
+                    self.nodes[dep].stop_index + 1 < self.get_node_trip(dep).stop_times.len()
+                })
+                .filter(|&dep| {
+                    // only departures the search could use, the boarding edge is missing at
+                    // skipped stops
+                    self.nodes[dep].get_edges().iter().any(|&next| {
+                        self.nodes[next].kind == NodeKind::Transport
+                            && self.can_traverse(dep, next, &context)
+                    })
+                })
+                .take(limit);
+            departures.extend(usable.map(|dep| self.build_departure(dep, stop)));
+        }
+        departures.sort_by_key(|departure| departure.departure_time);
+        departures.truncate(limit);
+        Ok(departures)
+    }
+
+    fn build_departure(&self, dep_node: usize, stop: &Stop) -> Departure {
+        let trip = self.get_node_trip(dep_node);
+        let stop_time = self.get_node_stop_time(dep_node);
+        let route_short_name = self
+            .routes
+            .get(&trip.route_id)
+            .map_or(String::new(), |route| route.route_short_name.clone());
+        Departure {
+            stop_id: stop.stop_id.clone(),
+            trip_id: trip.trip_id.clone(),
+            route_id: trip.route_id.clone(),
+            route_short_name: route_short_name,
+            headsign: stop_time
+                .stop_headsign
+                .clone()
+                .or_else(|| trip.trip_headsign.clone()),
+            platform_code: stop.platform_code.clone(),
+            scheduled_departure_time: stop_time.departure_time,
+            departure_time: stop_time.get_departure_time(),
+            departure_delay: stop_time.get_departure_delay(),
+        }
+    }
 
-        while let Some(popped) = heap.pop() {
-            println!("POPPED! {:?}", self.nodes[popped]);
-            let node_struct = &self.nodes[popped];
-            if node_struct.stop_id.as_str() == target_stop_id {
-                let mut index = popped;
-                while came_from[index] != -1 {
-                    println!("{:?}", self.nodes[index]);
-                    index = came_from[index] as usize;
+    /// Lists the next arrivals to a stop or to all the platforms of a station, sorted by the
+    /// predicted times. Trips starting at the stop are left out.
+    /// # Arguments
+    /// * stop_id - the stop or the station
+    /// * date - the service day, used to select the trips that run
+    /// * from_time - the earliest arrival time in seconds since midnight
+    /// * limit - the maximal number of arrivals returned
+    /// * options - the restrictions of the search, the transfer settings are not used
+    pub fn arrivals(
+        &self,
+        stop_id: &str,
+        date: NaiveDate,
+        from_time: u32,
+        limit: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<Arrival>, &str> {
+        if let Some(scheduled) = self.get_scheduled_network(date) {
+            return scheduled.arrivals(stop_id, date, from_time, limit, options);
+        }
+        if !self.stops.contains_key(stop_id) {
+            return Err("Stop not found.");
+        }
+        let context = self.create_search_context(date, options);
+        let mut arrivals = Vec::new();
+        for stop in self.get_station_stops(stop_id) {
+            // the arrival nodes are not sorted
+            let usable = stop.get_arr_nodes().iter().cloned().filter(|&arr| {
+                let stop_time = self.get_node_stop_time(arr);
+                stop_time.get_arrival_time() >= from_time
+                    && self.nodes[arr].stop_index > 0
+                    && !stop_time.skipped
+                    && context.active_trips[self.nodes[arr].trip_index]
+                    && !self.is_closed(arr, &context)
+            });
+            arrivals.extend(usable.map(|arr| self.build_arrival(arr, stop)));
+        }
+        arrivals.sort_by_key(|arrival| arrival.arrival_time);
+        arrivals.truncate(limit);
+        Ok(arrivals)
+    }
+
+    fn build_arrival(&self, arr_node: usize, stop: &Stop) -> Arrival {
+        let trip = self.get_node_trip(arr_node);
+        let stop_time = self.get_node_stop_time(arr_node);
+        let route_short_name = self
+            .routes
+            .get(&trip.route_id)
+            .map_or(String::new(), |route| route.route_short_name.clone());
+        let origin = trip
+            .stop_times
+            .first()
+            .and_then(|first| self.stops.get(&first.stop_id))
+            .map_or(String::new(), |origin| origin.stop_name.clone());
+        Arrival {
+            stop_id: stop.stop_id.clone(),
+            trip_id: trip.trip_id.clone(),
+            route_id: trip.route_id.clone(),
+            route_short_name: route_short_name,
+            origin: origin,
+            platform_code: stop.platform_code.clone(),
+            scheduled_arrival_time: stop_time.arrival_time,
+            arrival_time: stop_time.get_arrival_time(),
+            arrival_delay: stop_time.get_arrival_delay(),
+        }
+    }
+
+    /// Lists all the stops of a trip with the scheduled and the predicted times
+    /// # Arguments
+    /// * trip_id - the trip
+    /// * date - the service day, used to tell whether the trip runs
+    pub fn trip_detail(&self, trip_id: &str, date: NaiveDate) -> Result<TripDetail, &str> {
+        if let Some(scheduled) = self.get_scheduled_network(date) {
+            return scheduled.trip_detail(trip_id, date);
+        }
+        let trip = self.trips.get(trip_id).ok_or("Trip not found.")?;
+        let route_short_name = self
+            .routes
+            .get(&trip.route_id)
+            .map_or(String::new(), |route| route.route_short_name.clone());
+        let runs = !trip.cancelled
+            && self
+                .services
+                .get(&trip.service_id)
+                .map_or(false, |service| service.runs_on(date));
+        let stops = trip
+            .stop_times
+            .iter()
+            .map(|stop_time| {
+                let stop = self.stops.get(&stop_time.stop_id);
+                TripStop {
+                    stop_id: stop_time.stop_id.clone(),
+                    stop_name: stop.map_or(String::new(), |stop| stop.stop_name.clone()),
+                    stop_sequence: stop_time.stop_sequence,
+                    platform_code: stop.and_then(|stop| stop.platform_code.clone()),
+                    scheduled_arrival_time: stop_time.arrival_time,
+                    scheduled_departure_time: stop_time.departure_time,
+                    arrival_time: stop_time.get_arrival_time(),
+                    departure_time: stop_time.get_departure_time(),
+                    arrival_delay: stop_time.get_arrival_delay(),
+                    departure_delay: stop_time.get_departure_delay(),
+                    skipped: stop_time.skipped,
+                }
+            })
+            .collect();
+        Ok(TripDetail {
+            trip_id: trip.trip_id.clone(),
+            route_id: trip.route_id.clone(),
+            route_short_name: route_short_name,
+            headsign: trip.trip_headsign.clone(),
+            runs: runs,
+            cancelled: trip.cancelled,
+            stops: stops,
+        })
+    }
+
+    /// Converts the path of nodes found by the search into legs. The rides of trips without exact
+    /// times are reached later than the timetable of their runs, the legs keep the expected times.
+    fn build_itinerary(&self, path: &[(usize, u32)], context: &SearchContext) -> Itinerary {
+        let mut legs = Vec::new();
+        let mut boarded_at = None;
+        for pair in path.windows(2) {
+            let ((from, from_time), (to, to_time)) = (pair[0], pair[1]);
+            match (self.nodes[from].kind, self.nodes[to].kind) {
+                (NodeKind::Departure, NodeKind::Transport) => boarded_at = Some(from),
+                (NodeKind::Transport, NodeKind::Arrival) => {
+                    let delay = to_time - self.nodes[to].get_time();
+                    legs.push(self.build_ride_leg(boarded_at.unwrap(), to, delay, context));
                 }
-                return Ok(Some(node_struct.get_time() - time));
+                (NodeKind::Arrival, NodeKind::Departure) => {
+                    let arrived = self.get_node_stop_time(from);
+                    let departing = self.get_node_stop_time(to);
+                    if arrived.stop_id != departing.stop_id {
+                        legs.push(self.build_transfer_leg(
+                            &arrived.stop_id,
+                            &departing.stop_id,
+                            from_time,
+                            context,
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Itinerary::new(legs)
+    }
+
+    /// Finds the index of the shape point closest to the stop, starting the search at `from`
+    fn get_nearest_shape_point(&self, shape: &[ShapePoint], stop_id: &str, from: usize) -> usize {
+        let coords = match self.stops[stop_id].get_coords() {
+            Some(coords) => coords,
+            None => return from,
+        };
+        // degrees of longitude are shorter than degrees of latitude away from the equator
+        let lon_scale = coords.y().to_radians().cos();
+        let mut nearest = from;
+        let mut nearest_dist = f32::MAX;
+        for (index, point) in shape.iter().enumerate().skip(from) {
+            let dx = (point.shape_pt_lon - coords.x()) * lon_scale;
+            let dy = point.shape_pt_lat - coords.y();
+            let dist = dx * dx + dy * dy;
+            if dist < nearest_dist {
+                nearest = index;
+                nearest_dist = dist;
             }
-            for edge in node_struct.get_edges() {
-                if dists[edge.target_node] == -1 || ((node_struct.get_time() + edge.cost()) as i32) < dists[edge.target_node] {
-                    heap.push(edge.target_node); // TODO solve this inefficient bullcrap
-                    dists[edge.target_node] = (node_struct.get_time() + edge.cost()) as i32;
-                    came_from[edge.target_node] = popped as i32;
+        }
+        nearest
+    }
+
+    /// Cuts the part of the trip's shape between two of its stop times. The shape distances are
+    /// used when both the stop times and the shape provide them, otherwise the stops are projected
+    /// to the nearest shape points.
+    pub fn get_ride_geometry(
+        &self,
+        trip: &Trip,
+        from_index: usize,
+        to_index: usize,
+    ) -> Option<LineString<f32>> {
+        let shape = self.shapes.get(trip.shape_id.as_ref()?)?;
+        let boarding = &trip.stop_times[from_index];
+        let alighting = &trip.stop_times[to_index];
+        let has_distances = shape.iter().all(|p| p.shape_dist_traveled.is_some());
+        let points: Vec<&ShapePoint> =
+            match (boarding.shape_dist_traveled, alighting.shape_dist_traveled) {
+                (Some(from), Some(to)) if has_distances => shape
+                    .iter()
+                    .filter(|p| {
+                        let dist = p.shape_dist_traveled.unwrap();
+                        dist >= from && dist <= to
+                    })
+                    .collect(),
+                _ => {
+                    let start = self.get_nearest_shape_point(shape, &boarding.stop_id, 0);
+                    let end = self.get_nearest_shape_point(shape, &alighting.stop_id, start);
+                    shape[start..end + 1].iter().collect()
                 }
+            };
+        Some(LineString::from(
+            points
+                .iter()
+                .map(|p| (p.shape_pt_lon, p.shape_pt_lat))
+                .collect::<Vec<(f32, f32)>>(),
+        ))
+    }
+
+    /// Calculates the distance in meters travelled between two stop times of a trip. The shape
+    /// distances are converted to meters by comparing them to the length of the shape. When they
+    /// are not available, the distances between consecutive stops are summed.
+    pub fn get_ride_distance(&self, trip: &Trip, from_index: usize, to_index: usize) -> f32 {
+        let boarding = &trip.stop_times[from_index];
+        let alighting = &trip.stop_times[to_index];
+        let shape = trip.shape_id.as_ref().and_then(|id| self.shapes.get(id));
+        if let (Some(from), Some(to), Some(shape)) = (
+            boarding.shape_dist_traveled,
+            alighting.shape_dist_traveled,
+            shape,
+        ) {
+            let first = shape.first().and_then(|p| p.shape_dist_traveled);
+            let last = shape.last().and_then(|p| p.shape_dist_traveled);
+            if let (Some(first), Some(last)) = (first, last) {
+                if last > first {
+                    let line = LineString::from(
+                        shape
+                            .iter()
+                            .map(|p| (p.shape_pt_lon, p.shape_pt_lat))
+                            .collect::<Vec<(f32, f32)>>(),
+                    );
+                    return (to - from) * get_length(&line) / (last - first);
+                }
+            }
+        }
+        trip.stop_times[from_index..to_index + 1]
+            .windows(2)
+            .filter_map(|pair| {
+                let from = self.stops[&pair[0].stop_id].get_coords()?;
+                let to = self.stops[&pair[1].stop_id].get_coords()?;
+                Some(get_distance(from, to))
+            })
+            .sum()
+    }
+
+    /// Collects the alerts concerning the given route, trip or stops that are active during the
+    /// given time of the service day
+    fn get_alerts_for(
+        &self,
+        route: Option<&Route>,
+        trip_id: Option<&str>,
+        stop_ids: &[&str],
+        from: u32,
+        to: u32,
+        context: &SearchContext,
+    ) -> Vec<ServiceAlert> {
+        self.alerts
+            .iter()
+            .filter(|alert| {
+                alert.is_active_between(
+                    context.service_day_start + from as i64,
+                    context.service_day_start + to as i64,
+                )
+            })
+            .filter(|alert| {
+                alert.informed_entities.iter().any(|entity| {
+                    stop_ids
+                        .iter()
+                        .any(|stop_id| entity.matches(route, trip_id, Some(stop_id)))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn build_ride_leg(
+        &self,
+        dep_node: usize,
+        arr_node: usize,
+        delay: u32,
+        context: &SearchContext,
+    ) -> Leg {
+        let trip = self.get_node_trip(dep_node);
+        let boarding = self.get_node_stop_time(dep_node);
+        let alighting = self.get_node_stop_time(arr_node);
+        let from_index = self.nodes[dep_node].stop_index;
+        let to_index = self.nodes[arr_node].stop_index;
+        let geometry = self.get_ride_geometry(trip, from_index, to_index);
+        let stop_ids: Vec<&str> = trip.stop_times[from_index..to_index + 1]
+            .iter()
+            .map(|stop_time| stop_time.stop_id.as_str())
+            .collect();
+        let alerts = self.get_alerts_for(
+            self.routes.get(&trip.route_id),
+            Some(&trip.trip_id),
+            &stop_ids,
+            boarding.get_departure_time() + delay,
+            alighting.get_arrival_time() + delay,
+            context,
+        );
+        Leg {
+            from_stop_id: boarding.stop_id.clone(),
+            to_stop_id: alighting.stop_id.clone(),
+            departure_time: boarding.get_departure_time() + delay,
+            arrival_time: alighting.get_arrival_time() + delay,
+            departure_delay: boarding.get_departure_delay(),
+            arrival_delay: alighting.get_arrival_delay(),
+            trip_id: Some(trip.trip_id.clone()),
+            route_id: Some(trip.route_id.clone()),
+            headway: trip.headway,
+            distance: self.get_ride_distance(trip, from_index, to_index),
+            geometry: geometry,
+            alerts: alerts,
+            pickup_on_request: boarding.pickup_on_request(),
+            drop_off_on_request: alighting.drop_off_on_request(),
+            cycling: false,
+            night: self
+                .routes
+                .get(&trip.route_id)
+                .map_or(false, |route| route.is_night),
+            wheelchair_accessible: combine_accessibility(&[
+                trip.is_wheelchair_accessible(),
+                self.get_wheelchair_boarding(&boarding.stop_id),
+                self.get_wheelchair_boarding(&alighting.stop_id),
+            ]),
+            directions: Vec::new(),
+        }
+    }
+
+    /// Returns the position of a place in WGS84 coordinates
+    fn get_wgs84_coords(&self, place: &Place) -> Option<Point<f32>> {
+        match place {
+            Place::Stop(stop_id) => self.stops.get(stop_id).and_then(Stop::get_coords),
+            Place::Coordinates(point) => Some(*point),
+        }
+    }
+
+    /// Builds the walk between a place given by coordinates and a stop, or between two places
+    fn build_place_walk_leg(
+        &self,
+        from: &Place,
+        to: &Place,
+        departure_time: u32,
+        walk: &Access,
+    ) -> Leg {
+        let geometry = match (self.get_wgs84_coords(from), self.get_wgs84_coords(to)) {
+            (Some(from), Some(to)) => Some(LineString::from(vec![from.0, to.0])),
+            _ => None,
+        };
+        Leg {
+            from_stop_id: from.to_string(),
+            to_stop_id: to.to_string(),
+            departure_time: departure_time,
+            arrival_time: departure_time + walk.duration,
+            departure_delay: None,
+            arrival_delay: None,
+            trip_id: None,
+            route_id: None,
+            headway: None,
+            distance: walk.distance,
+            geometry: geometry,
+            alerts: Vec::new(),
+            pickup_on_request: false,
+            drop_off_on_request: false,
+            cycling: false,
+            night: false,
+            // the streets are assumed to be accessible, unlike the stops
+            wheelchair_accessible: combine_accessibility(
+                &[from, to]
+                    .iter()
+                    .filter_map(|place| match place {
+                        Place::Stop(stop_id) => Some(self.get_wheelchair_boarding(stop_id)),
+                        Place::Coordinates(_) => None,
+                    })
+                    .collect::<Vec<Option<bool>>>(),
+            ),
+            directions: Vec::new(),
+        }
+    }
+
+    /// Builds the leg of a transfer between two stops, walked or ridden on a bicycle
+    fn build_transfer_leg(
+        &self,
+        from_stop_id: &str,
+        to_stop_id: &str,
+        departure_time: u32,
+        context: &SearchContext,
+    ) -> Leg {
+        let footpath = match context.options.cycling {
+            Some(_) => None,
+            None => self.get_footpath(from_stop_id, to_stop_id, context.options),
+        };
+        let (distance, duration) = match &context.options.cycling {
+            Some(cycling) => {
+                let distance = self
+                    .proximity_index
+                    .get_distance(from_stop_id, to_stop_id)
+                    .unwrap_or(0.0);
+                (distance, cycling.get_duration(distance))
+            }
+            None => (
+                footpath.map_or(0.0, |footpath| footpath.distance),
+                footpath.map_or(0, |footpath| footpath.duration),
+            ),
+        };
+        let geometry = [from_stop_id, to_stop_id]
+            .iter()
+            .filter_map(|stop_id| self.stops[*stop_id].get_coords())
+            .collect::<LineString<f32>>();
+        Leg {
+            from_stop_id: String::from(from_stop_id),
+            to_stop_id: String::from(to_stop_id),
+            departure_time: departure_time,
+            arrival_time: departure_time + duration,
+            departure_delay: None,
+            arrival_delay: None,
+            trip_id: None,
+            route_id: None,
+            headway: None,
+            distance: distance,
+            geometry: Some(geometry),
+            alerts: self.get_alerts_for(
+                None,
+                None,
+                &[from_stop_id, to_stop_id],
+                departure_time,
+                departure_time + duration,
+                context,
+            ),
+            pickup_on_request: false,
+            drop_off_on_request: false,
+            cycling: context.options.cycling.is_some(),
+            night: false,
+            wheelchair_accessible: combine_accessibility(&[
+                self.get_wheelchair_boarding(from_stop_id),
+                self.get_wheelchair_boarding(to_stop_id),
+                // only the walks through the pathways are known to be step-free or not
+                footpath
+                    .filter(|footpath| !footpath.pathways.is_empty())
+                    .map_or(Some(true), |footpath| Some(footpath.step_free)),
+            ]),
+            directions: footpath
+                .map_or(Vec::new(), |footpath| self.get_walking_directions(footpath)),
+        }
+    }
+
+    /// Describes the pathways walked through by a footpath, e.g. "Take the stairs to
+    /// Malostranská (level Ulice), follow signs for Tram"
+    fn get_walking_directions(&self, footpath: &Footpath) -> Vec<String> {
+        let mut directions = Vec::new();
+        for (pathway_id, reversed) in &footpath.pathways {
+            let pathway = &self.pathways[pathway_id];
+            let (target_stop_id, signposted_as) = match reversed {
+                true => (&pathway.from_stop_id, &pathway.reversed_signposted_as),
+                false => (&pathway.to_stop_id, &pathway.signposted_as),
+            };
+            let mut direction = String::from(pathway.get_action());
+            if let Some(target) = self.stops.get(target_stop_id) {
+                if !target.stop_name.is_empty() {
+                    direction.push_str(&format!(" to {}", target.stop_name));
+                }
+                if let Some(level) = target
+                    .level_id
+                    .as_ref()
+                    .and_then(|level_id| self.levels.get(level_id))
+                {
+                    match &level.level_name {
+                        Some(level_name) => direction.push_str(&format!(" (level {})", level_name)),
+                        None => direction.push_str(&format!(" (level {})", level.level_index)),
+                    }
+                }
+            }
+            if let Some(signposted_as) = signposted_as {
+                direction.push_str(&format!(", follow signs for {}", signposted_as));
             }
-            dists[popped] = node_struct.get_time() as i32;
+            directions.push(direction);
         }
-        return Ok(None);
+        directions
     }
-    */
 }
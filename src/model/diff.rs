@@ -0,0 +1,468 @@
+use crate::model::data_structures::*;
+use crate::model::loader::{load_feed, Feed};
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+// This file compares two versions of a feed, telling what a new feed changed against the
+// previous one.
+
+/// Returns the sorted ids only present in the first map
+fn missing_keys<T, U>(map: &HashMap<String, T>, other: &HashMap<String, U>) -> Vec<String> {
+    let mut keys: Vec<String> = map
+        .keys()
+        .filter(|key| !other.contains_key(*key))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// The changes of the timetable of the trips of a route present in both feeds
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteChange {
+    pub route_id: String,
+    pub added_trips: usize,
+    pub removed_trips: usize,
+    // trips calling at the same stops at different times
+    pub retimed_trips: usize,
+    // trips calling at different stops
+    pub rerouted_trips: usize,
+    // the range of the shifts of the first departures of the retimed trips in seconds
+    pub min_shift: i64,
+    pub max_shift: i64,
+}
+
+impl RouteChange {
+    fn new(route_id: &str) -> RouteChange {
+        RouteChange {
+            route_id: String::from(route_id),
+            added_trips: 0,
+            removed_trips: 0,
+            retimed_trips: 0,
+            rerouted_trips: 0,
+            min_shift: 0,
+            max_shift: 0,
+        }
+    }
+
+    /// Compares the stops and the times of a trip present in both feeds
+    fn add_trip(&mut self, old: &Trip, new: &Trip) {
+        let old_stops: Vec<&str> = old
+            .stop_times
+            .iter()
+            .map(|st| st.stop_id.as_str())
+            .collect();
+        let new_stops: Vec<&str> = new
+            .stop_times
+            .iter()
+            .map(|st| st.stop_id.as_str())
+            .collect();
+        if old_stops != new_stops {
+            self.rerouted_trips += 1;
+            return;
+        }
+        let retimed = old
+            .stop_times
+            .iter()
+            .zip(&new.stop_times)
+            .any(|(old, new)| {
+                old.arrival_time != new.arrival_time || old.departure_time != new.departure_time
+            });
+        if retimed {
+            let shift = match (old.stop_times.first(), new.stop_times.first()) {
+                (Some(old), Some(new)) => new.departure_time as i64 - old.departure_time as i64,
+                _ => 0,
+            };
+            if self.retimed_trips == 0 {
+                self.min_shift = shift;
+                self.max_shift = shift;
+            } else {
+                self.min_shift = self.min_shift.min(shift);
+                self.max_shift = self.max_shift.max(shift);
+            }
+            self.retimed_trips += 1;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added_trips + self.removed_trips + self.retimed_trips + self.rerouted_trips == 0
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "route_id": self.route_id,
+            "added_trips": self.added_trips,
+            "removed_trips": self.removed_trips,
+            "retimed_trips": self.retimed_trips,
+            "rerouted_trips": self.rerouted_trips,
+            "min_shift": self.min_shift,
+            "max_shift": self.max_shift,
+        })
+    }
+}
+
+impl fmt::Display for RouteChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} added, {} removed, {} rerouted, {} retimed trips",
+            self.route_id,
+            self.added_trips,
+            self.removed_trips,
+            self.rerouted_trips,
+            self.retimed_trips
+        )?;
+        if self.retimed_trips > 0 {
+            write!(
+                f,
+                " (shifted by {} to {} s)",
+                self.min_shift, self.max_shift
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the days of the week a service runs on, like 1111100 in calendar.txt
+fn get_weekdays(service: &Service) -> String {
+    [
+        service.monday,
+        service.tuesday,
+        service.wednesday,
+        service.thursday,
+        service.friday,
+        service.saturday,
+        service.sunday,
+    ]
+    .iter()
+    .map(|runs| if *runs { '1' } else { '0' })
+    .collect()
+}
+
+/// The changes of the calendar of a service present in both feeds
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceChange {
+    pub service_id: String,
+    // the days of the week and the validity, old and new, only set if they changed
+    pub weekdays: Option<(String, String)>,
+    pub start_date: Option<(NaiveDate, NaiveDate)>,
+    pub end_date: Option<(NaiveDate, NaiveDate)>,
+    // the dates and exception types of calendar_dates.txt
+    pub added_exceptions: Vec<(NaiveDate, u8)>,
+    pub removed_exceptions: Vec<(NaiveDate, u8)>,
+}
+
+impl ServiceChange {
+    /// Compares two versions of a service, None if they are the same
+    fn new(old: &Service, new: &Service) -> Option<ServiceChange> {
+        let changed = |old: NaiveDate, new: NaiveDate| Some((old, new)).filter(|_| old != new);
+        let weekdays = (get_weekdays(old), get_weekdays(new));
+        let get_exceptions = |service: &Service| -> Vec<(NaiveDate, u8)> {
+            let mut exceptions: Vec<(NaiveDate, u8)> = service
+                .exceptions
+                .iter()
+                .map(|exception| (exception.date, exception.exception_type))
+                .collect();
+            exceptions.sort();
+            exceptions
+        };
+        let (old_exceptions, new_exceptions) = (get_exceptions(old), get_exceptions(new));
+        let change = ServiceChange {
+            service_id: new.service_id.clone(),
+            weekdays: Some(weekdays).filter(|(old, new)| old != new),
+            start_date: changed(old.start_date, new.start_date),
+            end_date: changed(old.end_date, new.end_date),
+            added_exceptions: new_exceptions
+                .iter()
+                .filter(|exception| !old_exceptions.contains(exception))
+                .cloned()
+                .collect(),
+            removed_exceptions: old_exceptions
+                .iter()
+                .filter(|exception| !new_exceptions.contains(exception))
+                .cloned()
+                .collect(),
+        };
+        let unchanged = change.weekdays.is_none()
+            && change.start_date.is_none()
+            && change.end_date.is_none()
+            && change.added_exceptions.is_empty()
+            && change.removed_exceptions.is_empty();
+        if unchanged {
+            None
+        } else {
+            Some(change)
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let pair = |pair: &Option<(String, String)>| {
+            pair.as_ref()
+                .map(|(old, new)| json!({"old": old, "new": new}))
+        };
+        let dates = |dates: &Option<(NaiveDate, NaiveDate)>| {
+            pair(&dates.map(|(old, new)| (old.to_string(), new.to_string())))
+        };
+        let exceptions = |exceptions: &Vec<(NaiveDate, u8)>| -> Vec<Value> {
+            exceptions
+                .iter()
+                .map(|(date, exception_type)| {
+                    json!({"date": date.to_string(), "exception_type": exception_type})
+                })
+                .collect()
+        };
+        json!({
+            "service_id": self.service_id,
+            "weekdays": pair(&self.weekdays),
+            "start_date": dates(&self.start_date),
+            "end_date": dates(&self.end_date),
+            "added_exceptions": exceptions(&self.added_exceptions),
+            "removed_exceptions": exceptions(&self.removed_exceptions),
+        })
+    }
+}
+
+impl fmt::Display for ServiceChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.service_id)?;
+        if let Some((old, new)) = &self.weekdays {
+            write!(f, " weekdays {} -> {}", old, new)?;
+        }
+        if let Some((old, new)) = &self.start_date {
+            write!(f, " start {} -> {}", old, new)?;
+        }
+        if let Some((old, new)) = &self.end_date {
+            write!(f, " end {} -> {}", old, new)?;
+        }
+        for (date, exception_type) in &self.added_exceptions {
+            write!(f, " +{} ({})", date, exception_type)?;
+        }
+        for (date, exception_type) in &self.removed_exceptions {
+            write!(f, " -{} ({})", date, exception_type)?;
+        }
+        Ok(())
+    }
+}
+
+fn get_route_change<'a, 'b>(
+    route_changes: &'b mut BTreeMap<&'a str, RouteChange>,
+    route_id: &'a str,
+) -> &'b mut RouteChange {
+    route_changes
+        .entry(route_id)
+        .or_insert_with(|| RouteChange::new(route_id))
+}
+
+/// The report of the changes between two versions of a feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedDiff {
+    pub added_stops: Vec<String>,
+    pub removed_stops: Vec<String>,
+    pub added_routes: Vec<String>,
+    pub removed_routes: Vec<String>,
+    pub added_trips: Vec<String>,
+    pub removed_trips: Vec<String>,
+    pub added_services: Vec<String>,
+    pub removed_services: Vec<String>,
+    // sorted by their ids, only the routes and the services that changed
+    pub route_changes: Vec<RouteChange>,
+    pub service_changes: Vec<ServiceChange>,
+}
+
+impl FeedDiff {
+    fn new<'a>(old: &'a Feed, new: &'a Feed) -> FeedDiff {
+        let added_trips = missing_keys(&new.trips, &old.trips);
+        let removed_trips = missing_keys(&old.trips, &new.trips);
+        let mut route_changes: BTreeMap<&'a str, RouteChange> = BTreeMap::new();
+        for trip_id in &added_trips {
+            get_route_change(&mut route_changes, &new.trips[trip_id].route_id).added_trips += 1;
+        }
+        for trip_id in &removed_trips {
+            get_route_change(&mut route_changes, &old.trips[trip_id].route_id).removed_trips += 1;
+        }
+        for (trip_id, new_trip) in &new.trips {
+            if let Some(old_trip) = old.trips.get(trip_id) {
+                get_route_change(&mut route_changes, &new_trip.route_id)
+                    .add_trip(old_trip, new_trip);
+            }
+        }
+        let mut service_changes: Vec<ServiceChange> = new
+            .services
+            .iter()
+            .filter_map(|(service_id, service)| {
+                old.services
+                    .get(service_id)
+                    .and_then(|old_service| ServiceChange::new(old_service, service))
+            })
+            .collect();
+        service_changes.sort_by(|a, b| a.service_id.cmp(&b.service_id));
+        FeedDiff {
+            added_stops: missing_keys(&new.stops, &old.stops),
+            removed_stops: missing_keys(&old.stops, &new.stops),
+            added_routes: missing_keys(&new.routes, &old.routes),
+            removed_routes: missing_keys(&old.routes, &new.routes),
+            added_trips: added_trips,
+            removed_trips: removed_trips,
+            added_services: missing_keys(&new.services, &old.services),
+            removed_services: missing_keys(&old.services, &new.services),
+            route_changes: route_changes
+                .into_values()
+                .filter(|change| !change.is_empty())
+                .collect(),
+            service_changes: service_changes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_stops.is_empty()
+            && self.removed_stops.is_empty()
+            && self.added_routes.is_empty()
+            && self.removed_routes.is_empty()
+            && self.route_changes.is_empty()
+            && self.added_services.is_empty()
+            && self.removed_services.is_empty()
+            && self.service_changes.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "stops": {"added": self.added_stops, "removed": self.removed_stops},
+            "routes": {"added": self.added_routes, "removed": self.removed_routes},
+            "trips": {"added": self.added_trips, "removed": self.removed_trips},
+            "services": {"added": self.added_services, "removed": self.removed_services},
+            "route_changes": self.route_changes.iter().map(RouteChange::to_json).collect::<Vec<Value>>(),
+            "service_changes": self.service_changes.iter().map(ServiceChange::to_json).collect::<Vec<Value>>(),
+        })
+    }
+}
+
+impl fmt::Display for FeedDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "The feeds are the same.");
+        }
+        let lists = [
+            ("Added stops", &self.added_stops),
+            ("Removed stops", &self.removed_stops),
+            ("Added routes", &self.added_routes),
+            ("Removed routes", &self.removed_routes),
+            ("Added trips", &self.added_trips),
+            ("Removed trips", &self.removed_trips),
+            ("Added services", &self.added_services),
+            ("Removed services", &self.removed_services),
+        ];
+        for (title, ids) in lists.iter().filter(|(_, ids)| !ids.is_empty()) {
+            writeln!(f, "{} ({}): {}", title, ids.len(), ids.join(", "))?;
+        }
+        if !self.route_changes.is_empty() {
+            writeln!(f, "Changed routes:")?;
+            for change in &self.route_changes {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        if !self.service_changes.is_empty() {
+            writeln!(f, "Changed services:")?;
+            for change in &self.service_changes {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares the feed in the old directory with the feed in the new one
+pub fn diff_feeds(old: &Path, new: &Path) -> FeedDiff {
+    FeedDiff::new(&load_feed(old), &load_feed(new))
+}
+
+#[test]
+fn test_feed_diff() {
+    let old = load_feed(Path::new("test_data/network/"));
+    let mut new = load_feed(Path::new("test_data/network/"));
+    assert!(FeedDiff::new(&old, &new).is_empty());
+
+    let mut stop = new.stops["MC1"].clone();
+    stop.stop_id = String::from("MC3");
+    new.stops.insert(String::from("MC3"), stop);
+    new.stops.remove("T3");
+    new.routes.remove("LN");
+    new.trips.remove("LN_1");
+    let mut trip = new.trips["LA_1"].clone();
+    trip.trip_id = String::from("LA_3");
+    new.trips.insert(String::from("LA_3"), trip);
+    for stop_time in &mut new.trips.get_mut("LA_1").unwrap().stop_times {
+        stop_time.arrival_time += 120;
+        stop_time.departure_time += 120;
+    }
+    for stop_time in &mut new.trips.get_mut("LA_2").unwrap().stop_times {
+        stop_time.arrival_time -= 60;
+        stop_time.departure_time -= 60;
+    }
+    new.trips.get_mut("L22_1").unwrap().stop_times.pop();
+    let service = new.services.get_mut("WD").unwrap();
+    service.saturday = true;
+    service.end_date = NaiveDate::from_ymd(2021, 6, 30);
+    let removed = service.exceptions.pop().unwrap();
+
+    let diff = FeedDiff::new(&old, &new);
+    assert_eq!(diff.added_stops, vec!["MC3"]);
+    assert_eq!(diff.removed_stops, vec!["T3"]);
+    assert!(diff.added_routes.is_empty());
+    assert_eq!(diff.removed_routes, vec!["LN"]);
+    assert_eq!(diff.added_trips, vec!["LA_3"]);
+    assert_eq!(diff.removed_trips, vec!["LN_1"]);
+    assert_eq!(
+        diff.route_changes,
+        vec![
+            RouteChange {
+                route_id: String::from("L22"),
+                rerouted_trips: 1,
+                ..RouteChange::new("L22")
+            },
+            RouteChange {
+                route_id: String::from("LA"),
+                added_trips: 1,
+                retimed_trips: 2,
+                min_shift: -60,
+                max_shift: 120,
+                ..RouteChange::new("LA")
+            },
+            RouteChange {
+                route_id: String::from("LN"),
+                removed_trips: 1,
+                ..RouteChange::new("LN")
+            },
+        ]
+    );
+    let change = &diff.service_changes[0];
+    assert_eq!(change.service_id, "WD");
+    assert_eq!(
+        change.weekdays,
+        Some((String::from("1111100"), String::from("1111110")))
+    );
+    assert_eq!(change.start_date, None);
+    assert_eq!(
+        change.end_date,
+        Some((
+            NaiveDate::from_ymd(2020, 12, 31),
+            NaiveDate::from_ymd(2021, 6, 30)
+        ))
+    );
+    assert!(change.added_exceptions.is_empty());
+    assert_eq!(
+        change.removed_exceptions,
+        vec![(removed.date, removed.exception_type)]
+    );
+
+    let json = diff.to_json();
+    assert_eq!(json["routes"]["removed"][0], "LN");
+    assert_eq!(json["route_changes"][1]["max_shift"], 120);
+    assert_eq!(json["service_changes"][0]["weekdays"]["new"], "1111110");
+    let text = diff.to_string();
+    assert!(text.contains("Removed routes (1): LN"));
+    assert!(text
+        .contains("LA: 1 added, 0 removed, 0 rerouted, 2 retimed trips (shifted by -60 to 120 s)"));
+}
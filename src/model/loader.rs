@@ -1,15 +1,66 @@
 use crate::model::data_structures::*;
-use std::collections::HashMap;
+use crate::model::osm;
+use core::cmp::Reverse;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap};
 use std::fs::File;
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 
+#[cfg(test)]
 use chrono::NaiveDate;
 use geo_types::Point;
 use proj::Proj;
 
 const MAX_PEDESTRIAN_DIST: f32 = 500.0;
-const PEDESTRIAN_SPEED: f32 = 3.6;
+pub const PEDESTRIAN_SPEED: f32 = 3.6;
 const BASE_PEDESTRIAN_TRANSFER_TIME: f32 = 60.0;
+// size of the squares used to find the stops reachable by cycling
+const CYCLING_SQUARE_SIZE: f32 = 1000.0;
+// the optional OpenStreetMap extract with the walkable ways, in the directory of the feed
+const OSM_FILE: &str = "pedestrian.osm.pbf";
+// the pedestrian connections measured along the ways, computed again when older than the
+// extract or the stops, or computed from other inputs
+const FOOTPATH_CACHE_FILE: &str = "footpaths_cache.txt";
+// the longest pedestrian connection along the ways in meters, streets are not straight lines
+const MAX_ROUTED_PEDESTRIAN_DIST: f32 = 750.0;
+// the farthest stations of different feeds with the same name are merged from, in meters
+const MAX_STATION_MERGE_DIST: f32 = 300.0;
+
+/// Loads the contents of agency.txt. Feeds with a single agency may leave out its id, it is
+/// stored under an empty id then.
+/// # Arguments
+/// * path - the path to the directory agency.txt is located in
+fn load_agencies(path: &Path) -> HashMap<String, Agency> {
+    let mut agencies = HashMap::new();
+    let mut file_path_buf = path.to_path_buf();
+    file_path_buf.push(Path::new("agency.txt"));
+    let file = File::open(file_path_buf.as_path()).unwrap(); // No need for error handling, if this fails, we want to panic
+    let mut rdr = csv::Reader::from_reader(file);
+    for result in rdr.deserialize() {
+        let record: Agency = result.unwrap();
+        agencies.insert(record.agency_id.clone().unwrap_or_default(), record);
+    }
+    return agencies;
+}
+
+#[test]
+fn test_agency_loading() {
+    let agencies = load_agencies(Path::new("test_data/"));
+    assert_eq!(agencies.len(), 1);
+    let agency = agencies.get("99").unwrap();
+    assert_eq!(agency.agency_name, "Pražská integrovaná doprava");
+    assert_eq!(agency.agency_url, "https://pid.cz");
+    assert_eq!(agency.agency_timezone, "Europe/Prague");
+    assert_eq!(agency.agency_lang, Some(String::from("cs")));
+    assert_eq!(agency.agency_phone, Some(String::from("+420234704560")));
+    let agencies = load_agencies(Path::new("test_data/non_pid/"));
+    let agency = agencies.get("").unwrap();
+    assert_eq!(agency.agency_id, None);
+    assert_eq!(agency.agency_phone, None);
+}
 
 /// Loads the contents of stops.txt
 /// # Arguments
@@ -22,6 +73,10 @@ fn load_stops(path: &Path) -> HashMap<String, Stop> {
     let mut rdr = csv::Reader::from_reader(file);
     for result in rdr.deserialize() {
         let record: Stop = result.unwrap();
+        // only the generic nodes and the boarding areas may lack the position
+        if record.location_type < 3 && record.get_coords().is_none() {
+            panic!("Stop {} has no coordinates.", record.stop_id);
+        }
         stops.insert(record.stop_id.clone(), record);
     }
     return stops;
@@ -34,9 +89,9 @@ fn test_stop_loading() {
     let stop = stops.get("U50S1").unwrap();
     assert_eq!(stop.stop_id, "U50S1");
     assert_eq!(stop.stop_name, "Budějovická");
-    assert_eq!(stop.stop_lat, 50.04441);
-    assert_eq!(stop.stop_lon, 14.44879);
-    assert_eq!(stop.zone_id, "P");
+    assert_eq!(stop.stop_lat, Some(50.04441));
+    assert_eq!(stop.stop_lon, Some(14.44879));
+    assert_eq!(stop.zone_id, Some(String::from("P")));
     assert_eq!(stop.stop_url, None);
     assert_eq!(stop.location_type, 1);
     assert_eq!(stop.parent_station, None);
@@ -45,6 +100,16 @@ fn test_stop_loading() {
     assert_eq!(stop.platform_code, None);
 }
 
+#[test]
+fn test_stop_loading_without_optional_columns() {
+    let stops = load_stops(Path::new("test_data/non_pid/"));
+    let stop = stops.get("S1").unwrap();
+    assert_eq!(stop.stop_name, "Hlavní nádraží");
+    assert_eq!(stop.zone_id, None);
+    assert_eq!(stop.location_type, 0);
+    assert_eq!(stop.wheelchair_boarding, None);
+}
+
 /// Loads the contents of routes.txt
 /// # Arguments
 /// * path - the path to the directory routes.txt is located in
@@ -67,7 +132,7 @@ fn test_route_loading() {
     assert_eq!(1, routes.len());
     let route = routes.get("L991").unwrap();
     assert_eq!(route.route_id, "L991");
-    assert_eq!(route.agency_id, "99");
+    assert_eq!(route.agency_id, Some(String::from("99")));
     assert_eq!(route.route_short_name, "A");
     assert_eq!(
         route.route_long_name,
@@ -83,6 +148,16 @@ fn test_route_loading() {
     assert_eq!(route.is_night, false);
 }
 
+#[test]
+fn test_route_loading_without_optional_columns() {
+    let routes = load_routes(Path::new("test_data/non_pid/"));
+    let route = routes.get("R1").unwrap();
+    assert_eq!(route.agency_id, None);
+    assert_eq!(route.route_short_name, "12");
+    assert_eq!(route.route_long_name, "");
+    assert_eq!(route.is_night, false);
+}
+
 /// Loads the contents of trips.txt
 /// # Arguments
 /// * path - the path to the directory trips.txt is located in
@@ -109,7 +184,7 @@ fn test_trip_loading() {
     assert_eq!(trip.trip_id, "991_1411_191224");
     assert_eq!(trip.trip_headsign, Some(String::from("Nemocnice Motol")));
     assert_eq!(trip.trip_short_name, None);
-    assert_eq!(trip.direction_id, 0);
+    assert_eq!(trip.direction_id, Some(0));
     assert_eq!(trip.block_id, None);
     assert_eq!(trip.shape_id, Some(String::from("L991V1")));
     assert_eq!(trip.wheelchair_accessible, Some(1));
@@ -118,6 +193,16 @@ fn test_trip_loading() {
     assert_eq!(trip.trip_operation_type, Some(1));
 }
 
+#[test]
+fn test_trip_loading_without_optional_columns() {
+    let trips = load_trips(Path::new("test_data/non_pid/"));
+    let trip = trips.get("R1_1").unwrap();
+    assert_eq!(trip.direction_id, None);
+    assert_eq!(trip.shape_id, None);
+    assert_eq!(trip.exceptional, None);
+    assert_eq!(trip.trip_operation_type, None);
+}
+
 /// Loads the contents of services.txt and service_dates.txt
 /// # Arguments
 /// * path - the path to the directory the files are located in
@@ -161,14 +246,29 @@ fn load_service_exceptions(path: &Path, services: &mut HashMap<String, Service>)
     let mut rdr = csv::Reader::from_reader(file);
     for result in rdr.deserialize() {
         let record: ServiceException = result.unwrap();
+        // services may be defined only by their exceptions, without a row in calendar.txt
         services
-            .get_mut(&record.service_id)
-            .unwrap()
+            .entry(record.service_id.clone())
+            .or_insert_with(|| Service::without_calendar(&record.service_id, record.date))
             .exceptions
             .push(record);
     }
 }
 
+#[test]
+fn test_service_exception_loading() {
+    let mut services = load_services(Path::new("test_data/"));
+    load_service_exceptions(Path::new("test_data/"), &mut services);
+    assert_eq!(services.len(), 2);
+    let service = services.get("0000011-2").unwrap();
+    assert_eq!(service.exceptions.len(), 1);
+    assert_eq!(service.runs_on(NaiveDate::from_ymd(2020, 1, 26)), false);
+    let service = services.get("0000010-1").unwrap();
+    assert_eq!(service.runs_on(NaiveDate::from_ymd(2020, 2, 1)), true);
+    assert_eq!(service.runs_on(NaiveDate::from_ymd(2020, 2, 3)), false);
+    assert_eq!(service.runs_on(NaiveDate::from_ymd(2020, 2, 8)), false);
+}
+
 fn load_stop_times(path: &Path, trips: &mut HashMap<String, Trip>) {
     let mut file_path_buf = path.to_path_buf();
     file_path_buf.push(Path::new("stop_times.txt"));
@@ -182,22 +282,286 @@ fn load_stop_times(path: &Path, trips: &mut HashMap<String, Trip>) {
             .stop_times
             .push(stop_time);
     }
-    for trip in trips.values_mut() {
+    trips.retain(|trip_id, trip| {
         trip.stop_times
             .sort_by(|a, b| a.stop_sequence.cmp(&b.stop_sequence));
+        let complete = interpolate_stop_times(&mut trip.stop_times);
+        if !complete {
+            println!(
+                "Skipping trip {}, its first or last stop has no time.",
+                trip_id
+            );
+        }
+        complete
+    });
+}
+
+/// Fills in the times of the stops that are not timepoints. The times are interpolated between
+/// the surrounding timepoints by shape_dist_traveled if known, evenly by the stops otherwise.
+/// Returns false if the first or the last stop has no time.
+fn interpolate_stop_times(stop_times: &mut [StopTime]) -> bool {
+    for stop_time in stop_times.iter_mut() {
+        // a stop with only one of the times is left right after arriving
+        if stop_time.arrival_time == UNKNOWN_TIME {
+            stop_time.arrival_time = stop_time.departure_time;
+        } else if stop_time.departure_time == UNKNOWN_TIME {
+            stop_time.departure_time = stop_time.arrival_time;
+        }
+    }
+    if stop_times.is_empty() {
+        return true;
     }
+    let timepoints: Vec<usize> = (0..stop_times.len())
+        .filter(|&index| stop_times[index].arrival_time != UNKNOWN_TIME)
+        .collect();
+    if timepoints.first() != Some(&0) || timepoints.last() != Some(&(stop_times.len() - 1)) {
+        return false;
+    }
+    for pair in timepoints.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let start = stop_times[from].departure_time;
+        let duration = stop_times[to].arrival_time.saturating_sub(start);
+        let from_distance = stop_times[from].shape_dist_traveled;
+        let to_distance = stop_times[to].shape_dist_traveled;
+        for (index, stop_time) in stop_times.iter_mut().enumerate().take(to).skip(from + 1) {
+            let fraction = match (from_distance, stop_time.shape_dist_traveled, to_distance) {
+                (Some(first), Some(distance), Some(last)) if last > first => {
+                    ((distance - first) / (last - first)).clamp(0.0, 1.0)
+                }
+                _ => (index - from) as f32 / (to - from) as f32,
+            };
+            let time = start + (fraction * duration as f32).round() as u32;
+            stop_time.arrival_time = time;
+            stop_time.departure_time = time;
+        }
+    }
+    true
 }
 
-/// Converts stop coordinates in WGS84 to UTM coordinates in zone 33U
+#[test]
+fn test_stop_time_loading() {
+    let mut trips = load_trips(Path::new("test_data/"));
+    load_stop_times(Path::new("test_data/"), &mut trips);
+    let stop_time = &trips.get("991_1411_191224").unwrap().stop_times[0];
+    assert_eq!(stop_time.arrival_time, 11 * 3600 + 2 * 60 + 40);
+    assert_eq!(stop_time.departure_time, 11 * 3600 + 2 * 60 + 40);
+    assert_eq!(stop_time.stop_id, "U1071Z102P");
+    assert_eq!(stop_time.stop_sequence, 1);
+    assert_eq!(stop_time.shape_dist_traveled, Some(0.0));
+}
+
+#[test]
+fn test_stop_time_loading_without_optional_columns() {
+    let mut trips = load_trips(Path::new("test_data/non_pid/"));
+    load_stop_times(Path::new("test_data/non_pid/"), &mut trips);
+    let stop_time = &trips.get("R1_1").unwrap().stop_times[0];
+    assert_eq!(stop_time.arrival_time, 8 * 3600);
+    assert_eq!(stop_time.stop_headsign, None);
+    assert_eq!(stop_time.pickup_type, 0);
+    assert_eq!(stop_time.drop_off_type, 0);
+    // the stops that are not timepoints get times between the surrounding ones, by the distance
+    // if known
+    let times = |trip_id: &str| -> Vec<(u32, u32)> {
+        trips[trip_id]
+            .stop_times
+            .iter()
+            .map(|stop_time| (stop_time.arrival_time, stop_time.departure_time))
+            .collect()
+    };
+    assert_eq!(
+        times("R1_1"),
+        vec![
+            (8 * 3600, 8 * 3600),
+            (8 * 3600 + 3 * 60, 8 * 3600 + 3 * 60),
+            (8 * 3600 + 10 * 60, 8 * 3600 + 11 * 60),
+            (8 * 3600 + 15 * 60, 8 * 3600 + 15 * 60),
+        ]
+    );
+    assert_eq!(
+        times("R1_2")[1..3],
+        [
+            (9 * 3600 + 5 * 60, 9 * 3600 + 5 * 60),
+            (9 * 3600 + 10 * 60, 9 * 3600 + 10 * 60)
+        ]
+    );
+    // a trip without the time of its last stop cannot be used
+    assert!(!trips.contains_key("R1_3"));
+}
+
+/// Loads the contents of frequencies.txt. The file is optional, an empty list is returned if it
+/// is missing.
+/// # Arguments
+/// * path - the path to the directory frequencies.txt is located in
+fn load_frequencies(path: &Path) -> Vec<Frequency> {
+    let mut frequencies = Vec::new();
+    let mut file_path_buf = path.to_path_buf();
+    file_path_buf.push(Path::new("frequencies.txt"));
+    let file = match File::open(file_path_buf.as_path()) {
+        Ok(file) => file,
+        Err(_) => return frequencies,
+    };
+    let mut rdr = csv::Reader::from_reader(file);
+    for result in rdr.deserialize() {
+        let record: Frequency = result.unwrap();
+        frequencies.push(record);
+    }
+    return frequencies;
+}
+
+#[test]
+fn test_frequency_loading() {
+    let frequencies = load_frequencies(Path::new("test_data/network/"));
+    assert_eq!(frequencies.len(), 2);
+    assert_eq!(frequencies[0].trip_id, "LC_T");
+    assert_eq!(frequencies[0].start_time, 8 * 3600);
+    assert_eq!(frequencies[0].end_time, 9 * 3600);
+    assert_eq!(frequencies[0].headway_secs, 600);
+    assert_eq!(frequencies[0].exact_times, 1);
+    assert!(load_frequencies(Path::new("test_data/")).is_empty());
+}
+
+/// Replaces the template trips referenced from frequencies.txt with concrete trips, one run for
+/// every headway between the start and end time. The runs of trips without exact times keep the
+/// headway, the search expects a wait of half of it before boarding them. Frequencies with a zero
+/// headway and templates without stop times are skipped.
+fn expand_frequencies(trips: &mut HashMap<String, Trip>, frequencies: &Vec<Frequency>) {
+    for frequency in frequencies {
+        let template = match trips.get(&frequency.trip_id) {
+            Some(template) if !template.stop_times.is_empty() => template.clone(),
+            _ => continue,
+        };
+        if frequency.headway_secs == 0 {
+            println!(
+                "Skipping the frequency of trip {} with a zero headway.",
+                frequency.trip_id
+            );
+            continue;
+        }
+        let template_start = template.stop_times[0].departure_time;
+        let mut start = frequency.start_time;
+        while start < frequency.end_time {
+            let mut trip = template.clone();
+            trip.trip_id = format!("{}_{}", template.trip_id, format_time(start));
+            trip.template_trip_id = Some(template.trip_id.clone());
+            if frequency.exact_times == 0 {
+                trip.headway = Some(frequency.headway_secs);
+            }
+            for stop_time in trip.stop_times.iter_mut() {
+                stop_time.trip_id = trip.trip_id.clone();
+                stop_time.arrival_time = stop_time.arrival_time + start - template_start;
+                stop_time.departure_time = stop_time.departure_time + start - template_start;
+            }
+            trips.insert(trip.trip_id.clone(), trip);
+            start += frequency.headway_secs;
+        }
+    }
+    for frequency in frequencies {
+        trips.remove(&frequency.trip_id);
+    }
+}
+
+#[test]
+fn test_frequency_expansion() {
+    let mut trips = load_trips(Path::new("test_data/network/"));
+    load_stop_times(Path::new("test_data/network/"), &mut trips);
+    expand_frequencies(
+        &mut trips,
+        &load_frequencies(Path::new("test_data/network/")),
+    );
+    assert!(trips.get("LC_T").is_none());
+    assert_eq!(
+        trips
+            .values()
+            .filter(|trip| trip.template_trip_id == Some(String::from("LC_T")))
+            .count(),
+        6
+    );
+    let trip = trips.get("LC_T_08:10:00").unwrap();
+    assert_eq!(trip.headway, None);
+    assert_eq!(trip.stop_times[0].departure_time, 8 * 3600 + 10 * 60);
+    assert_eq!(trip.stop_times[1].arrival_time, 8 * 3600 + 12 * 60);
+    assert_eq!(trips.get("LC_E_08:55:00").unwrap().headway, Some(300));
+    assert_eq!(
+        trips
+            .values()
+            .filter(|trip| trip.template_trip_id == Some(String::from("LC_E")))
+            .count(),
+        12
+    );
+
+    // zero headways and empty templates are skipped
+    let mut trips = load_trips(Path::new("test_data/network/"));
+    load_stop_times(Path::new("test_data/network/"), &mut trips);
+    let mut frequencies = load_frequencies(Path::new("test_data/network/"));
+    frequencies[0].headway_secs = 0;
+    trips.get_mut("LC_E").unwrap().stop_times.clear();
+    expand_frequencies(&mut trips, &frequencies);
+    assert!(trips.values().all(|trip| trip.template_trip_id.is_none()));
+}
+
+/// Loads the contents of shapes.txt, grouped by shape and sorted by sequence. The file is
+/// optional, an empty map is returned if it is missing.
+/// # Arguments
+/// * path - the path to the directory shapes.txt is located in
+fn load_shapes(path: &Path) -> HashMap<String, Vec<ShapePoint>> {
+    let mut shapes: HashMap<String, Vec<ShapePoint>> = HashMap::new();
+    let mut file_path_buf = path.to_path_buf();
+    file_path_buf.push(Path::new("shapes.txt"));
+    let file = match File::open(file_path_buf.as_path()) {
+        Ok(file) => file,
+        Err(_) => return shapes,
+    };
+    let mut rdr = csv::Reader::from_reader(file);
+    for result in rdr.deserialize() {
+        let record: ShapePoint = result.unwrap();
+        shapes
+            .entry(record.shape_id.clone())
+            .or_insert_with(Vec::new)
+            .push(record);
+    }
+    for shape in shapes.values_mut() {
+        shape.sort_by(|a, b| a.shape_pt_sequence.cmp(&b.shape_pt_sequence));
+    }
+    return shapes;
+}
+
+#[test]
+fn test_shape_loading() {
+    let shapes = load_shapes(Path::new("test_data/network/"));
+    assert_eq!(shapes.len(), 2);
+    let shape = shapes.get("LAV1").unwrap();
+    assert_eq!(shape.len(), 5);
+    assert_eq!(shape[1].shape_pt_lat, 50.08);
+    assert_eq!(shape[1].shape_pt_lon, 14.405);
+    assert_eq!(shape[1].shape_dist_traveled, Some(0.3575));
+    assert!(load_shapes(Path::new("test_data/")).is_empty());
+}
+
+pub fn create_wgs84_to_utm() -> Proj {
+    let from = "EPSG:4326";
+    let to = "EPSG:32633";
+    Proj::new_known_crs(&from, &to, None).unwrap()
+}
+
+thread_local! {
+    // creating the transformation is slow and it cannot be shared between threads
+    static WGS84_TO_UTM: Proj = create_wgs84_to_utm();
+}
+
+/// Converts a point in WGS84 coordinates to UTM coordinates in zone 33U, None if the point cannot
+/// be converted
+pub fn wgs84_to_utm(point: Point<f32>) -> Option<Point<f32>> {
+    WGS84_TO_UTM.with(|wgs84_to_utm| wgs84_to_utm.convert(point).ok())
+}
+
+/// Converts stop coordinates in WGS84 to UTM coordinates in zone 33U. The stops without a
+/// position are left out, they are only reached through pathways.
 fn get_stop_coords_in_utm(stops: &HashMap<String, Stop>) -> HashMap<String, Point<f32>> {
     let mut stop_coords: HashMap<String, Point<f32>> = HashMap::new();
     for (stop_id, stop) in stops {
-        let from = "EPSG:4326";
-        let to = "EPSG:32633";
-        let wsg_to_utm = Proj::new_known_crs(&from, &to, None).unwrap();
-        let wsg_coords = Point::new(stop.stop_lon, stop.stop_lat);
-        let coords = wsg_to_utm.convert(wsg_coords).unwrap();
-        stop_coords.insert(stop_id.clone(), coords);
+        if let Some(coords) = stop.get_coords().and_then(wgs84_to_utm) {
+            stop_coords.insert(stop_id.clone(), coords);
+        }
     }
     return stop_coords;
 }
@@ -226,6 +590,323 @@ fn calculate_proximity_squares(
     return squares;
 }
 
+/// Loads the contents of levels.txt. The file is optional, an empty map is returned if it is
+/// missing.
+/// # Arguments
+/// * path - the path to the directory levels.txt is located in
+fn load_levels(path: &Path) -> HashMap<String, Level> {
+    let mut levels = HashMap::new();
+    let file = match File::open(path.join("levels.txt")) {
+        Ok(file) => file,
+        Err(_) => return levels,
+    };
+    let mut rdr = csv::Reader::from_reader(file);
+    for result in rdr.deserialize() {
+        let record: Level = result.unwrap();
+        levels.insert(record.level_id.clone(), record);
+    }
+    return levels;
+}
+
+/// Loads the contents of pathways.txt. The file is optional, an empty map is returned if it is
+/// missing.
+/// # Arguments
+/// * path - the path to the directory pathways.txt is located in
+fn load_pathways(path: &Path) -> HashMap<String, Pathway> {
+    let mut pathways = HashMap::new();
+    let file = match File::open(path.join("pathways.txt")) {
+        Ok(file) => file,
+        Err(_) => return pathways,
+    };
+    let mut rdr = csv::Reader::from_reader(file);
+    for result in rdr.deserialize() {
+        let record: Pathway = result.unwrap();
+        pathways.insert(record.pathway_id.clone(), record);
+    }
+    return pathways;
+}
+
+#[test]
+fn test_level_and_pathway_loading() {
+    let levels = load_levels(Path::new("test_data/network/"));
+    assert_eq!(levels.len(), 2);
+    assert_eq!(levels["L-1"].level_index, -1.0);
+    assert_eq!(levels["L-1"].level_name, Some(String::from("Nástupiště")));
+    let pathways = load_pathways(Path::new("test_data/network/"));
+    assert_eq!(pathways.len(), 4);
+    let stairs = &pathways["MAL_STAIRS"];
+    assert_eq!(stairs.from_stop_id, "MA3");
+    assert!(stairs.is_bidirectional);
+    assert!(!stairs.is_step_free());
+    assert_eq!(stairs.stair_count, Some(40));
+    assert!(load_pathways(Path::new("test_data/")).is_empty());
+}
+
+/// A walk through the pathways, its duration, its distance and the pathways walked through with
+/// whether they were walked against their direction
+type PathwayWalk = (u32, f32, Vec<(String, bool)>);
+
+/// Finds the fastest walks through the pathways from a platform, or one of its boarding areas,
+/// to the other platforms. Returns the durations, the distances and the pathways walked through
+/// for the platforms reached.
+fn walk_pathways<'a>(
+    sources: &[&'a str],
+    outgoing: &HashMap<&'a str, Vec<(&'a Pathway, bool)>>,
+    step_free: bool,
+) -> HashMap<&'a str, PathwayWalk> {
+    let mut settled: HashMap<&str, (u32, f32)> = HashMap::new();
+    // the pathway a location was reached by and the location it was walked from
+    let mut came_from: HashMap<&str, (&str, bool, &str)> = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    for &source in sources {
+        heap.push(Reverse((0, source, 0.0f32.to_bits(), None)));
+    }
+    while let Some(Reverse((duration, location, distance, from))) = heap.pop() {
+        if settled.contains_key(location) {
+            continue;
+        }
+        settled.insert(location, (duration, f32::from_bits(distance)));
+        if let Some(from) = from {
+            came_from.insert(location, from);
+        }
+        for &(pathway, reversed) in outgoing.get(location).into_iter().flatten() {
+            if step_free && !pathway.is_step_free() {
+                continue;
+            }
+            let next = if reversed {
+                pathway.from_stop_id.as_str()
+            } else {
+                pathway.to_stop_id.as_str()
+            };
+            if !settled.contains_key(next) {
+                let next_distance = f32::from_bits(distance) + pathway.length.unwrap_or(0.0);
+                heap.push(Reverse((
+                    duration + pathway.get_traversal_time(PEDESTRIAN_SPEED),
+                    next,
+                    next_distance.to_bits(),
+                    Some((pathway.pathway_id.as_str(), reversed, location)),
+                )));
+            }
+        }
+    }
+    settled
+        .into_iter()
+        .map(|(location, (duration, distance))| {
+            let mut walked = Vec::new();
+            let mut current = location;
+            while let Some((pathway_id, reversed, from)) = came_from.get(current) {
+                walked.push((String::from(*pathway_id), *reversed));
+                current = from;
+            }
+            walked.reverse();
+            (location, (duration, distance, walked))
+        })
+        .collect()
+}
+
+/// Creates the footpaths between the platforms of stations connected by pathways. Besides the
+/// fastest walk, a slower step-free one is added when the fastest one uses stairs or escalators.
+fn get_pathway_connections(
+    stops: &HashMap<String, Stop>,
+    pathways: &HashMap<String, Pathway>,
+) -> HashMap<String, Vec<Footpath>> {
+    let mut outgoing: HashMap<&str, Vec<(&Pathway, bool)>> = HashMap::new();
+    for pathway in pathways.values() {
+        outgoing
+            .entry(&pathway.from_stop_id)
+            .or_insert_with(Vec::new)
+            .push((pathway, false));
+        if pathway.is_bidirectional {
+            outgoing
+                .entry(&pathway.to_stop_id)
+                .or_insert_with(Vec::new)
+                .push((pathway, true));
+        }
+    }
+    // boarding areas (location_type 4) belong to their platforms
+    let get_platform = |location: &str| -> Option<&str> {
+        let stop = stops.get(location)?;
+        match stop.location_type {
+            0 => Some(stop.stop_id.as_str()),
+            4 => stop.parent_station.as_deref(),
+            _ => None,
+        }
+    };
+    let mut boarding_areas: HashMap<&str, Vec<&str>> = HashMap::new();
+    for stop in stops.values().filter(|stop| stop.location_type == 4) {
+        if let Some(parent) = &stop.parent_station {
+            boarding_areas
+                .entry(parent)
+                .or_insert_with(Vec::new)
+                .push(&stop.stop_id);
+        }
+    }
+    let mut connections: HashMap<String, Vec<Footpath>> = HashMap::new();
+    for platform in stops.values().filter(|stop| stop.location_type == 0) {
+        let sources: Vec<&str> = std::iter::once(platform.stop_id.as_str())
+            .chain(
+                boarding_areas
+                    .get(platform.stop_id.as_str())
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            )
+            .filter(|location| outgoing.contains_key(location))
+            .collect();
+        if sources.is_empty() {
+            continue;
+        }
+        let mut fastest: HashMap<&str, PathwayWalk> = HashMap::new();
+        let mut fastest_step_free = fastest.clone();
+        for (step_free, best) in [(false, &mut fastest), (true, &mut fastest_step_free)] {
+            for (location, walk) in walk_pathways(&sources, &outgoing, step_free) {
+                match get_platform(location) {
+                    Some(target)
+                        if target != platform.stop_id
+                            && best.get(target).map_or(true, |old| walk.0 < old.0) =>
+                    {
+                        best.insert(target, walk);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut footpaths = Vec::new();
+        for (target, (duration, distance, walked)) in fastest {
+            let step_free = walked
+                .iter()
+                .all(|(pathway_id, _)| pathways[pathway_id].is_step_free());
+            footpaths.push(Footpath {
+                target_stop_id: String::from(target),
+                distance: distance,
+                duration: duration,
+                pathways: walked,
+                step_free: step_free,
+            });
+            if step_free {
+                continue;
+            }
+            if let Some((duration, distance, walked)) = fastest_step_free.remove(target) {
+                footpaths.push(Footpath {
+                    target_stop_id: String::from(target),
+                    distance: distance,
+                    duration: duration,
+                    pathways: walked,
+                    step_free: true,
+                });
+            }
+        }
+        connections.insert(platform.stop_id.clone(), footpaths);
+    }
+    connections
+}
+
+/// Replaces the straight line footpaths between the stops connected by pathways with the walks
+/// through the pathways
+fn add_pathway_connections(
+    footpaths: &mut HashMap<String, Vec<Footpath>>,
+    pathway_connections: HashMap<String, Vec<Footpath>>,
+) {
+    for (stop_id, pathway_footpaths) in pathway_connections {
+        let stop_footpaths = footpaths.entry(stop_id).or_insert_with(Vec::new);
+        stop_footpaths.retain(|footpath| {
+            !pathway_footpaths
+                .iter()
+                .any(|other| other.target_stop_id == footpath.target_stop_id)
+        });
+        stop_footpaths.extend(pathway_footpaths);
+    }
+}
+
+/// Loads the fares of fare_attributes.txt and fare_rules.txt, the fares of GTFS Fares v2 and the
+/// tickets of the non-standard zone_tickets.txt. All the files are optional.
+/// # Arguments
+/// * path - the path to the directory the files are located in
+fn load_fares(path: &Path) -> Fares {
+    fn load_optional<T: serde::de::DeserializeOwned>(path: &Path) -> Vec<T> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Vec::new(),
+        };
+        let mut rdr = csv::Reader::from_reader(file);
+        rdr.deserialize().map(|result| result.unwrap()).collect()
+    }
+    Fares {
+        rules: FareRules::new(
+            load_optional(&path.join("fare_attributes.txt")),
+            load_optional(&path.join("fare_rules.txt")),
+        ),
+        products: FareProducts::new(
+            load_optional(&path.join("fare_products.txt")),
+            load_optional(&path.join("fare_leg_rules.txt")),
+            load_optional(&path.join("fare_transfer_rules.txt")),
+            load_optional(&path.join("areas.txt")),
+            load_optional(&path.join("stop_areas.txt")),
+            load_optional(&path.join("route_networks.txt")),
+        ),
+        zone_tickets: ZoneTickets::new(load_optional(&path.join("zone_tickets.txt"))),
+    }
+}
+
+#[test]
+fn test_fare_loading() {
+    let fares = load_fares(Path::new("test_data/network/"));
+    assert!(!fares.rules.is_empty());
+    assert!(!fares.zone_tickets.is_empty());
+    assert!(fares.products.is_empty());
+    let fares = load_fares(Path::new("test_data/"));
+    assert!(fares.rules.is_empty());
+    assert!(fares.zone_tickets.is_empty());
+}
+
+#[test]
+fn test_fare_products_loading() {
+    let fares = load_fares(Path::new("test_data/fares_v2/"));
+    assert!(fares.rules.is_empty());
+    assert!(!fares.products.is_empty());
+    assert_eq!(
+        fares.products.get_area("PRAHA").unwrap().area_name,
+        Some(String::from("Praha"))
+    );
+    assert_eq!(
+        fares.products.get_stop_areas("MAL"),
+        &[String::from("PRAHA")]
+    );
+    assert_eq!(
+        fares.products.get_route_network("L22"),
+        Some(&String::from("pid"))
+    );
+    // the tram ride is in Prague, the transfer to the metro within 90 minutes is free
+    let ride = |leg_index, route_id: &str, departure_time, arrival_time| FareRide {
+        leg_index: leg_index,
+        route_id: String::from(route_id),
+        agency_id: None,
+        network_id: Some(String::from("pid")),
+        from_areas: vec![String::from("PRAHA")],
+        to_areas: vec![String::from("PRAHA")],
+        departure_time: departure_time,
+        arrival_time: arrival_time,
+        zones: Vec::new(),
+    };
+    let fare = fares
+        .get_fare(&[ride(0, "L22", 0, 600), ride(2, "LA", 1200, 1800)])
+        .unwrap();
+    assert_eq!(fare.price, 40.0);
+    assert_eq!(
+        fare.tickets[0].name,
+        Some(String::from("Základní 90 minut"))
+    );
+    let fare = fares
+        .get_fare(&[ride(0, "L22", 0, 600), ride(2, "LA", 6000, 6600)])
+        .unwrap();
+    assert_eq!(fare.price, 80.0);
+}
+
+/// Returns the time in seconds needed to walk the given distance in meters between two stops
+pub fn get_walking_duration(distance: f32) -> u32 {
+    (BASE_PEDESTRIAN_TRANSFER_TIME + distance / PEDESTRIAN_SPEED).round() as u32
+}
+
 /// Takes squares of sizes max_conn_dist times max_conn_dist that contain stops in utm coordinates,
 /// and it efficiently computes connections between stops closer than max_conn_dist. (efficiently means faster than
 /// O(N^2) N being the number of all stops.
@@ -233,8 +914,8 @@ fn get_pedestrian_connections(
     utm_coords: &HashMap<String, Point<f32>>,
     squares: &HashMap<(i32, i32), Vec<String>>,
     max_conn_dist: f32,
-) -> HashMap<String, Vec<(String, f32)>> {
-    let mut connections: HashMap<String, Vec<(String, f32)>> = HashMap::new();
+) -> HashMap<String, Vec<Footpath>> {
+    let mut connections: HashMap<String, Vec<Footpath>> = HashMap::new();
     for ((x, y), stop_ids) in squares {
         for stop_id in stop_ids {
             let coord = utm_coords.get(stop_id).unwrap();
@@ -242,18 +923,18 @@ fn get_pedestrian_connections(
                 for dy in -1..2 {
                     if let Some(near_stop_ids) = squares.get(&(x + dx, y + dy)) {
                         for near_id in near_stop_ids {
+                            if near_id == stop_id {
+                                continue;
+                            }
                             let near_coord = utm_coords.get(near_id).unwrap();
                             let distance = (coord.x() - near_coord.x()).abs()
                                 + (coord.y() - near_coord.y()).abs();
-                            if (distance <= max_conn_dist) {
-                                if let Some(connection) = connections.get_mut(stop_id) {
-                                    connection.push((String::from(near_id), distance));
-                                } else {
-                                    connections.insert(
-                                        String::from(stop_id),
-                                        vec![(String::from(near_id), distance)],
-                                    );
-                                }
+                            if distance <= max_conn_dist {
+                                let cost = get_walking_duration(distance);
+                                connections
+                                    .entry(String::from(stop_id))
+                                    .or_insert_with(Vec::new)
+                                    .push(Footpath::new(String::from(near_id), distance, cost));
                             }
                         }
                     }
@@ -264,105 +945,1498 @@ fn get_pedestrian_connections(
     return connections;
 }
 
-/// creates a node collection with depart node, arrival node and the actual node in the vehicle
-/// Returns the transport node created
-/// TODO can this be done without so many side effects?
-fn create_node_triplet(nodes: &mut Vec<Node>, arrival_nodes: &mut Hashmap<String, Vec<usize>>, departure_nodes: &mut HashMap<String, Vec<usize>>, stop_time: &StopTime) -> Node {
-    // the id is the node's position in the list, therefore we can use current list length as the id
-    let mut transport_node = Node::new(nodes.len(), stop_time.arrival_time);
-    nodes.push(transport_node);
-    let mut arr_node = Node::new(nodes.len(), stop_time.arrival_time + MINIMAL_TRANSFER_TIME);
-    nodes.push(arr_node);
-    let mut dep_node = Node::new(nodes.len(), stop_time.departure_time);
-    nodes.push(dep_node);
-    transport_node.add_edge(arr_node.node_id);
-    dep_node.add_edge(transport_node.node_id);
-    // adding nodes to temporary hashmaps for further processing and edge-adding
-    arrival_nodes.get(&stop_time.stop_id).unwrap().push(arr_node.node_id);
-    departure_nodes.get(&stop_time.stop_id).unwrap().push(dep_node.node_id);
-    return transport_node;
-}
-
-fn create_nodes(stops: &HashMap<String, Stop>, trips: &HashMap<String, Trip>) -> Vec<Node> {
-    let mut nodes: Vec<Node> = Vec::new();
-    let mut arrival_nodes: HashMap<String, Vec<usize>> = HashMap::new();
-    let mut departure_nodes: HashMap<String, Vec<usize>> = HashMap::new();
-    for stop_id, _ in stops {
-        
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedFootpath {
+    stop_id: String,
+    target_stop_id: String,
+    distance: f32,
+    duration: u32,
+}
+
+/// Describes what the routed pedestrian connections were computed from, the limits and a hash of
+/// the stop coordinates and the straight line connections. The cache stores it on its first line
+/// and is only used when it matches. The hash of the standard library may change between Rust
+/// releases, the connections are computed again then.
+fn get_footpath_cache_key(
+    utm_coords: &HashMap<String, Point<f32>>,
+    straight_line: &HashMap<String, Vec<Footpath>>,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    let mut coords: Vec<(&String, &Point<f32>)> = utm_coords.iter().collect();
+    coords.sort_by(|a, b| a.0.cmp(b.0));
+    for (stop_id, coord) in coords {
+        (stop_id, coord.x().to_bits(), coord.y().to_bits()).hash(&mut hasher);
+    }
+    let mut footpaths: Vec<(&String, &Footpath)> = straight_line
+        .iter()
+        .flat_map(|(stop_id, footpaths)| footpaths.iter().map(move |footpath| (stop_id, footpath)))
+        .collect();
+    footpaths.sort_by(|a, b| (a.0, &a.1.target_stop_id).cmp(&(b.0, &b.1.target_stop_id)));
+    for (stop_id, footpath) in footpaths {
+        (
+            stop_id,
+            &footpath.target_stop_id,
+            footpath.distance.to_bits(),
+            footpath.duration,
+        )
+            .hash(&mut hasher);
     }
-    // add nodes for trips and edges for vehicle movements including getting on and off of them
-    for trip in trips.values() {
-        let mut last = create_node_triplet(&mut nodes, &mut arrival_nodes, &mut departure_nodes, &trip.stop_times[0]);
-        for i in 1..trip.stop_times.len() - 1 {
-            let stop_time = trip.stop_times[i];
-            let mut new = create_node_triplet(&mut nodes, &mut arrival_nodes, &mut departure_nodes, &stop_time);
-            last.add_edge(new.node_id);
-            last = new;
+    format!(
+        "max_distance={} snap_distance={} inputs={:016x}",
+        MAX_ROUTED_PEDESTRIAN_DIST,
+        osm::SNAP_DISTANCE,
+        hasher.finish()
+    )
+}
+
+fn save_footpath_cache(
+    cache_path: &Path,
+    key: &str,
+    footpaths: &HashMap<String, Vec<Footpath>>,
+) -> Result<(), String> {
+    let mut file = File::create(cache_path).map_err(|e| e.to_string())?;
+    writeln!(file, "{}", key).map_err(|e| e.to_string())?;
+    let mut writer = csv::Writer::from_writer(file);
+    for (stop_id, footpaths) in footpaths {
+        for footpath in footpaths {
+            writer
+                .serialize(CachedFootpath {
+                    stop_id: stop_id.clone(),
+                    target_stop_id: footpath.target_stop_id.clone(),
+                    distance: footpath.distance,
+                    duration: footpath.duration,
+                })
+                .map_err(|e| e.to_string())?;
         }
     }
+    writer.flush().map_err(|e| e.to_string())
+}
 
-    return nodes;
+fn load_footpath_cache(
+    cache_path: &Path,
+    key: &str,
+) -> Result<HashMap<String, Vec<Footpath>>, String> {
+    let mut footpaths: HashMap<String, Vec<Footpath>> = HashMap::new();
+    let mut reader = BufReader::new(File::open(cache_path).map_err(|e| e.to_string())?);
+    let mut cached_key = String::new();
+    reader
+        .read_line(&mut cached_key)
+        .map_err(|e| e.to_string())?;
+    if cached_key.trim_end() != key {
+        return Err(String::from("The cache was computed from other inputs."));
+    }
+    let mut rdr = csv::Reader::from_reader(reader);
+    for result in rdr.deserialize() {
+        let record: CachedFootpath = result.map_err(|e| e.to_string())?;
+        footpaths
+            .entry(record.stop_id)
+            .or_insert_with(Vec::new)
+            .push(Footpath::new(
+                record.target_stop_id,
+                record.distance,
+                record.duration,
+            ));
+    }
+    Ok(footpaths)
 }
 
-// TODO simplify and make readable
-pub fn load_transport_network(path: &Path) -> Network {
-    let mut stops = load_stops(path);
-    let routes = load_routes(path);
-    let mut services = load_services(path);
-    load_service_exceptions(path, &mut services);
-    let mut trips = load_trips(path);
-    load_stop_times(path, &mut trips);
+#[test]
+fn test_footpath_cache() {
+    let cache_path = std::env::temp_dir().join("prahadlo_test_footpaths_cache.txt");
+    let mut footpaths = HashMap::new();
+    footpaths.insert(
+        String::from("MA3"),
+        vec![Footpath::new(String::from("T1"), 222.5, 122)],
+    );
+    let mut utm_coords = HashMap::new();
+    utm_coords.insert(String::from("MA3"), Point::new(458_000.0, 5_548_000.0));
+    utm_coords.insert(String::from("T1"), Point::new(458_200.0, 5_548_100.0));
+    let key = get_footpath_cache_key(&utm_coords, &footpaths);
+    save_footpath_cache(&cache_path, &key, &footpaths).unwrap();
+    let loaded = load_footpath_cache(&cache_path, &key).unwrap();
+    assert_eq!(loaded["MA3"][0].target_stop_id, "T1");
+    assert_eq!(loaded["MA3"][0].distance, 222.5);
+    assert_eq!(loaded["MA3"][0].duration, 122);
+    // the cache of moved stops or other connections is not used
+    utm_coords.insert(String::from("T1"), Point::new(458_300.0, 5_548_100.0));
+    assert_ne!(get_footpath_cache_key(&utm_coords, &footpaths), key);
+    assert!(load_footpath_cache(
+        &cache_path,
+        &get_footpath_cache_key(&utm_coords, &footpaths)
+    )
+    .is_err());
+    footpaths.insert(
+        String::from("T1"),
+        vec![Footpath::new(String::from("MA3"), 222.5, 122)],
+    );
+    assert_ne!(get_footpath_cache_key(&utm_coords, &footpaths), key);
+    std::fs::remove_file(&cache_path).unwrap();
+}
 
-    println!("Finalizing stops...");
-    for stop in stops.values_mut() {
-        stop.finalize(&mut nodes);
+/// Tells whether the file exists and was modified after all the other files
+fn is_newer(file: &Path, others: &[&Path]) -> bool {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    match modified(file) {
+        Some(file_modified) => others
+            .iter()
+            .all(|other| modified(other).map_or(true, |other| other <= file_modified)),
+        None => false,
     }
+}
 
-    println!("Calculating pedestrian connections...");
-    // TODO this takes ages! Speed up needed
+/// Measures the straight line pedestrian connections along the walkable ways of the
+/// OpenStreetMap extract in the feed directory, if there is one. The results are cached next to
+/// the extract if cached is set.
+fn route_pedestrian_connections(
+    path: &Path,
+    utm_coords: &HashMap<String, Point<f32>>,
+    straight_line: HashMap<String, Vec<Footpath>>,
+    cached: bool,
+) -> HashMap<String, Vec<Footpath>> {
+    let osm_path = path.join(OSM_FILE);
+    if !osm_path.exists() {
+        return straight_line;
+    }
+    let cache_path = path.join(FOOTPATH_CACHE_FILE);
+    let cache_key = get_footpath_cache_key(utm_coords, &straight_line);
+    if cached && is_newer(&cache_path, &[&osm_path, &path.join("stops.txt")]) {
+        match load_footpath_cache(&cache_path, &cache_key) {
+            Ok(footpaths) => return footpaths,
+            Err(message) => println!("Unable to load the cached footpaths: {}", message),
+        }
+    }
+    match osm::load_pedestrian_network(&osm_path) {
+        Ok(network) => {
+            let footpaths =
+                network.route_footpaths(straight_line, utm_coords, MAX_ROUTED_PEDESTRIAN_DIST);
+            if cached {
+                if let Err(message) = save_footpath_cache(&cache_path, &cache_key, &footpaths) {
+                    println!("Unable to cache the footpaths: {}", message);
+                }
+            }
+            footpaths
+        }
+        Err(message) => {
+            println!("Unable to load the pedestrian network: {}", message);
+            straight_line
+        }
+    }
+}
+
+#[test]
+fn test_routed_pedestrian_connections() {
+    let stops = load_stops(Path::new("test_data/network/"));
     let utm_coords = get_stop_coords_in_utm(&stops);
     let squares = calculate_proximity_squares(&utm_coords, MAX_PEDESTRIAN_DIST);
-    let connections = get_pedestrian_connections(&utm_coords, &squares, MAX_PEDESTRIAN_DIST);
+    let mut straight_line = get_pedestrian_connections(&utm_coords, &squares, MAX_PEDESTRIAN_DIST);
+    assert!(straight_line["MA3"]
+        .iter()
+        .any(|footpath| footpath.target_stop_id == "T1"));
+    // a stop too far from the ways
+    straight_line
+        .entry(String::from("MA1"))
+        .or_insert_with(Vec::new)
+        .push(Footpath::new(String::from("X"), 50.0, 74));
 
-    println!("Adding edges between arrival and departure nodes...");
-    for arr_node in arrival_nodes {
-        let arr_time = nodes[arr_node].get_time();
+    let network =
+        osm::load_pedestrian_network(Path::new("test_data/osm/malostranska.osm.pbf")).unwrap();
+    let routed = network.route_footpaths(straight_line, &utm_coords, MAX_ROUTED_PEDESTRIAN_DIST);
+    // the metro is on the other bank of the river than the tram, the bridge is far away
+    assert!(routed["MA3"].is_empty());
+    let to_station = routed["T1"]
+        .iter()
+        .find(|footpath| footpath.target_stop_id == "MAL")
+        .unwrap();
+    assert!(to_station.distance > 100.0 && to_station.distance < 130.0);
+    assert_eq!(
+        to_station.duration,
+        get_walking_duration(to_station.distance)
+    );
+    assert_eq!(routed["MA1"][0].target_stop_id, "X");
+}
+
+/// creates a node collection with depart node, arrival node and the actual node in the vehicle.
+/// The departure node is left out if passengers cannot board at the stop, the arrival node if they
+/// cannot alight there.
+/// Returns the id of the transport node created
+/// TODO can this be done without so many side effects?
+fn create_node_triplet(
+    nodes: &mut Vec<Node>,
+    arrival_nodes: &mut Vec<(usize, String)>,
+    stops: &mut HashMap<String, Stop>,
+    trip_index: usize,
+    stop_index: usize,
+    stop_time: &StopTime,
+) -> usize {
+    // the id is the node's position in the list, therefore we can use current list length as the id
+    let transport_id = nodes.len();
+    nodes.push(Node::new(
+        transport_id,
+        NodeKind::Transport,
+        stop_time.get_arrival_time(),
+        trip_index,
+        stop_index,
+    ));
+    if stop_time.allows_drop_off() {
+        let arr_id = nodes.len();
+        nodes.push(Node::new(
+            arr_id,
+            NodeKind::Arrival,
+            stop_time.get_arrival_time() + MINIMAL_TRANSFER_TIME,
+            trip_index,
+            stop_index,
+        ));
+        // a stop skipped by a realtime update keeps its nodes, so that the edges can be restored
+        if !stop_time.skipped {
+            nodes[transport_id].add_edge(arr_id);
+        }
+        // adding nodes to temporary list for further processing and edge-adding
+        arrival_nodes.push((arr_id, stop_time.stop_id.clone()));
+        stops
+            .get_mut(&stop_time.stop_id)
+            .unwrap()
+            .add_arr_node(arr_id);
+    }
+    if stop_time.allows_pickup() {
+        let dep_id = nodes.len();
+        nodes.push(Node::new(
+            dep_id,
+            NodeKind::Departure,
+            stop_time.get_departure_time(),
+            trip_index,
+            stop_index,
+        ));
+        if !stop_time.skipped {
+            nodes[dep_id].add_edge(transport_id);
+        }
+        stops
+            .get_mut(&stop_time.stop_id)
+            .unwrap()
+            .add_dep_node(dep_id)
+            .unwrap();
+    }
+    return transport_id;
+}
+
+/// The nodes of the trips before the transfers are added
+struct TripGraph {
+    nodes: Vec<Node>,
+    // the trip ids in the order used by Node::trip_index
+    trip_ids: Vec<String>,
+    // the ranges of node ids belonging to the trips
+    trip_nodes: Vec<(usize, usize)>,
+    // the arrival nodes together with their stops
+    arrival_nodes: Vec<(usize, String)>,
+}
+
+/// Adds nodes for trips and edges for vehicle movements including getting on and off of them
+fn create_nodes(stops: &mut HashMap<String, Stop>, trips: &HashMap<String, Trip>) -> TripGraph {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut arrival_nodes: Vec<(usize, String)> = Vec::new();
+    let mut trip_ids: Vec<String> = trips.keys().cloned().collect();
+    trip_ids.sort();
+    let mut trip_nodes = Vec::new();
+    for (trip_index, trip_id) in trip_ids.iter().enumerate() {
+        let first = nodes.len();
+        let mut last: Option<usize> = None;
+        for (stop_index, stop_time) in trips[trip_id].stop_times.iter().enumerate() {
+            let new = create_node_triplet(
+                &mut nodes,
+                &mut arrival_nodes,
+                stops,
+                trip_index,
+                stop_index,
+                stop_time,
+            );
+            if let Some(last) = last {
+                nodes[last].add_edge(new);
+            }
+            last = Some(new);
+        }
+        trip_nodes.push((first, nodes.len()));
+    }
+    return TripGraph {
+        nodes: nodes,
+        trip_ids: trip_ids,
+        trip_nodes: trip_nodes,
+        arrival_nodes: arrival_nodes,
+    };
+}
+
+/// Connects every arrival node to the earliest reachable departure at the same stop and at the
+/// stops within walking distance
+fn add_transfer_edges(
+    nodes: &mut Vec<Node>,
+    arrival_nodes: &Vec<(usize, String)>,
+    stops: &HashMap<String, Stop>,
+    footpaths: &HashMap<String, Vec<Footpath>>,
+) {
+    for (arr_node, stop_id) in arrival_nodes {
+        let arr_time = nodes[*arr_node].get_time();
         if let Some(dep) = stops
-            .get(&nodes[arr_node].stop_id)
+            .get(stop_id)
             .unwrap()
-            .get_earliest_dep(arr_time + MINIMAL_TRANSFER_TIME, &nodes)
+            .get_earliest_dep(arr_time, &nodes)
             .unwrap()
         {
-            nodes[arr_node].add_edge(Edge::new(
-                arr_time,
-                arr_time + MINIMAL_TRANSFER_TIME,
-                None,
-                dep,
-            ))
-        }
-        if let Some(conn_arr) = connections.get(&nodes[arr_node].stop_id) {
-            for conn in conn_arr {
-                let node_index = nodes.len();
-                let cost =
-                    (BASE_PEDESTRIAN_TRANSFER_TIME + conn.1 / PEDESTRIAN_SPEED).round() as u32;
-                let new_arr_node =
-                    Node::new(conn.0.clone(), node_index, NodeKind::Arr, arr_time + cost);
+            nodes[*arr_node].add_edge(dep);
+        }
+        if let Some(stop_footpaths) = footpaths.get(stop_id) {
+            for footpath in stop_footpaths {
                 if let Some(dep) = stops
-                    .get(&conn.0)
+                    .get(&footpath.target_stop_id)
                     .unwrap()
-                    .get_earliest_dep(arr_time + cost + MINIMAL_TRANSFER_TIME, &nodes)
+                    .get_earliest_dep(arr_time + footpath.duration, &nodes)
                     .unwrap()
                 {
-                    nodes[arr_node].add_edge(Edge::new(
-                        arr_time + cost,
-                        arr_time + cost + MINIMAL_TRANSFER_TIME,
-                        None,
-                        dep,
-                    ))
+                    nodes[*arr_node].add_edge(dep);
+                }
+            }
+        }
+    }
+}
+
+/// Creates the nodes and all the edges between them. The stops get their departure and arrival
+/// nodes. Returns the nodes, the trip ids in the order used by Node::trip_index and the ranges of
+/// node ids belonging to the trips.
+pub(crate) fn build_graph(
+    stops: &mut HashMap<String, Stop>,
+    trips: &HashMap<String, Trip>,
+    footpaths: &HashMap<String, Vec<Footpath>>,
+) -> (Vec<Node>, Vec<String>, Vec<(usize, usize)>) {
+    let mut graph = create_nodes(stops, trips);
+    for stop in stops.values_mut() {
+        stop.finalize(&mut graph.nodes);
+    }
+    add_transfer_edges(&mut graph.nodes, &graph.arrival_nodes, stops, footpaths);
+    return (graph.nodes, graph.trip_ids, graph.trip_nodes);
+}
+
+/// The contents of a single feed, before the feeds are merged into one network
+pub(crate) struct Feed {
+    pub(crate) agencies: HashMap<String, Agency>,
+    pub(crate) stops: HashMap<String, Stop>,
+    pub(crate) routes: HashMap<String, Route>,
+    pub(crate) services: HashMap<String, Service>,
+    pub(crate) trips: HashMap<String, Trip>,
+    pub(crate) levels: HashMap<String, Level>,
+    pub(crate) pathways: HashMap<String, Pathway>,
+    pub(crate) shapes: HashMap<String, Vec<ShapePoint>>,
+    pub(crate) fares: Fares,
+}
+
+pub(crate) fn load_feed(path: &Path) -> Feed {
+    let mut services = load_services(path);
+    load_service_exceptions(path, &mut services);
+    let mut trips = load_trips(path);
+    load_stop_times(path, &mut trips);
+    let frequencies = load_frequencies(path);
+    expand_frequencies(&mut trips, &frequencies);
+    Feed {
+        agencies: load_agencies(path),
+        stops: load_stops(path),
+        routes: load_routes(path),
+        services: services,
+        trips: trips,
+        levels: load_levels(path),
+        pathways: load_pathways(path),
+        shapes: load_shapes(path),
+        fares: load_fares(path),
+    }
+}
+
+impl Feed {
+    /// Prefixes all the ids of the feed, so that they do not collide with the ids of other feeds
+    fn add_prefix(&mut self, prefix: &str) {
+        self.agencies = rekey(std::mem::take(&mut self.agencies), |_, mut agency| {
+            // the single agency of a feed may have no id
+            let mut agency_id = agency.agency_id.take().unwrap_or_default();
+            add_prefix(prefix, &mut agency_id);
+            agency.agency_id = Some(agency_id.clone());
+            (agency_id, agency)
+        });
+        self.stops = rekey(std::mem::take(&mut self.stops), |_, mut stop| {
+            add_prefix(prefix, &mut stop.stop_id);
+            add_prefix_to_option(prefix, &mut stop.parent_station);
+            add_prefix_to_option(prefix, &mut stop.level_id);
+            add_prefix_to_zones(prefix, &mut stop.zone_id);
+            (stop.stop_id.clone(), stop)
+        });
+        self.routes = rekey(std::mem::take(&mut self.routes), |_, mut route| {
+            add_prefix(prefix, &mut route.route_id);
+            let mut agency_id = route.agency_id.take().unwrap_or_default();
+            add_prefix(prefix, &mut agency_id);
+            route.agency_id = Some(agency_id);
+            add_prefix_to_option(prefix, &mut route.network_id);
+            (route.route_id.clone(), route)
+        });
+        self.services = rekey(std::mem::take(&mut self.services), |_, mut service| {
+            add_prefix(prefix, &mut service.service_id);
+            for exception in &mut service.exceptions {
+                add_prefix(prefix, &mut exception.service_id);
+            }
+            (service.service_id.clone(), service)
+        });
+        self.trips = rekey(std::mem::take(&mut self.trips), |_, mut trip| {
+            add_prefix(prefix, &mut trip.trip_id);
+            add_prefix(prefix, &mut trip.route_id);
+            add_prefix(prefix, &mut trip.service_id);
+            add_prefix_to_option(prefix, &mut trip.block_id);
+            add_prefix_to_option(prefix, &mut trip.shape_id);
+            add_prefix_to_option(prefix, &mut trip.template_trip_id);
+            for stop_time in &mut trip.stop_times {
+                add_prefix(prefix, &mut stop_time.trip_id);
+                add_prefix(prefix, &mut stop_time.stop_id);
+            }
+            (trip.trip_id.clone(), trip)
+        });
+        self.levels = rekey(std::mem::take(&mut self.levels), |_, mut level| {
+            add_prefix(prefix, &mut level.level_id);
+            (level.level_id.clone(), level)
+        });
+        self.pathways = rekey(std::mem::take(&mut self.pathways), |_, mut pathway| {
+            add_prefix(prefix, &mut pathway.pathway_id);
+            add_prefix(prefix, &mut pathway.from_stop_id);
+            add_prefix(prefix, &mut pathway.to_stop_id);
+            (pathway.pathway_id.clone(), pathway)
+        });
+        self.shapes = rekey(std::mem::take(&mut self.shapes), |_, mut points| {
+            for point in points.iter_mut() {
+                add_prefix(prefix, &mut point.shape_id);
+            }
+            let shape_id = points
+                .first()
+                .map_or(String::new(), |point| point.shape_id.clone());
+            (shape_id, points)
+        });
+        self.fares.add_prefix(prefix);
+    }
+
+    /// Adds the contents of another feed, their ids must not collide with the ones of this feed
+    fn merge(&mut self, other: Feed) {
+        self.agencies.extend(other.agencies);
+        self.stops.extend(other.stops);
+        self.routes.extend(other.routes);
+        self.services.extend(other.services);
+        self.trips.extend(other.trips);
+        self.levels.extend(other.levels);
+        self.pathways.extend(other.pathways);
+        self.shapes.extend(other.shapes);
+        self.fares.merge(other.fares);
+    }
+}
+
+/// Returns the prefixes of the ids of the feeds, the names of their directories followed by a
+/// colon. Feeds in directories of the same name are numbered.
+pub(crate) fn get_feed_prefixes(paths: &[PathBuf]) -> Vec<String> {
+    let mut prefixes: Vec<String> = Vec::new();
+    for (index, path) in paths.iter().enumerate() {
+        let name = path
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let mut prefix = format!("{}:", name);
+        if name.is_empty() || prefixes.contains(&prefix) {
+            prefix = format!("{}{}:", name, index + 1);
+        }
+        prefixes.push(prefix);
+    }
+    prefixes
+}
+
+#[test]
+fn test_feed_prefixes() {
+    let paths = vec![
+        PathBuf::from("data/pid/"),
+        PathBuf::from("data/cd"),
+        PathBuf::from("other/pid"),
+    ];
+    assert_eq!(get_feed_prefixes(&paths), vec!["pid:", "cd:", "pid3:"]);
+}
+
+/// Normalizes the name of a stop for matching the stations of different feeds, only the letters
+/// and the digits are compared regardless of their case
+fn normalize_stop_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Merges the stations of different feeds with the same name that are close to each other into
+/// shared stations, so that they can be searched from and to as one place. Stops without a
+/// station are merged as well, a new station is created for them if none of them is a station.
+/// The platforms are moved to the station of the earliest feed and the other stations removed.
+/// # Arguments
+/// * stops - the stops of all the feeds
+/// * fare_products - the fare products of all the feeds, the areas of the removed stations are
+///   copied to their platforms
+/// * feed_indices - the index of the feed of each stop
+fn merge_stations(
+    stops: &mut HashMap<String, Stop>,
+    fare_products: &mut FareProducts,
+    feed_indices: &HashMap<String, usize>,
+) {
+    // the stations and the stops without one, by their names
+    let mut by_name: HashMap<String, Vec<&Stop>> = HashMap::new();
+    for stop in stops.values() {
+        if stop.location_type == 1 || (stop.location_type == 0 && stop.parent_station.is_none()) {
+            by_name
+                .entry(normalize_stop_name(&stop.stop_name))
+                .or_insert_with(Vec::new)
+                .push(stop);
+        }
+    }
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    for candidates in by_name.values_mut() {
+        // the earliest feed first, its stations before its stops
+        candidates.sort_by_key(|stop| {
+            (
+                feed_indices[&stop.stop_id],
+                stop.location_type != 1,
+                &stop.stop_id,
+            )
+        });
+        let mut group_of: Vec<Option<usize>> = vec![None; candidates.len()];
+        for i in 0..candidates.len() {
+            for j in 0..i {
+                let close = match (candidates[i].get_coords(), candidates[j].get_coords()) {
+                    (Some(a), Some(b)) => get_distance(a, b) <= MAX_STATION_MERGE_DIST,
+                    _ => false,
+                };
+                let other_feed =
+                    feed_indices[&candidates[i].stop_id] != feed_indices[&candidates[j].stop_id];
+                if close && other_feed && group_of[i].is_none() {
+                    let group = match group_of[j] {
+                        Some(group) => group,
+                        None => {
+                            groups.push(vec![candidates[j].stop_id.clone()]);
+                            group_of[j] = Some(groups.len() - 1);
+                            groups.len() - 1
+                        }
+                    };
+                    groups[group].push(candidates[i].stop_id.clone());
+                    group_of[i] = Some(group);
+                }
+            }
+        }
+    }
+    for group in groups {
+        let station_id = if stops[&group[0]].location_type == 1 {
+            group[0].clone()
+        } else {
+            let mut station = stops[&group[0]].clone();
+            station.stop_id = format!("{}:station", group[0]);
+            station.location_type = 1;
+            station.level_id = None;
+            station.platform_code = None;
+            let station_id = station.stop_id.clone();
+            stops.insert(station_id.clone(), station);
+            station_id
+        };
+        for stop_id in group.into_iter().filter(|stop_id| *stop_id != station_id) {
+            if stops[&stop_id].location_type == 1 {
+                // the platforms keep the zone and the fare areas of their original station
+                let zone_id = stops[&stop_id].zone_id.clone();
+                for stop in stops.values_mut() {
+                    if stop.parent_station.as_ref() == Some(&stop_id) {
+                        stop.parent_station = Some(station_id.clone());
+                        stop.merged = true;
+                        if stop.zone_id.is_none() {
+                            stop.zone_id = zone_id.clone();
+                        }
+                        fare_products.copy_stop_areas(&stop_id, &stop.stop_id);
+                    }
                 }
+                stops.remove(&stop_id);
+            } else {
+                let stop = stops.get_mut(&stop_id).unwrap();
+                stop.parent_station = Some(station_id.clone());
+                stop.merged = true;
             }
         }
     }
+}
+
+/// Loads the feed from the given directory into a network
+pub fn load_transport_network(path: &Path) -> Network {
+    load_merged_transport_network(&[path.to_path_buf()])
+}
+
+/// Returns the feed in the given directory, or the feeds in its subdirectories if it contains
+/// none itself
+pub fn find_feeds(path: &Path) -> Vec<PathBuf> {
+    if path.join("stops.txt").exists() {
+        return vec![path.to_path_buf()];
+    }
+    let mut feeds: Vec<PathBuf> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.join("stops.txt").exists())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    feeds.sort();
+    feeds
+}
+
+/// Loads several feeds into one network. The ids of each feed are prefixed by the name of its
+/// directory and a colon, the stations of the feeds are merged and the pedestrian connections
+/// are found between the stops of all the feeds. A single feed keeps its ids. The OpenStreetMap
+/// extract is taken from the directory of the first feed.
+pub fn load_merged_transport_network(paths: &[PathBuf]) -> Network {
+    let mut feed: Option<Feed> = None;
+    let mut feed_indices = HashMap::new();
+    for (index, (path, prefix)) in paths.iter().zip(get_feed_prefixes(paths)).enumerate() {
+        let mut loaded = load_feed(path);
+        if paths.len() > 1 {
+            loaded.add_prefix(&prefix);
+        }
+        feed_indices.extend(loaded.stops.keys().map(|stop_id| (stop_id.clone(), index)));
+        match &mut feed {
+            Some(feed) => feed.merge(loaded),
+            None => feed = Some(loaded),
+        }
+    }
+    let mut feed = feed.expect("No feed to load.");
+    if paths.len() > 1 {
+        merge_stations(&mut feed.stops, &mut feed.fares.products, &feed_indices);
+    }
+
+    println!("Calculating pedestrian connections...");
+    // TODO this takes ages! Speed up needed
+    let utm_coords = get_stop_coords_in_utm(&feed.stops);
+    let squares = calculate_proximity_squares(&utm_coords, MAX_PEDESTRIAN_DIST);
+    let footpaths = get_pedestrian_connections(&utm_coords, &squares, MAX_PEDESTRIAN_DIST);
+    // the cache is only valid for the stops of a single feed
+    let mut footpaths =
+        route_pedestrian_connections(&paths[0], &utm_coords, footpaths, paths.len() == 1);
+    add_pathway_connections(
+        &mut footpaths,
+        get_pathway_connections(&feed.stops, &feed.pathways),
+    );
+    let cycling_squares = calculate_proximity_squares(&utm_coords, CYCLING_SQUARE_SIZE);
+    let proximity_index = ProximityIndex::new(utm_coords, cycling_squares, CYCLING_SQUARE_SIZE);
+
+    println!("Building the graph...");
+    let (nodes, trip_ids, trip_nodes) = build_graph(&mut feed.stops, &feed.trips, &footpaths);
+
+    let mut network = Network::new(
+        feed.agencies,
+        feed.stops,
+        feed.routes,
+        feed.trips,
+        feed.services,
+        trip_ids,
+        trip_nodes,
+        footpaths,
+        feed.pathways,
+        feed.levels,
+        proximity_index,
+        feed.shapes,
+        nodes,
+    );
+    network.set_fares(feed.fares);
+    return network;
+}
+
+#[test]
+fn test_merged_feeds() {
+    let paths = vec![
+        PathBuf::from("test_data/network/"),
+        PathBuf::from("test_data/rail/"),
+    ];
+    let network = load_merged_transport_network(&paths);
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // both feeds have a stop MA1, a route LA and a trip LA_1
+    assert_eq!(
+        network.get_stop("network:MA1").unwrap().stop_name,
+        "Dejvická"
+    );
+    assert_eq!(
+        network.get_stop("rail:MA1").unwrap().stop_name,
+        "Kralupy nad Vltavou"
+    );
+    assert!(network.get_stop("MA1").is_none());
+    assert_eq!(
+        network.get_route("rail:LA").unwrap().agency_id,
+        Some(String::from("rail:cd"))
+    );
+    // the rail station Dejvická gets a station shared with the metro, the metro Malostranská
+    // station takes over the platform of the rail station of the same name
+    let dejvicka = network.get_stop("network:MA1:station").unwrap();
+    assert_eq!(dejvicka.location_type, 1);
+    assert_eq!(
+        network.get_stop("rail:DEJ").unwrap().parent_station,
+        Some(String::from("network:MA1:station"))
+    );
+    assert!(network.get_stop("rail:MAL").is_none());
+    assert_eq!(
+        network.get_stop("rail:MAL1").unwrap().parent_station,
+        Some(String::from("network:MAL"))
+    );
+    // Florenc is too far from the rail station of the same name
+    assert_eq!(network.get_stop("rail:FLO").unwrap().parent_station, None);
+    // the moved platforms keep the zones of their feed
+    assert_eq!(network.get_zone_id("rail:MAL1"), None);
+    assert_eq!(network.get_zone_id("rail:DEJ"), None);
+    assert_eq!(
+        network.get_zone_id("network:T1"),
+        Some(&String::from("network:P"))
+    );
+    // the train connects to the metro by a walk between the feeds
+    let itinerary = network
+        .find_connection(
+            "rail:MA1",
+            "network:MA3",
+            monday,
+            7 * 3600 + 30 * 60,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("rail:LA_1")));
+    assert_eq!(itinerary.legs[1].from_stop_id, "rail:DEJ");
+    assert_eq!(itinerary.legs[1].to_stop_id, "network:MA1");
+    assert_eq!(
+        itinerary.legs[2].trip_id,
+        Some(String::from("network:LA_1"))
+    );
+    // the stations are searched as one
+    assert!(network
+        .find_connection(
+            "rail:MA1",
+            "network:MA1:station",
+            monday,
+            7 * 3600 + 30 * 60,
+            &SearchOptions::default()
+        )
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_connection_search() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs.len(), 3);
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("LA_1")));
+    assert!(itinerary.legs[1].is_walk());
+    assert_eq!(itinerary.legs[1].from_stop_id, "MA3");
+    assert_eq!(itinerary.legs[1].to_stop_id, "T1");
+    assert_eq!(itinerary.legs[2].trip_id, Some(String::from("L22_1")));
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 16 * 60));
+    assert_eq!(itinerary.transfer_count(), 1);
+    // the service does not run on weekends and on Easter Monday
+    let saturday = NaiveDate::from_ymd(2020, 2, 1);
+    assert!(network
+        .find_connection("MA1", "T4", saturday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_none());
+    let easter_monday = NaiveDate::from_ymd(2020, 4, 13);
+    assert!(network
+        .find_connection(
+            "MA1",
+            "T4",
+            easter_monday,
+            8 * 3600,
+            &SearchOptions::default()
+        )
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_departures() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // the metro trips end at Malostranská, only the trams depart from the station
+    let departures = network
+        .departures("MAL", monday, 8 * 3600, 5, &SearchOptions::default())
+        .unwrap();
+    assert_eq!(departures.len(), 2);
+    assert_eq!(departures[0].trip_id, "L22_1");
+    assert_eq!(departures[0].route_short_name, "22");
+    assert_eq!(
+        departures[0].headsign,
+        Some(String::from("Právnická fakulta"))
+    );
+    assert_eq!(departures[0].platform_code, Some(String::from("A")));
+    assert_eq!(departures[1].headsign, Some(String::from("Právnická fak.")));
+    assert_eq!(departures[1].departure_time, 8 * 3600 + 20 * 60);
+    let departures = network
+        .departures("MA1", monday, 8 * 3600, 1, &SearchOptions::default())
+        .unwrap();
+    assert_eq!(departures.len(), 1);
+    assert_eq!(departures[0].trip_id, "LA_1");
+    assert!(network
+        .departures("XX", monday, 8 * 3600, 1, &SearchOptions::default())
+        .is_err());
+}
+
+#[test]
+fn test_arrivals_and_trip_detail() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // the trams start at Malostranská, only the metro trips arrive to the station
+    let arrivals = network
+        .arrivals("MAL", monday, 8 * 3600, 5, &SearchOptions::default())
+        .unwrap();
+    assert_eq!(arrivals.len(), 2);
+    assert_eq!(arrivals[0].trip_id, "LA_1");
+    assert_eq!(arrivals[0].origin, "Dejvická");
+    assert_eq!(arrivals[0].platform_code, Some(String::from("1")));
+    assert_eq!(arrivals[1].arrival_time, 8 * 3600 + 9 * 60);
+    assert!(network
+        .arrivals("MAL", monday, 9 * 3600, 5, &SearchOptions::default())
+        .unwrap()
+        .is_empty());
+
+    let detail = network.trip_detail("L22_1", monday).unwrap();
+    assert!(detail.runs);
+    assert_eq!(detail.route_short_name, "22");
+    let names: Vec<&str> = detail
+        .stops
+        .iter()
+        .map(|stop| stop.stop_name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        vec!["Malostranská", "Klárov", "Čechův most", "Právnická fakulta"]
+    );
+    assert_eq!(detail.stops[3].arrival_time, 8 * 3600 + 16 * 60);
+    let saturday = NaiveDate::from_ymd(2020, 2, 1);
+    assert!(!network.trip_detail("L22_1", saturday).unwrap().runs);
+    assert!(network.trip_detail("XX", monday).is_err());
+}
+
+#[test]
+fn test_wheelchair_routing() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let wheelchair = SearchOptions {
+        wheelchair: true,
+        ..SearchOptions::default()
+    };
+    // Malostranská inherits the accessibility from its station
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &wheelchair)
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 16 * 60));
+    assert!(!itinerary.is_accessibility_uncertain());
+    // L22_2 is not accessible and Klárov is not accessible
+    assert!(network
+        .find_connection("MA1", "T4", monday, 8 * 3600 + 5 * 60, &wheelchair)
+        .unwrap()
+        .is_none());
+    assert!(network
+        .find_connection("T1", "T2", monday, 8 * 3600, &wheelchair)
+        .unwrap()
+        .is_none());
+    // the accessibility of Hlavní nádraží is unknown
+    let itinerary = network
+        .find_connection("MC1", "MC2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert!(itinerary.is_accessibility_uncertain());
+    assert!(network
+        .find_connection("MC1", "MC2", monday, 8 * 3600, &wheelchair)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_pathway_transfers() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // the vestibule is a generic node without a position, it is only reached by the pathways
+    assert_eq!(network.get_stop("MAN").unwrap().get_coords(), None);
+    assert!(network.get_footpaths("MAN").is_empty());
+    // the escalator is faster than the stairs, it only goes up
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let walk = &itinerary.legs[1];
+    assert_eq!(walk.arrival_time - walk.departure_time, 140);
+    assert_eq!(walk.distance, 221.0);
+    assert_eq!(walk.wheelchair_accessible, Some(false));
+    assert_eq!(
+        walk.directions,
+        vec![
+            "Take the escalator to Vestibul (level Ulice), follow signs for Výstup",
+            "Walk to Malostranská (level Ulice), follow signs for Tramvaj",
+        ]
+    );
+    let down = network
+        .get_footpaths("T1")
+        .iter()
+        .find(|footpath| footpath.target_stop_id == "MA3")
+        .unwrap();
+    assert_eq!(down.duration, 170);
+    assert_eq!(
+        down.pathways,
+        vec![
+            (String::from("MAL_WALKWAY"), true),
+            (String::from("MAL_STAIRS"), true)
+        ]
+    );
+    // wheelchair users take the elevator
+    let wheelchair = SearchOptions {
+        wheelchair: true,
+        ..SearchOptions::default()
+    };
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &wheelchair)
+        .unwrap()
+        .unwrap();
+    let walk = &itinerary.legs[1];
+    assert_eq!(walk.arrival_time - walk.departure_time, 320);
+    assert_eq!(walk.wheelchair_accessible, Some(true));
+    assert_eq!(
+        walk.directions,
+        vec![
+            "Take the elevator to Vestibul (level Ulice)",
+            "Walk to Malostranská (level Ulice), follow signs for Tramvaj",
+        ]
+    );
+}
+
+#[test]
+fn test_fare_calculation() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // the single metro ride is cheaper than the time ticket
+    let itinerary = network
+        .find_connection("MA1", "MA3", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let fare = itinerary.fare.unwrap();
+    assert_eq!(fare.price, 20.0);
+    assert_eq!(fare.currency, "CZK");
+    assert_eq!(fare.tickets[0].fare_id, "METRO");
+    // the tram goes to zone 0, one ticket for both rides is cheaper than two
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let fare = itinerary.fare.as_ref().unwrap();
+    assert_eq!(fare.price, 40.0);
+    assert_eq!(fare.tickets[0].legs, vec![0, 2]);
+    let zone_fare = itinerary.zone_fare.as_ref().unwrap();
+    assert_eq!(zone_fare.price, 30.0);
+    assert_eq!(
+        zone_fare.tickets[0].name,
+        Some(String::from("Krátkodobá 30 minut"))
+    );
+    assert!(itinerary
+        .to_string()
+        .contains("Zone fare: 30.00 CZK (Krátkodobá 30 minut)"));
+}
+
+#[test]
+fn test_bicycle_routing() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let bikes = SearchOptions {
+        bikes: true,
+        ..SearchOptions::default()
+    };
+    // the trams do not carry bicycles
+    assert!(network
+        .find_connection("MA1", "MA3", monday, 8 * 3600, &bikes)
+        .unwrap()
+        .is_some());
+    assert!(network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &bikes)
+        .unwrap()
+        .is_none());
+    // Florenc is too far to walk from Malostranská, but not to cycle
+    assert!(network
+        .find_connection("MA1", "MC2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_none());
+    let cycling = SearchOptions {
+        cycling: Some(Cycling::default()),
+        ..SearchOptions::default()
+    };
+    // the bicycle is taken on the trams too
+    assert!(network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &cycling)
+        .unwrap()
+        .is_none());
+    let itinerary = network
+        .find_connection("MA1", "MC2", monday, 8 * 3600, &cycling)
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs.len(), 3);
+    assert!(itinerary.legs[1].cycling);
+    assert_eq!(itinerary.legs[1].from_stop_id, "MA3");
+    assert_eq!(itinerary.legs[1].to_stop_id, "MC1");
+    assert!(itinerary.legs[1].distance > 2000.0);
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 22 * 60));
+    let short_rides = SearchOptions {
+        cycling: Some(Cycling {
+            max_distance: 500.0,
+            ..Cycling::default()
+        }),
+        ..SearchOptions::default()
+    };
+    assert!(network
+        .find_connection("MA1", "MC2", monday, 8 * 3600, &short_rides)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_mode_and_route_filtering() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let metro_and_tram = SearchOptions {
+        allowed_modes: vec![Mode::Subway, Mode::Tram],
+        ..SearchOptions::default()
+    };
+    assert!(network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &metro_and_tram)
+        .unwrap()
+        .is_some());
+    let no_trams = SearchOptions {
+        denied_modes: vec![Mode::Tram],
+        ..SearchOptions::default()
+    };
+    assert!(network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &no_trams)
+        .unwrap()
+        .is_none());
+    let no_line_22 = SearchOptions {
+        denied_routes: vec![String::from("L22")],
+        ..SearchOptions::default()
+    };
+    assert!(network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &no_line_22)
+        .unwrap()
+        .is_none());
+    let only_line_a = SearchOptions {
+        allowed_routes: vec![String::from("LA")],
+        ..SearchOptions::default()
+    };
+    assert!(network
+        .find_connection("MA1", "MA3", monday, 8 * 3600, &only_line_a)
+        .unwrap()
+        .is_some());
+    let other_agency = SearchOptions {
+        allowed_agencies: vec![String::from("1")],
+        ..SearchOptions::default()
+    };
+    assert!(network
+        .find_connection("MA1", "MA3", monday, 8 * 3600, &other_agency)
+        .unwrap()
+        .is_none());
+    // the boards use the same filters
+    assert_eq!(
+        network
+            .departures("MAL", monday, 8 * 3600, 5, &no_line_22)
+            .unwrap()
+            .len(),
+        0
+    );
+}
+
+#[test]
+fn test_night_lines() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let with_night_lines = |night_lines| SearchOptions {
+        night_lines: night_lines,
+        ..SearchOptions::default()
+    };
+    let search = |night_lines| {
+        network
+            .find_connection(
+                "MA1",
+                "MA2",
+                monday,
+                7 * 3600 + 45 * 60,
+                &with_night_lines(night_lines),
+            )
+            .unwrap()
+            .unwrap()
+    };
+    // the night bus leaves earlier, but the metro arrives first
+    let itinerary = search(NightLines::Any);
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("LA_1")));
+    assert!(!itinerary.legs[0].night);
+    let itinerary = search(NightLines::Prefer);
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("LN_1")));
+    assert!(itinerary.legs[0].night);
+    assert_eq!(
+        search(NightLines::Require).legs[0].trip_id,
+        Some(String::from("LN_1"))
+    );
+    assert_eq!(
+        search(NightLines::Avoid).legs[0].trip_id,
+        Some(String::from("LA_1"))
+    );
+    // without a night line connection, the preference falls back to the fastest one
+    let options = with_night_lines(NightLines::Prefer);
+    let itinerary = network
+        .find_connection("MA1", "MA3", monday, 7 * 3600 + 45 * 60, &options)
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("LA_1")));
+    let options = with_night_lines(NightLines::Require);
+    assert!(network
+        .find_connection("MA1", "MA3", monday, 7 * 3600 + 45 * 60, &options)
+        .unwrap()
+        .is_none());
 
-    return Network::new(stops, routes, trips, services, nodes);
+    let departures = network
+        .departures(
+            "MA1",
+            monday,
+            7 * 3600,
+            5,
+            &with_night_lines(NightLines::Require),
+        )
+        .unwrap();
+    assert_eq!(departures.len(), 1);
+    assert_eq!(departures[0].trip_id, "LN_1");
+}
+
+#[test]
+fn test_via_and_avoided_stops() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let search = |dep: &str, target: &str, options: &SearchOptions| {
+        network
+            .find_connection(dep, target, monday, 8 * 3600, options)
+            .unwrap()
+    };
+    let via = |stop_id: &str, min_dwell_time| SearchOptions {
+        via: vec![Via {
+            stop_id: String::from(stop_id),
+            min_dwell_time: min_dwell_time,
+        }],
+        ..SearchOptions::default()
+    };
+    // staying at Hradčanská makes the first tram unreachable
+    let itinerary = search("MA1", "T4", &via("MA2", 4 * 60)).unwrap();
+    assert_eq!(itinerary.legs[0].to_stop_id, "MA2");
+    assert_eq!(itinerary.legs[1].trip_id, Some(String::from("LA_2")));
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 26 * 60));
+    // a via station is reached at any of its platforms
+    let itinerary = search("MA1", "T4", &via("MAL", 0)).unwrap();
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 16 * 60));
+    // stations can be searched from and to
+    let itinerary = search("MAL", "T4", &SearchOptions::default()).unwrap();
+    assert_eq!(itinerary.legs[0].from_stop_id, "T1");
+    let itinerary = search("MA1", "MAL", &SearchOptions::default()).unwrap();
+    assert_eq!(itinerary.legs[0].to_stop_id, "MA3");
+
+    let avoiding = |stop_id: &str| SearchOptions {
+        avoided_stops: vec![String::from(stop_id)],
+        ..SearchOptions::default()
+    };
+    // the tram passes through Klárov, the metro through Hradčanská
+    assert!(search("MA1", "T4", &avoiding("T2")).is_none());
+    assert!(search("MA1", "T4", &avoiding("MA2")).is_none());
+    // avoiding a station avoids all its platforms
+    assert!(search("MA1", "T4", &avoiding("MAL")).is_none());
+    assert!(search("MA1", "MA3", &avoiding("T1")).is_some());
+}
+
+#[test]
+fn test_transfer_and_walking_limits() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let search =
+        |options: &SearchOptions| network.find_connection("MA1", "T4", monday, 8 * 3600, options);
+    // the metro and the tram are connected by a walk of about 220 m
+    let limited = SearchOptions {
+        max_transfers: Some(1),
+        max_walking_distance: Some(300.0),
+        max_walking_leg_distance: Some(300.0),
+        ..SearchOptions::default()
+    };
+    let itinerary = search(&limited).unwrap().unwrap();
+    assert_eq!(itinerary.transfer_count(), 1);
+    assert!(itinerary.walking_distance() <= 300.0);
+    let no_transfers = SearchOptions {
+        max_transfers: Some(0),
+        ..SearchOptions::default()
+    };
+    assert_eq!(search(&no_transfers).unwrap_err(), NO_JOURNEY_WITHIN_LIMITS);
+    let short_walks = SearchOptions {
+        max_walking_leg_distance: Some(100.0),
+        ..SearchOptions::default()
+    };
+    assert_eq!(search(&short_walks).unwrap_err(), NO_JOURNEY_WITHIN_LIMITS);
+    let little_walking = SearchOptions {
+        max_walking_distance: Some(100.0),
+        ..SearchOptions::default()
+    };
+    assert_eq!(
+        search(&little_walking).unwrap_err(),
+        NO_JOURNEY_WITHIN_LIMITS
+    );
+    assert_eq!(
+        network
+            .find_connection_arriving_by("MA1", "T4", monday, 8 * 3600 + 30 * 60, &no_transfers)
+            .unwrap_err(),
+        NO_JOURNEY_WITHIN_LIMITS
+    );
+    // without any connection the limits are not to blame
+    assert!(network
+        .find_connection("MA1", "T4", monday, 20 * 3600, &no_transfers)
+        .unwrap()
+        .is_none());
+
+    // the limits hold for the whole journey through the via stops, getting off at Hradčanská
+    // makes three rides
+    let via = |max_transfers| SearchOptions {
+        via: vec![Via {
+            stop_id: String::from("MA2"),
+            min_dwell_time: 0,
+        }],
+        max_transfers: Some(max_transfers),
+        ..SearchOptions::default()
+    };
+    assert_eq!(search(&via(2)).unwrap().unwrap().transfer_count(), 2);
+    assert_eq!(search(&via(1)).unwrap_err(), NO_JOURNEY_WITHIN_LIMITS);
+
+    // the earliest arrival to the via stop needs a transfer, the direct bus arrives later but
+    // leaves one for the rest of the journey
+    let network = load_transport_network(Path::new("test_data/via/"));
+    let options = SearchOptions {
+        via: vec![Via {
+            stop_id: String::from("B"),
+            min_dwell_time: 0,
+        }],
+        max_transfers: Some(1),
+        ..SearchOptions::default()
+    };
+    let itinerary = network
+        .find_connection("A", "C", monday, 8 * 3600, &options)
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("L3_1")));
+    assert_eq!(itinerary.transfer_count(), 1);
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 30 * 60));
+}
+
+#[test]
+fn test_coordinate_search() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // next to Dejvická and next to Právnická fakulta
+    let from = Place::parse("50.0805,14.4005").unwrap();
+    let to = Place::parse("50.0825,14.4505").unwrap();
+    assert_eq!(from, Place::Coordinates(Point::new(14.4005, 50.0805)));
+    assert_eq!(Place::parse("Dejvická::50.0805,14.4005"), Ok(from.clone()));
+    assert_eq!(Place::parse("MA1"), Ok(Place::Stop(String::from("MA1"))));
+    assert!(Place::parse("nan,nan").is_err());
+    assert!(Place::parse("1000,1000").is_err());
+    assert!(Place::parse("inf,0").is_err());
+    let search = |from: &Place, to: &Place, time| {
+        network
+            .find_connection_between_places(from, to, monday, time, &SearchOptions::default())
+            .unwrap()
+    };
+    let itinerary = search(&from, &to, 7 * 3600 + 59 * 60).unwrap();
+    let (first, last) = (&itinerary.legs[0], itinerary.legs.last().unwrap());
+    assert!(first.is_walk());
+    assert_eq!(first.from_stop_id, "50.08050,14.40050");
+    assert_eq!(first.to_stop_id, "MA1");
+    assert_eq!(first.arrival_time, 8 * 3600);
+    assert_eq!(itinerary.legs[1].trip_id, Some(String::from("LA_1")));
+    assert!(last.is_walk());
+    assert_eq!(last.from_stop_id, "T4");
+    assert_eq!(last.to_stop_id, "50.08250,14.45050");
+    assert_eq!(last.departure_time, 8 * 3600 + 16 * 60);
+    assert!(last.distance > 0.0 && last.distance < 200.0);
+    // a stop and coordinates can be combined
+    let dejvicka = Place::Stop(String::from("MA1"));
+    let itinerary = search(&dejvicka, &to, 8 * 3600).unwrap();
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("LA_1")));
+    assert_eq!(itinerary.legs.last().unwrap().from_stop_id, "T4");
+
+    // walking between Dejvická and Hradčanská is faster than waiting for the metro
+    let near_dejvicka = Place::parse("50.08,14.401").unwrap();
+    let near_hradcanska = Place::parse("50.08,14.409").unwrap();
+    let itinerary = search(&near_dejvicka, &near_hradcanska, 7 * 3600 + 59 * 60).unwrap();
+    assert_eq!(itinerary.legs.len(), 1);
+    assert!(itinerary.legs[0].is_walk());
+    assert!(itinerary.arrival_time().unwrap() < 8 * 3600 + 2 * 60);
+    // unless it is too long
+    assert!(search(&Place::parse("50.0,14.0").unwrap(), &to, 8 * 3600).is_none());
+
+    let itinerary = network
+        .find_connection_between_places_arriving_by(
+            &from,
+            &to,
+            monday,
+            8 * 3600 + 20 * 60,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[1].trip_id, Some(String::from("LA_1")));
+    assert_eq!(itinerary.legs[0].arrival_time, 8 * 3600);
+}
+
+#[test]
+fn test_pickup_and_drop_off_types() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    // L22_1 does not let passengers off at T3, so the later L22_2 has to be taken
+    let itinerary = network
+        .find_connection("MA1", "T3", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let last = itinerary.legs.last().unwrap();
+    assert_eq!(last.trip_id, Some(String::from("L22_2")));
+    assert!(!last.is_request_stop());
+    // T2 is a request stop
+    let itinerary = network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let last = itinerary.legs.last().unwrap();
+    assert!(last.drop_off_on_request);
+    assert!(!last.pickup_on_request);
+    assert!(last.is_request_stop());
+    // L22_2 does not pick up passengers at T3
+    assert!(network
+        .find_connection(
+            "T3",
+            "T4",
+            monday,
+            8 * 3600 + 15 * 60,
+            &SearchOptions::default()
+        )
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_frequency_based_connection() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let itinerary = network
+        .find_connection(
+            "MC1",
+            "MC2",
+            monday,
+            8 * 3600 + 3 * 60,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        itinerary.legs[0].trip_id,
+        Some(String::from("LC_T_08:10:00"))
+    );
+    assert_eq!(itinerary.legs[0].headway, None);
+    let itinerary = network
+        .find_connection(
+            "MC2",
+            "MC1",
+            monday,
+            8 * 3600 + 3 * 60,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].headway, Some(300));
+    // the runs without exact times are boarded after waiting for half of the headway
+    assert_eq!(
+        itinerary.legs[0].trip_id,
+        Some(String::from("LC_E_08:05:00"))
+    );
+    assert_eq!(itinerary.legs[0].departure_time, 8 * 3600 + 5 * 60 + 30);
+    assert_eq!(itinerary.legs[0].arrival_time, 8 * 3600 + 7 * 60 + 30);
+    // the next run comes later than the expected wait
+    let itinerary = network
+        .find_connection(
+            "MC2",
+            "MC1",
+            monday,
+            8 * 3600 + 60,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].departure_time, 8 * 3600 + 5 * 60);
+    let departures = network
+        .departures("MC2", monday, 8 * 3600, 3, &SearchOptions::default())
+        .unwrap();
+    let times: Vec<u32> = departures
+        .iter()
+        .filter(|departure| departure.route_id == "LC" && departure.trip_id.starts_with("LC_E"))
+        .map(|departure| departure.departure_time)
+        .collect();
+    assert_eq!(times, vec![8 * 3600, 8 * 3600 + 5 * 60, 8 * 3600 + 10 * 60]);
+}
+
+#[test]
+fn test_leg_geometry() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let itinerary = network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let metro = itinerary.legs[0].geometry.as_ref().unwrap();
+    assert_eq!(metro.0.len(), 5);
+    assert_eq!(metro.0[4].x, 14.42);
+    let walk = itinerary.legs[1].geometry.as_ref().unwrap();
+    assert_eq!(walk.0.len(), 2);
+    // the tram is only taken for a part of its shape
+    let tram = itinerary.legs[2].geometry.as_ref().unwrap();
+    assert_eq!(tram.0.len(), 3);
+    assert_eq!(tram.0[0].x, 14.42);
+    assert_eq!(tram.0[2].x, 14.43);
+    let geojson = to_geojson(tram);
+    assert_eq!(geojson["type"], "LineString");
+    assert_eq!(geojson["coordinates"].as_array().unwrap().len(), 3);
+    // line C has no shape
+    let itinerary = network
+        .find_connection("MC1", "MC2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert!(itinerary.legs[0].geometry.is_none());
+}
+
+#[test]
+fn test_leg_distance() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let itinerary = network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    // from shape_dist_traveled
+    let metro = get_distance(Point::new(14.40, 50.08), Point::new(14.42, 50.08));
+    assert!((itinerary.legs[0].distance - metro).abs() < 1.0);
+    // from the pedestrian connection
+    assert!(itinerary.legs[1].distance > 200.0 && itinerary.legs[1].distance < 250.0);
+    assert_eq!(itinerary.walking_distance(), itinerary.legs[1].distance);
+    let tram = get_distance(Point::new(14.42, 50.082), Point::new(14.43, 50.082));
+    assert!((itinerary.legs[2].distance - tram).abs() < 1.0);
+    // line C has no shape, so the stop coordinates are used
+    let itinerary = network
+        .find_connection("MC1", "MC2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    let metro = get_distance(Point::new(14.44, 50.09), Point::new(14.45, 50.09));
+    assert!((itinerary.distance() - metro).abs() < 1.0);
 }
@@ -0,0 +1,520 @@
+use crate::model::data_structures::*;
+use crate::model::loader;
+use core::cmp::Reverse;
+use flate2::read::ZlibDecoder;
+use geo_types::Point;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+// This file reads the walkable ways from an OpenStreetMap PBF extract and uses them to measure the
+// pedestrian connections between stops along the streets instead of in straight lines.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BlobHeader {
+    #[prost(string, required, tag = "1")]
+    pub r#type: String,
+    #[prost(int32, required, tag = "3")]
+    pub datasize: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Blob {
+    #[prost(bytes, optional, tag = "1")]
+    pub raw: Option<Vec<u8>>,
+    #[prost(int32, optional, tag = "2")]
+    pub raw_size: Option<i32>,
+    #[prost(bytes, optional, tag = "3")]
+    pub zlib_data: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrimitiveBlock {
+    #[prost(message, required, tag = "1")]
+    pub stringtable: StringTable,
+    #[prost(message, repeated, tag = "2")]
+    pub primitivegroup: Vec<PrimitiveGroup>,
+    // in nanodegrees
+    #[prost(int32, optional, tag = "17", default = "100")]
+    pub granularity: Option<i32>,
+    #[prost(int64, optional, tag = "19", default = "0")]
+    pub lat_offset: Option<i64>,
+    #[prost(int64, optional, tag = "20", default = "0")]
+    pub lon_offset: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StringTable {
+    #[prost(bytes, repeated, tag = "1")]
+    pub s: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrimitiveGroup {
+    #[prost(message, repeated, tag = "1")]
+    pub nodes: Vec<OsmNode>,
+    #[prost(message, optional, tag = "2")]
+    pub dense: Option<DenseNodes>,
+    #[prost(message, repeated, tag = "3")]
+    pub ways: Vec<Way>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OsmNode {
+    #[prost(sint64, required, tag = "1")]
+    pub id: i64,
+    #[prost(sint64, required, tag = "8")]
+    pub lat: i64,
+    #[prost(sint64, required, tag = "9")]
+    pub lon: i64,
+}
+
+/// Nodes with the ids and the coordinates delta coded
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenseNodes {
+    #[prost(sint64, repeated, tag = "1")]
+    pub id: Vec<i64>,
+    #[prost(sint64, repeated, tag = "8")]
+    pub lat: Vec<i64>,
+    #[prost(sint64, repeated, tag = "9")]
+    pub lon: Vec<i64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Way {
+    #[prost(int64, required, tag = "1")]
+    pub id: i64,
+    // indices into the string table
+    #[prost(uint32, repeated, tag = "2")]
+    pub keys: Vec<u32>,
+    #[prost(uint32, repeated, tag = "3")]
+    pub vals: Vec<u32>,
+    // delta coded node ids
+    #[prost(sint64, repeated, tag = "8")]
+    pub refs: Vec<i64>,
+}
+
+/// Kinds of highways pedestrians may use unless tagged otherwise
+const PEDESTRIAN_HIGHWAYS: &[&str] = &[
+    "footway",
+    "pedestrian",
+    "path",
+    "steps",
+    "living_street",
+    "residential",
+    "service",
+    "unclassified",
+    "track",
+    "corridor",
+    "platform",
+    "tertiary",
+    "tertiary_link",
+    "secondary",
+    "secondary_link",
+    "primary",
+    "primary_link",
+];
+
+/// Decides from the tags of a way whether pedestrians can walk along it
+fn is_walkable(tags: &HashMap<&str, &str>) -> bool {
+    match tags.get("foot") {
+        Some(&"no") | Some(&"private") => return false,
+        Some(&"yes") | Some(&"designated") | Some(&"permissive") => return true,
+        _ => {}
+    }
+    if let Some(&"no") | Some(&"private") = tags.get("access") {
+        return false;
+    }
+    tags.get("highway")
+        .map_or(false, |highway| PEDESTRIAN_HIGHWAYS.contains(highway))
+}
+
+// the largest blob header and blob allowed by the format, in bytes
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// Reads the blocks of nodes and ways from an OSM PBF file one blob at a time
+pub struct BlockReader {
+    reader: BufReader<File>,
+}
+
+impl BlockReader {
+    pub fn open(path: &Path) -> Result<BlockReader, String> {
+        Ok(BlockReader {
+            reader: BufReader::new(File::open(path).map_err(|e| e.to_string())?),
+        })
+    }
+
+    /// Reads the next block of nodes and ways, skipping the other blobs. Returns None at the end
+    /// of the file.
+    fn read_block(&mut self) -> Result<Option<PrimitiveBlock>, String> {
+        loop {
+            // each blob is preceded by its header and the length of the header
+            if self
+                .reader
+                .fill_buf()
+                .map_err(|e| e.to_string())?
+                .is_empty()
+            {
+                return Ok(None);
+            }
+            let mut header_length = [0u8; 4];
+            self.reader
+                .read_exact(&mut header_length)
+                .map_err(|_| "Truncated blob header length.")?;
+            let header_length = u32::from_be_bytes(header_length) as usize;
+            if header_length > MAX_HEADER_SIZE {
+                return Err(String::from("The blob header is too large."));
+            }
+            let mut header = vec![0u8; header_length];
+            self.reader
+                .read_exact(&mut header)
+                .map_err(|_| "Truncated blob header.")?;
+            let header: BlobHeader = prost::Message::decode(&header[..])
+                .map_err(|e| format!("Unable to decode the blob header: {}", e))?;
+            let blob_length: usize = match header.datasize.try_into() {
+                Ok(blob_length) if blob_length <= MAX_BLOB_SIZE => blob_length,
+                _ => return Err(String::from("Invalid blob size.")),
+            };
+            let mut blob = vec![0u8; blob_length];
+            self.reader
+                .read_exact(&mut blob)
+                .map_err(|_| "Truncated blob.")?;
+            if header.r#type != "OSMData" {
+                continue;
+            }
+            let blob: Blob = prost::Message::decode(&blob[..])
+                .map_err(|e| format!("Unable to decode the blob: {}", e))?;
+            let data = match (blob.raw, blob.zlib_data) {
+                (Some(raw), _) => raw,
+                (None, Some(zlib_data)) => {
+                    let mut data = Vec::new();
+                    ZlibDecoder::new(&zlib_data[..])
+                        .take(MAX_BLOB_SIZE as u64)
+                        .read_to_end(&mut data)
+                        .map_err(|e| e.to_string())?;
+                    data
+                }
+                (None, None) => return Err(String::from("Unsupported blob compression.")),
+            };
+            return prost::Message::decode(&data[..])
+                .map(Some)
+                .map_err(|e| format!("Unable to decode the primitive block: {}", e));
+        }
+    }
+}
+
+impl Iterator for BlockReader {
+    type Item = Result<PrimitiveBlock, String>;
+
+    fn next(&mut self) -> Option<Result<PrimitiveBlock, String>> {
+        self.read_block().transpose()
+    }
+}
+
+/// Collects the node ids of the walkable ways of a block
+fn get_walkable_ways(block: &PrimitiveBlock) -> Result<Vec<Vec<i64>>, String> {
+    let strings: Vec<&str> = block
+        .stringtable
+        .s
+        .iter()
+        .map(|s| std::str::from_utf8(s).unwrap_or(""))
+        .collect();
+    let get_string = |index: u32| {
+        strings
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("String {} is missing from the string table.", index))
+    };
+    let mut ways = Vec::new();
+    for way in block.primitivegroup.iter().flat_map(|group| &group.ways) {
+        let tags = way
+            .keys
+            .iter()
+            .zip(way.vals.iter())
+            .map(|(&key, &val)| Ok((get_string(key)?, get_string(val)?)))
+            .collect::<Result<HashMap<&str, &str>, String>>()?;
+        if is_walkable(&tags) {
+            let mut id = 0;
+            ways.push(
+                way.refs
+                    .iter()
+                    .map(|delta| {
+                        id += delta;
+                        id
+                    })
+                    .collect(),
+            );
+        }
+    }
+    Ok(ways)
+}
+
+/// Adds the coordinates of the nodes of a block that are among the wanted ones
+fn add_node_coords(
+    block: &PrimitiveBlock,
+    wanted: &HashSet<i64>,
+    osm_coords: &mut HashMap<i64, Point<f32>>,
+) -> Result<(), String> {
+    let granularity = block.granularity.unwrap_or(100) as i64;
+    let (lat_offset, lon_offset) = (block.lat_offset.unwrap_or(0), block.lon_offset.unwrap_or(0));
+    let to_point = |lat: i64, lon: i64| {
+        Point::new(
+            ((lon_offset + granularity * lon) as f64 * 1e-9) as f32,
+            ((lat_offset + granularity * lat) as f64 * 1e-9) as f32,
+        )
+    };
+    for group in &block.primitivegroup {
+        for node in group.nodes.iter().filter(|node| wanted.contains(&node.id)) {
+            osm_coords.insert(node.id, to_point(node.lat, node.lon));
+        }
+        if let Some(dense) = &group.dense {
+            let (mut id, mut lat, mut lon) = (0, 0, 0);
+            for (i, delta) in dense.id.iter().enumerate() {
+                let (delta_lat, delta_lon) = match (dense.lat.get(i), dense.lon.get(i)) {
+                    (Some(delta_lat), Some(delta_lon)) => (delta_lat, delta_lon),
+                    _ => return Err(String::from("Dense nodes without coordinates.")),
+                };
+                id += delta;
+                lat += delta_lat;
+                lon += delta_lon;
+                if wanted.contains(&id) {
+                    osm_coords.insert(id, to_point(lat, lon));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_malformed_blocks() {
+    let mut block = PrimitiveBlock {
+        stringtable: StringTable {
+            s: vec![b"".to_vec(), b"highway".to_vec(), b"footway".to_vec()],
+        },
+        primitivegroup: vec![PrimitiveGroup {
+            nodes: Vec::new(),
+            dense: Some(DenseNodes {
+                id: vec![1, 1],
+                lat: vec![500_000_000, 10],
+                lon: vec![144_000_000],
+            }),
+            ways: vec![Way {
+                id: 1,
+                keys: vec![1],
+                vals: vec![2],
+                refs: vec![1, 1],
+            }],
+        }],
+        granularity: Some(100),
+        lat_offset: Some(0),
+        lon_offset: Some(0),
+    };
+    assert_eq!(get_walkable_ways(&block), Ok(vec![vec![1, 2]]));
+    let wanted: HashSet<i64> = [1, 2].iter().cloned().collect();
+    assert!(add_node_coords(&block, &wanted, &mut HashMap::new()).is_err());
+    block.primitivegroup[0].ways[0].vals = vec![3];
+    assert!(get_walkable_ways(&block).is_err());
+}
+
+/// The walkable ways of an OpenStreetMap extract as a graph in UTM coordinates
+pub struct PedestrianNetwork {
+    coords: Vec<Point<f32>>,
+    // the neighbours of each node with the distances in meters
+    edges: Vec<Vec<(usize, f32)>>,
+    // the nodes divided into squares of SNAP_DISTANCE by their coordinates
+    squares: HashMap<(i32, i32), Vec<usize>>,
+}
+
+/// The farthest a stop can be from the nearest node of the ways to be connected to them, in meters
+pub const SNAP_DISTANCE: f32 = 100.0;
+
+impl PedestrianNetwork {
+    /// Builds the graph of the walkable ways from the node ids of the ways and the coordinates
+    /// of the nodes
+    pub fn new(ways: Vec<Vec<i64>>, osm_coords: &HashMap<i64, Point<f32>>) -> PedestrianNetwork {
+        let wsg_to_utm = loader::create_wgs84_to_utm();
+        let mut indices: HashMap<i64, usize> = HashMap::new();
+        let mut coords = Vec::new();
+        let mut edges: Vec<Vec<(usize, f32)>> = Vec::new();
+        for way in ways {
+            let mut previous: Option<usize> = None;
+            for osm_id in way {
+                // ways may reference nodes left out of the extract
+                let point = match osm_coords.get(&osm_id) {
+                    Some(&point) => point,
+                    None => {
+                        previous = None;
+                        continue;
+                    }
+                };
+                let index = *indices.entry(osm_id).or_insert_with(|| {
+                    coords.push(wsg_to_utm.convert(point).unwrap());
+                    edges.push(Vec::new());
+                    coords.len() - 1
+                });
+                if let Some(previous) = previous {
+                    let (from, to) = (coords[previous], coords[index]);
+                    let distance =
+                        ((from.x() - to.x()).powi(2) + (from.y() - to.y()).powi(2)).sqrt();
+                    edges[previous].push((index, distance));
+                    edges[index].push((previous, distance));
+                }
+                previous = Some(index);
+            }
+        }
+        let mut squares: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (index, coord) in coords.iter().enumerate() {
+            squares
+                .entry(PedestrianNetwork::get_square(*coord))
+                .or_insert_with(Vec::new)
+                .push(index);
+        }
+        PedestrianNetwork {
+            coords: coords,
+            edges: edges,
+            squares: squares,
+        }
+    }
+
+    fn get_square(coord: Point<f32>) -> (i32, i32) {
+        (
+            (coord.x() / SNAP_DISTANCE) as i32,
+            (coord.y() / SNAP_DISTANCE) as i32,
+        )
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.coords.len()
+    }
+
+    /// Finds the node nearest to a point in UTM coordinates within SNAP_DISTANCE, together with
+    /// its distance
+    pub fn get_nearest_node(&self, coord: Point<f32>) -> Option<(usize, f32)> {
+        let (x, y) = PedestrianNetwork::get_square(coord);
+        let mut nearest: Option<(usize, f32)> = None;
+        for dx in -1..2 {
+            for dy in -1..2 {
+                for &index in self.squares.get(&(x + dx, y + dy)).into_iter().flatten() {
+                    let node = self.coords[index];
+                    let distance =
+                        ((coord.x() - node.x()).powi(2) + (coord.y() - node.y()).powi(2)).sqrt();
+                    if distance <= SNAP_DISTANCE
+                        && nearest.map_or(true, |(_, nearest)| distance < nearest)
+                    {
+                        nearest = Some((index, distance));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Finds the lengths of the shortest paths from a node to the nodes at most max_distance
+    /// meters away along the ways
+    pub fn get_distances_from(&self, start: usize, max_distance: f32) -> HashMap<usize, f32> {
+        let mut distances: HashMap<usize, f32> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        // the bits of non-negative floats are ordered the same way as the floats
+        heap.push(Reverse((0f32.to_bits(), start)));
+        while let Some(Reverse((bits, node))) = heap.pop() {
+            if distances.contains_key(&node) {
+                continue;
+            }
+            let distance = f32::from_bits(bits);
+            distances.insert(node, distance);
+            for &(next, length) in &self.edges[node] {
+                let next_distance = distance + length;
+                if next_distance <= max_distance && !distances.contains_key(&next) {
+                    heap.push(Reverse((next_distance.to_bits(), next)));
+                }
+            }
+        }
+        distances
+    }
+
+    /// Measures the straight line pedestrian connections along the ways. Connections of stops
+    /// far from the ways keep their straight line distances, connections without a path shorter
+    /// than max_distance are left out.
+    pub fn route_footpaths(
+        &self,
+        straight_line: HashMap<String, Vec<Footpath>>,
+        utm_coords: &HashMap<String, Point<f32>>,
+        max_distance: f32,
+    ) -> HashMap<String, Vec<Footpath>> {
+        let nearest_nodes: HashMap<&String, (usize, f32)> = utm_coords
+            .iter()
+            .filter_map(|(stop_id, &coord)| {
+                self.get_nearest_node(coord)
+                    .map(|nearest| (stop_id, nearest))
+            })
+            .collect();
+        let mut routed = HashMap::new();
+        for (stop_id, footpaths) in straight_line {
+            let (start, start_distance) = match nearest_nodes.get(&stop_id) {
+                Some(&nearest) => nearest,
+                None => {
+                    routed.insert(stop_id, footpaths);
+                    continue;
+                }
+            };
+            let distances = self.get_distances_from(start, max_distance);
+            let footpaths = footpaths
+                .into_iter()
+                .filter_map(
+                    |footpath| match nearest_nodes.get(&footpath.target_stop_id) {
+                        Some(&(end, end_distance)) => {
+                            let distance = start_distance + distances.get(&end)? + end_distance;
+                            if distance > max_distance {
+                                return None;
+                            }
+                            Some(Footpath::new(
+                                footpath.target_stop_id,
+                                distance,
+                                loader::get_walking_duration(distance),
+                            ))
+                        }
+                        None => Some(footpath),
+                    },
+                )
+                .collect();
+            routed.insert(stop_id, footpaths);
+        }
+        routed
+    }
+}
+
+/// Loads the walkable ways from an OSM PBF file. The nodes precede the ways in the file, so it is
+/// read twice, once for the ways and once for the coordinates of the nodes they use.
+pub fn load_pedestrian_network(path: &Path) -> Result<PedestrianNetwork, String> {
+    let mut ways = Vec::new();
+    for block in BlockReader::open(path)? {
+        ways.extend(get_walkable_ways(&block?)?);
+    }
+    let wanted: HashSet<i64> = ways.iter().flatten().cloned().collect();
+    let mut osm_coords = HashMap::new();
+    for block in BlockReader::open(path)? {
+        add_node_coords(&block?, &wanted, &mut osm_coords)?;
+    }
+    Ok(PedestrianNetwork::new(ways, &osm_coords))
+}
+
+#[test]
+fn test_pedestrian_network_loading() {
+    let network = load_pedestrian_network(Path::new("test_data/osm/malostranska.osm.pbf")).unwrap();
+    // the motorway bridge and the footbridge closed to pedestrians are left out, their end
+    // nodes are shared with the walkable ways
+    assert_eq!(network.node_count(), 7);
+    let (start, _) = network
+        .get_nearest_node(loader::wgs84_to_utm(Point::new(14.42, 50.0801)).unwrap())
+        .unwrap();
+    let distances = network.get_distances_from(start, 5000.0);
+    assert_eq!(distances.len(), 7);
+    assert!(network
+        .get_nearest_node(loader::wgs84_to_utm(Point::new(14.40, 50.08)).unwrap())
+        .is_none());
+    assert!(load_pedestrian_network(Path::new("test_data/network/alerts.pb")).is_err());
+}
@@ -0,0 +1,1031 @@
+use crate::model::data_structures::*;
+use crate::model::loader::get_feed_prefixes;
+#[cfg(test)]
+use crate::model::loader::{load_merged_transport_network, load_transport_network};
+use chrono::{NaiveDate, TimeZone, Utc};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(test)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// This file contains the subset of the GTFS-Realtime protobuf messages prahadlo understands, the
+// means to read them and the application of the updates to the network.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FeedMessage {
+    #[prost(message, required, tag = "1")]
+    pub header: FeedHeader,
+    #[prost(message, repeated, tag = "2")]
+    pub entity: Vec<FeedEntity>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FeedHeader {
+    #[prost(string, required, tag = "1")]
+    pub gtfs_realtime_version: String,
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FeedEntity {
+    #[prost(string, required, tag = "1")]
+    pub id: String,
+    #[prost(bool, optional, tag = "2")]
+    pub is_deleted: Option<bool>,
+    #[prost(message, optional, tag = "3")]
+    pub trip_update: Option<TripUpdate>,
+    #[prost(message, optional, tag = "5")]
+    pub alert: Option<Alert>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TripUpdate {
+    #[prost(message, required, tag = "1")]
+    pub trip: TripDescriptor,
+    #[prost(message, repeated, tag = "2")]
+    pub stop_time_update: Vec<StopTimeUpdate>,
+    #[prost(uint64, optional, tag = "4")]
+    pub timestamp: Option<u64>,
+    #[prost(int32, optional, tag = "5")]
+    pub delay: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TripDescriptor {
+    #[prost(string, optional, tag = "1")]
+    pub trip_id: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub start_time: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub start_date: Option<String>,
+    #[prost(enumeration = "TripScheduleRelationship", optional, tag = "4")]
+    pub schedule_relationship: Option<i32>,
+    #[prost(string, optional, tag = "5")]
+    pub route_id: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TripScheduleRelationship {
+    Scheduled = 0,
+    Added = 1,
+    Unscheduled = 2,
+    Canceled = 3,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StopTimeUpdate {
+    #[prost(uint32, optional, tag = "1")]
+    pub stop_sequence: Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    pub arrival: Option<StopTimeEvent>,
+    #[prost(message, optional, tag = "3")]
+    pub departure: Option<StopTimeEvent>,
+    #[prost(string, optional, tag = "4")]
+    pub stop_id: Option<String>,
+    #[prost(enumeration = "StopTimeScheduleRelationship", optional, tag = "5")]
+    pub schedule_relationship: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum StopTimeScheduleRelationship {
+    Scheduled = 0,
+    Skipped = 1,
+    NoData = 2,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StopTimeEvent {
+    #[prost(int32, optional, tag = "1")]
+    pub delay: Option<i32>,
+    #[prost(int64, optional, tag = "2")]
+    pub time: Option<i64>,
+    #[prost(int32, optional, tag = "3")]
+    pub uncertainty: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Alert {
+    #[prost(message, repeated, tag = "1")]
+    pub active_period: Vec<TimeRange>,
+    #[prost(message, repeated, tag = "5")]
+    pub informed_entity: Vec<EntitySelector>,
+    #[prost(enumeration = "Effect", optional, tag = "7")]
+    pub effect: Option<i32>,
+    #[prost(message, optional, tag = "8")]
+    pub url: Option<TranslatedString>,
+    #[prost(message, optional, tag = "10")]
+    pub header_text: Option<TranslatedString>,
+    #[prost(message, optional, tag = "11")]
+    pub description_text: Option<TranslatedString>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Effect {
+    NoService = 1,
+    ReducedService = 2,
+    SignificantDelays = 3,
+    Detour = 4,
+    AdditionalService = 5,
+    ModifiedService = 6,
+    OtherEffect = 7,
+    UnknownEffect = 8,
+    StopMoved = 9,
+    NoEffect = 10,
+    AccessibilityIssue = 11,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeRange {
+    #[prost(uint64, optional, tag = "1")]
+    pub start: Option<u64>,
+    #[prost(uint64, optional, tag = "2")]
+    pub end: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EntitySelector {
+    #[prost(string, optional, tag = "1")]
+    pub agency_id: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub route_id: Option<String>,
+    #[prost(int32, optional, tag = "3")]
+    pub route_type: Option<i32>,
+    #[prost(message, optional, tag = "4")]
+    pub trip: Option<TripDescriptor>,
+    #[prost(string, optional, tag = "5")]
+    pub stop_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TranslatedString {
+    #[prost(message, repeated, tag = "1")]
+    pub translation: Vec<Translation>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Translation {
+    #[prost(string, required, tag = "1")]
+    pub text: String,
+    #[prost(string, optional, tag = "2")]
+    pub language: Option<String>,
+}
+
+/// Decodes a GTFS-Realtime feed message
+pub fn decode_feed(bytes: &[u8]) -> Result<FeedMessage, String> {
+    prost::Message::decode(bytes).map_err(|e| format!("Unable to decode the feed: {}", e))
+}
+
+// how long the server of a realtime feed may take to connect, to accept the request or to send
+// the next part of the response
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Adds the default HTTP port to the host of an URL if it has none
+fn get_http_address(host: &str) -> String {
+    // the colons of IPv6 addresses are enclosed in brackets
+    let has_port = host
+        .rsplit(']')
+        .next()
+        .map_or(false, |tail| tail.contains(':'));
+    if has_port {
+        String::from(host)
+    } else {
+        format!("{}:80", host)
+    }
+}
+
+#[test]
+fn test_http_address() {
+    assert_eq!(get_http_address("example.com"), "example.com:80");
+    assert_eq!(get_http_address("127.0.0.1:8080"), "127.0.0.1:8080");
+    assert_eq!(get_http_address("[::1]"), "[::1]:80");
+    assert_eq!(get_http_address("[::1]:8080"), "[::1]:8080");
+}
+
+/// Downloads a resource over plain HTTP. Only meant for feeds served on the local network,
+/// there is no support for TLS or redirects.
+/// # Arguments
+/// * url - the http:// URL, the port is 80 if not given
+/// * timeout - the longest wait for the connection and for each read and write
+fn fetch_http(url: &str, timeout: Duration) -> Result<Vec<u8>, String> {
+    let without_scheme = url.trim_start_matches("http://");
+    let (host, path) = match without_scheme.find('/') {
+        Some(index) => without_scheme.split_at(index),
+        None => (without_scheme, "/"),
+    };
+    let mut result = Err(format!("Unable to resolve {}.", host));
+    for address in get_http_address(host)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+    {
+        result = TcpStream::connect_timeout(&address, timeout).map_err(|e| e.to_string());
+        if result.is_ok() {
+            break;
+        }
+    }
+    let mut stream = result?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    )
+    .map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| e.to_string())?;
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or("Malformed HTTP response.")?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]);
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!(
+            "Unexpected HTTP response: {}",
+            status_line.lines().next().unwrap_or("")
+        ));
+    }
+    Ok(response[header_end + 4..].to_vec())
+}
+
+/// Reads a GTFS-Realtime feed from a file or from a plain http:// URL
+pub fn load_feed(source: &str) -> Result<FeedMessage, String> {
+    let bytes = if source.starts_with("http://") {
+        fetch_http(source, HTTP_TIMEOUT)?
+    } else {
+        fs::read(source).map_err(|e| e.to_string())?
+    };
+    decode_feed(&bytes)
+}
+
+#[test]
+fn test_feed_loading() {
+    let feed = load_feed("test_data/network/trip_updates.pb").unwrap();
+    assert_eq!(feed.header.gtfs_realtime_version, "2.0");
+    assert_eq!(feed.entity.len(), 4);
+    let update = feed.entity[0].trip_update.as_ref().unwrap();
+    assert_eq!(update.trip.trip_id, Some(String::from("LA_1")));
+    assert_eq!(update.stop_time_update[0].stop_sequence, Some(2));
+    assert_eq!(
+        update.stop_time_update[0].arrival.as_ref().unwrap().delay,
+        Some(90)
+    );
+}
+
+#[test]
+fn test_feed_loading_over_http() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // reading the request up to the empty line ending the headers
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        let body = fs::read("test_data/network/trip_updates.pb").unwrap();
+        write!(
+            stream,
+            "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+    });
+    let feed = load_feed(&format!("http://{}/trip_updates.pb", address)).unwrap();
+    server.join().unwrap();
+    assert_eq!(feed.entity.len(), 4);
+}
+
+#[test]
+fn test_unresponsive_feed_server() {
+    use std::net::TcpListener;
+
+    // the connection is accepted by the system, but nothing is ever sent
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/trip_updates.pb", listener.local_addr().unwrap());
+    assert!(fetch_http(&url, Duration::from_millis(200)).is_err());
+}
+
+fn add_prefix_to_trip(prefix: &str, trip: &mut TripDescriptor) {
+    add_prefix_to_option(prefix, &mut trip.trip_id);
+    add_prefix_to_option(prefix, &mut trip.route_id);
+}
+
+impl FeedMessage {
+    /// Prefixes the ids of the entities and the trips, routes, stops and agencies they refer to,
+    /// the same way load_merged_transport_network prefixes the ids of the static feed
+    pub fn add_prefix(&mut self, prefix: &str) {
+        for entity in &mut self.entity {
+            add_prefix(prefix, &mut entity.id);
+            if let Some(update) = &mut entity.trip_update {
+                add_prefix_to_trip(prefix, &mut update.trip);
+                for stop_time_update in &mut update.stop_time_update {
+                    add_prefix_to_option(prefix, &mut stop_time_update.stop_id);
+                }
+            }
+            if let Some(alert) = &mut entity.alert {
+                for selector in &mut alert.informed_entity {
+                    add_prefix_to_option(prefix, &mut selector.agency_id);
+                    add_prefix_to_option(prefix, &mut selector.route_id);
+                    add_prefix_to_option(prefix, &mut selector.stop_id);
+                    if let Some(trip) = &mut selector.trip {
+                        add_prefix_to_trip(prefix, trip);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the entities of another feed, the later of the two timestamps is kept
+    pub fn merge(&mut self, other: FeedMessage) {
+        self.header.timestamp = self.header.timestamp.max(other.header.timestamp);
+        self.entity.extend(other.entity);
+    }
+}
+
+/// Pairs the realtime sources given on the command line with the prefixes of the loaded feeds. A
+/// single feed takes plain sources, several feeds take sources in the form
+/// <feed directory name>=<source>.
+/// # Arguments
+/// * paths - the directories of the loaded feeds
+/// * args - the realtime sources, either files or http:// URLs
+pub fn get_realtime_sources(
+    paths: &[PathBuf],
+    args: &[String],
+) -> Result<Vec<(String, String)>, String> {
+    if paths.len() == 1 {
+        return Ok(args
+            .iter()
+            .map(|source| (String::new(), source.clone()))
+            .collect());
+    }
+    let prefixes = get_feed_prefixes(paths);
+    let mut sources = Vec::new();
+    for arg in args {
+        let (name, source) = match arg.split_once('=') {
+            Some(pair) => pair,
+            None => {
+                return Err(format!(
+                    "Expected {} to name its feed, several feeds are loaded.",
+                    arg
+                ))
+            }
+        };
+        let prefix = format!("{}:", name);
+        if !prefixes.contains(&prefix) {
+            return Err(format!("Unknown feed {}.", name));
+        }
+        sources.push((prefix, String::from(source)));
+    }
+    Ok(sources)
+}
+
+/// Reads the realtime feeds of several static feeds and combines them into one, the ids of each
+/// are prefixed with the prefix of its static feed
+/// # Arguments
+/// * sources - the prefixes of the static feeds with their realtime sources
+pub fn load_feeds(sources: &[(String, String)]) -> Result<FeedMessage, String> {
+    let mut combined: Option<FeedMessage> = None;
+    for (prefix, source) in sources {
+        let mut feed = load_feed(source).map_err(|e| format!("{}: {}", source, e))?;
+        if !prefix.is_empty() {
+            feed.add_prefix(prefix);
+        }
+        match &mut combined {
+            Some(combined) => combined.merge(feed),
+            None => combined = Some(feed),
+        }
+    }
+    combined.ok_or_else(|| String::from("No realtime source given."))
+}
+
+#[test]
+fn test_realtime_sources() {
+    let single = vec![PathBuf::from("test_data/network/")];
+    let source = String::from("test_data/network/trip_updates.pb");
+    assert_eq!(
+        get_realtime_sources(&single, &[source.clone()]),
+        Ok(vec![(String::new(), source.clone())])
+    );
+    let merged = vec![
+        PathBuf::from("test_data/network/"),
+        PathBuf::from("test_data/rail/"),
+    ];
+    assert!(get_realtime_sources(&merged, &[source.clone()]).is_err());
+    assert!(get_realtime_sources(&merged, &[format!("metro={}", source)]).is_err());
+    assert_eq!(
+        get_realtime_sources(&merged, &[String::from("rail=http://host/feed?a=b")]),
+        Ok(vec![(
+            String::from("rail:"),
+            String::from("http://host/feed?a=b")
+        )])
+    );
+}
+
+#[test]
+fn test_realtime_data_of_merged_feeds() {
+    let paths = vec![
+        PathBuf::from("test_data/network/"),
+        PathBuf::from("test_data/rail/"),
+    ];
+    let mut network = load_merged_transport_network(&paths);
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let sources = get_realtime_sources(
+        &paths,
+        &[
+            String::from("network=test_data/network/trip_updates.pb"),
+            String::from("network=test_data/network/alerts.pb"),
+        ],
+    )
+    .unwrap();
+    let feed = load_feeds(&sources).unwrap();
+    assert_eq!(network.apply_trip_updates(&feed), 4);
+    assert_eq!(network.apply_alerts(&feed), 3);
+    let itinerary = network
+        .find_connection(
+            "network:MA1",
+            "network:T4",
+            monday,
+            8 * 3600,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        itinerary.legs[0].trip_id,
+        Some(String::from("network:LA_1"))
+    );
+    assert_eq!(itinerary.legs[0].arrival_delay, Some(90));
+    assert_eq!(itinerary.legs[0].alerts[0].alert_id, "network:hradcanska");
+    // Hradčanská is closed in the morning
+    assert!(network
+        .find_connection(
+            "network:MA1",
+            "network:MA2",
+            monday,
+            8 * 3600,
+            &SearchOptions::default()
+        )
+        .unwrap()
+        .is_none());
+}
+
+/// Picks the first translation of a text, the feeds usually only provide one
+fn get_text(text: &Option<TranslatedString>) -> Option<String> {
+    text.as_ref()
+        .and_then(|text| text.translation.first())
+        .map(|translation| translation.text.clone())
+}
+
+/// Converts the protobuf alert into the form used by the network
+fn convert_alert(id: &str, alert: &Alert) -> ServiceAlert {
+    ServiceAlert {
+        alert_id: String::from(id),
+        active_periods: alert
+            .active_period
+            .iter()
+            .map(|period| (period.start, period.end))
+            .collect(),
+        informed_entities: alert
+            .informed_entity
+            .iter()
+            .map(|entity| InformedEntity {
+                agency_id: entity.agency_id.clone(),
+                route_id: entity.route_id.clone(),
+                route_type: entity.route_type.map(|route_type| route_type as u32),
+                trip_id: entity.trip.as_ref().and_then(|trip| trip.trip_id.clone()),
+                stop_id: entity.stop_id.clone(),
+            })
+            .collect(),
+        no_service: alert.effect == Some(Effect::NoService as i32),
+        header_text: get_text(&alert.header_text),
+        description_text: get_text(&alert.description_text),
+        url: get_text(&alert.url),
+    }
+}
+
+/// Returns the delay of an event in seconds. Events that only carry the absolute time are
+/// compared to the scheduled time on the service day of the run.
+fn get_event_delay(
+    event: &StopTimeEvent,
+    scheduled_time: u32,
+    service_day_start: i64,
+) -> Option<i32> {
+    event.delay.or_else(|| {
+        event
+            .time
+            .map(|time| (time - service_day_start - scheduled_time as i64) as i32)
+    })
+}
+
+/// Applies a single trip update to the stop times of the trip. Delays propagate to the following
+/// stops until another update provides new information.
+/// # Arguments
+/// * trip - the updated trip
+/// * update - the update of the trip
+/// * service_day_start - the POSIX timestamp the times of the updated run are measured from
+fn apply_trip_update(trip: &mut Trip, update: &TripUpdate, service_day_start: i64) {
+    if update.trip.schedule_relationship == Some(TripScheduleRelationship::Canceled as i32) {
+        trip.cancelled = true;
+        return;
+    }
+    let mut delay = update.delay;
+    let mut last_time = 0;
+    for stop_time in trip.stop_times.iter_mut() {
+        let stop_time_update = update
+            .stop_time_update
+            .iter()
+            .find(|u| match u.stop_sequence {
+                Some(stop_sequence) => stop_sequence == stop_time.stop_sequence,
+                None => u.stop_id.as_ref() == Some(&stop_time.stop_id),
+            });
+        let mut arrival_delay = delay;
+        let mut departure_delay = delay;
+        if let Some(stop_time_update) = stop_time_update {
+            match stop_time_update.schedule_relationship {
+                Some(r) if r == StopTimeScheduleRelationship::Skipped as i32 => {
+                    stop_time.skipped = true;
+                }
+                Some(r) if r == StopTimeScheduleRelationship::NoData as i32 => {
+                    delay = None;
+                    arrival_delay = None;
+                    departure_delay = None;
+                }
+                _ => {
+                    if let Some(d) = stop_time_update
+                        .arrival
+                        .as_ref()
+                        .and_then(|e| get_event_delay(e, stop_time.arrival_time, service_day_start))
+                    {
+                        arrival_delay = Some(d);
+                        departure_delay = Some(d);
+                        delay = Some(d);
+                    }
+                    if let Some(d) = stop_time_update.departure.as_ref().and_then(|e| {
+                        get_event_delay(e, stop_time.departure_time, service_day_start)
+                    }) {
+                        departure_delay = Some(d);
+                        delay = Some(d);
+                    }
+                }
+            }
+        }
+        if let (Some(arrival_delay), Some(departure_delay)) = (arrival_delay, departure_delay) {
+            // the vehicle can neither arrive before leaving the previous stop nor leave before
+            // arriving
+            let arrival = (stop_time.arrival_time as i64 + arrival_delay as i64).max(last_time);
+            let departure = (stop_time.departure_time as i64 + departure_delay as i64).max(arrival);
+            stop_time.realtime_arrival_time = Some(arrival as u32);
+            stop_time.realtime_departure_time = Some(departure as u32);
+        }
+        last_time = stop_time.get_departure_time() as i64;
+    }
+}
+
+impl Network {
+    /// Applies the trip updates of a feed, replacing any previously applied realtime data, and
+    /// updates the nodes of the affected trips so that the searches use the predicted times.
+    /// Trips are matched by trip_id, frequency based trips by trip_id and start_time, stops by
+    /// stop_sequence. The network keeps one copy of each trip, so the updates only apply to the
+    /// service day of the feed timestamp, the searches on the other days use the schedule.
+    /// Updates of runs on other service days are skipped. Returns the number of trip updates
+    /// that matched a trip.
+    pub fn apply_trip_updates(&mut self, feed: &FeedMessage) -> usize {
+        let now = feed
+            .header
+            .timestamp
+            .map_or_else(|| Utc::now().timestamp(), |timestamp| timestamp as i64);
+        let today = match Utc.timestamp_opt(now, 0).single() {
+            Some(now) => now.with_timezone(&self.get_timezone()).date_naive(),
+            None => return 0,
+        };
+        let service_day_start = self.get_service_day_start(today);
+        self.reset_realtime_data(today);
+        let mut changed_trips = Vec::new();
+        for entity in &feed.entity {
+            let update = match &entity.trip_update {
+                Some(update) if entity.is_deleted != Some(true) => update,
+                _ => continue,
+            };
+            let trip_id = match &update.trip.trip_id {
+                Some(trip_id) => trip_id,
+                None => continue,
+            };
+            let trip_id = match &update.trip.start_time {
+                Some(start_time) if self.get_trip(trip_id).is_none() => {
+                    format!("{}_{}", trip_id, start_time)
+                }
+                _ => trip_id.clone(),
+            };
+            let is_today = update.trip.start_date.as_ref().map_or(true, |start_date| {
+                NaiveDate::parse_from_str(start_date, "%Y%m%d") == Ok(today)
+            });
+            if !is_today {
+                continue;
+            }
+            if let Some(trip) = self.get_trip_mut(&trip_id) {
+                apply_trip_update(trip, update, service_day_start);
+                changed_trips.push(trip_id);
+            }
+        }
+        let applied = changed_trips.len();
+        changed_trips.sort();
+        changed_trips.dedup();
+        for trip_id in changed_trips {
+            self.update_trip_nodes(&trip_id);
+        }
+        applied
+    }
+
+    /// Replaces the informed stations with their platforms, the graph only knows the platforms
+    fn resolve_informed_stations(&self, entities: Vec<InformedEntity>) -> Vec<InformedEntity> {
+        let mut resolved = Vec::new();
+        for entity in entities {
+            let platforms = match &entity.stop_id {
+                Some(stop_id) => self.get_station_stops(stop_id),
+                None => Vec::new(),
+            };
+            match platforms.first() {
+                Some(platform) if Some(&platform.stop_id) != entity.stop_id.as_ref() => {
+                    for platform in platforms {
+                        resolved.push(InformedEntity {
+                            stop_id: Some(platform.stop_id.clone()),
+                            ..entity.clone()
+                        });
+                    }
+                }
+                _ => resolved.push(entity),
+            }
+        }
+        resolved
+    }
+
+    /// Replaces the service alerts of the network with the alerts of a feed. Alerts with the
+    /// NO_SERVICE effect close the informed stops, stations, agencies, routes and trips for their
+    /// active periods. Returns the number of alerts loaded.
+    pub fn apply_alerts(&mut self, feed: &FeedMessage) -> usize {
+        let alerts: Vec<ServiceAlert> = feed
+            .entity
+            .iter()
+            .filter(|entity| entity.is_deleted != Some(true))
+            .filter_map(|entity| {
+                entity
+                    .alert
+                    .as_ref()
+                    .map(|alert| convert_alert(&entity.id, alert))
+            })
+            .map(|mut alert| {
+                alert.informed_entities = self.resolve_informed_stations(alert.informed_entities);
+                alert
+            })
+            .collect();
+        let count = alerts.len();
+        self.set_alerts(alerts);
+        count
+    }
+}
+
+#[test]
+fn test_trip_update_application() {
+    let mut network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    assert!(network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_some());
+
+    let feed = load_feed("test_data/network/trip_updates.pb").unwrap();
+    assert_eq!(network.apply_trip_updates(&feed), 4);
+    // LA_1 is delayed, but the tram can still be caught
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].trip_id, Some(String::from("LA_1")));
+    assert_eq!(itinerary.legs[0].departure_delay, None);
+    assert_eq!(itinerary.legs[0].arrival_delay, Some(90));
+    assert_eq!(itinerary.legs[0].arrival_time, 8 * 3600 + 5 * 60 + 30);
+    assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 16 * 60));
+    // L22_1 skips T2 and L22_2 is cancelled
+    assert!(network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_none());
+    // frequency based trips are matched by their start time
+    let itinerary = network
+        .find_connection(
+            "MC1",
+            "MC2",
+            monday,
+            8 * 3600 + 5 * 60,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].departure_time, 8 * 3600 + 11 * 60);
+
+    // a new feed replaces the previous one
+    let empty = FeedMessage {
+        header: feed.header.clone(),
+        entity: Vec::new(),
+    };
+    assert_eq!(network.apply_trip_updates(&empty), 0);
+    let itinerary = network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].arrival_delay, None);
+}
+
+#[test]
+fn test_updates_leave_snapshots_unchanged() {
+    let mut network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let snapshot = network.clone();
+    let feed = load_feed("test_data/network/trip_updates.pb").unwrap();
+    assert_eq!(network.apply_trip_updates(&feed), 4);
+    let delay = |network: &Network| {
+        network
+            .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+            .unwrap()
+            .unwrap()
+            .legs[0]
+            .arrival_delay
+    };
+    assert_eq!(delay(&network), Some(90));
+    assert_eq!(delay(&snapshot), None);
+}
+
+#[test]
+fn test_trip_update_service_days() {
+    let mut network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let mut feed = load_feed("test_data/network/trip_updates.pb").unwrap();
+    // the delay of LA_1 given as the absolute time of the arrival to MA2, 8:03:30 in Prague
+    let arrival = &mut feed.entity[0]
+        .trip_update
+        .as_mut()
+        .unwrap()
+        .stop_time_update[0];
+    arrival.arrival = Some(StopTimeEvent {
+        delay: None,
+        time: Some(1580108400 + 3 * 60 + 30),
+        uncertainty: None,
+    });
+    assert_eq!(network.apply_trip_updates(&feed), 4);
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].arrival_delay, Some(90));
+
+    // the searches on the other days use the schedule
+    let tuesday = NaiveDate::from_ymd(2020, 1, 28);
+    let itinerary = network
+        .find_connection("MA1", "T4", tuesday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].arrival_delay, None);
+    assert!(network
+        .find_connection("MA1", "T2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_none());
+    assert!(network
+        .find_connection("MA1", "T2", tuesday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_some());
+    assert!(network.trip_detail("L22_2", monday).unwrap().cancelled);
+    assert!(!network.trip_detail("L22_2", tuesday).unwrap().cancelled);
+    let departures = network
+        .departures("MA1", tuesday, 8 * 3600, 5, &SearchOptions::default())
+        .unwrap();
+    assert!(departures
+        .iter()
+        .all(|departure| departure.departure_delay.is_none()));
+
+    // updates for another day than the one of the feed are skipped
+    for entity in feed.entity.iter_mut() {
+        entity.trip_update.as_mut().unwrap().trip.start_date = Some(String::from("20200128"));
+    }
+    assert_eq!(network.apply_trip_updates(&feed), 0);
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].arrival_delay, None);
+}
+
+#[test]
+fn test_incremental_graph_update() {
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let feed = load_feed("test_data/network/trip_updates.pb").unwrap();
+    let mut updated = load_transport_network(Path::new("test_data/network/"));
+    let mut rebuilt = load_transport_network(Path::new("test_data/network/"));
+    updated.apply_trip_updates(&feed);
+    rebuilt.apply_trip_updates(&feed);
+    rebuilt.rebuild_graph();
+
+    let stop_ids = ["MA1", "MA2", "MA3", "T1", "T2", "T3", "T4", "MC1", "MC2"];
+    for stop_id in stop_ids.iter() {
+        // the departure chains stay sorted
+        let stop = updated.get_stop(stop_id).unwrap();
+        for index in 1..stop.dep_node_count() {
+            let previous = updated.get_node(stop.get_dep_node(index - 1)).get_time();
+            let current = updated.get_node(stop.get_dep_node(index)).get_time();
+            assert!(previous <= current);
+        }
+    }
+    let summarize = |network: &Network, from: &str, to: &str, time: u32| {
+        network
+            .find_connection(from, to, monday, time, &SearchOptions::default())
+            .unwrap()
+            .map(|itinerary| (itinerary.departure_time(), itinerary.arrival_time()))
+    };
+    for from in stop_ids.iter() {
+        for to in stop_ids.iter() {
+            for minutes in (7 * 60 + 50..8 * 60 + 30).step_by(5) {
+                assert_eq!(
+                    summarize(&updated, from, to, minutes * 60),
+                    summarize(&rebuilt, from, to, minutes * 60)
+                );
+            }
+        }
+    }
+
+    // reverting the updates restores the scheduled graph
+    let empty = FeedMessage {
+        header: feed.header.clone(),
+        entity: Vec::new(),
+    };
+    updated.apply_trip_updates(&empty);
+    let scheduled = load_transport_network(Path::new("test_data/network/"));
+    for from in stop_ids.iter() {
+        for to in stop_ids.iter() {
+            for minutes in (7 * 60 + 50..8 * 60 + 30).step_by(5) {
+                assert_eq!(
+                    summarize(&updated, from, to, minutes * 60),
+                    summarize(&scheduled, from, to, minutes * 60)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_incremental_update_over_one_way_footpaths() {
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let header = load_feed("test_data/network/trip_updates.pb")
+        .unwrap()
+        .header;
+    // Motol is connected to Řepy by a one-way pathway, the delayed bus from Řepy waits for
+    // the walkers from Motol
+    let feed = FeedMessage {
+        header: header,
+        entity: vec![FeedEntity {
+            id: String::from("1"),
+            is_deleted: None,
+            trip_update: Some(TripUpdate {
+                trip: TripDescriptor {
+                    trip_id: Some(String::from("L4_0")),
+                    ..TripDescriptor::default()
+                },
+                stop_time_update: Vec::new(),
+                timestamp: None,
+                delay: Some(5 * 60),
+            }),
+            alert: None,
+        }],
+    };
+    let mut updated = load_transport_network(Path::new("test_data/via/"));
+    let mut rebuilt = load_transport_network(Path::new("test_data/via/"));
+    updated.apply_trip_updates(&feed);
+    rebuilt.apply_trip_updates(&feed);
+    rebuilt.rebuild_graph();
+    for network in [&updated, &rebuilt].iter() {
+        let itinerary = network
+            .find_connection("A", "C", monday, 8 * 3600, &SearchOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(itinerary.arrival_time(), Some(8 * 3600 + 18 * 60));
+    }
+}
+
+#[test]
+fn test_alert_application() {
+    let mut network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let feed = load_feed("test_data/network/alerts.pb").unwrap();
+    assert_eq!(network.apply_alerts(&feed), 3);
+
+    // Hradčanská is closed in the morning, the trains pass through
+    assert!(network
+        .find_connection("MA1", "MA2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_none());
+    let tuesday = NaiveDate::from_ymd(2020, 1, 28);
+    assert!(network
+        .find_connection("MA1", "MA2", tuesday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .is_some());
+
+    let itinerary = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].alerts.len(), 1);
+    assert_eq!(itinerary.legs[0].alerts[0].alert_id, "hradcanska");
+    assert!(itinerary.legs[1].alerts.is_empty());
+    assert_eq!(itinerary.legs[2].alerts.len(), 1);
+    assert_eq!(
+        itinerary.legs[2].alerts[0].header_text,
+        Some(String::from("Zpoždění tramvají"))
+    );
+
+    // line C does not run until 8:30
+    let itinerary = network
+        .find_connection("MC1", "MC2", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(itinerary.legs[0].departure_time, 8 * 3600 + 30 * 60);
+    assert!(itinerary.legs[0].alerts.is_empty());
+}
+
+#[test]
+fn test_alert_entity_selectors() {
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let header = load_feed("test_data/network/alerts.pb").unwrap().header;
+    let closure = |selector: EntitySelector| FeedMessage {
+        header: header.clone(),
+        entity: vec![FeedEntity {
+            id: String::from("closure"),
+            is_deleted: None,
+            trip_update: None,
+            alert: Some(Alert {
+                active_period: Vec::new(),
+                informed_entity: vec![selector],
+                effect: Some(Effect::NoService as i32),
+                url: None,
+                header_text: None,
+                description_text: None,
+            }),
+        }],
+    };
+    let connects = |network: &Network, from: &str, to: &str| {
+        network
+            .find_connection(from, to, monday, 8 * 3600, &SearchOptions::default())
+            .unwrap()
+            .is_some()
+    };
+    let mut network = load_transport_network(Path::new("test_data/network/"));
+
+    // an alert for another agency closes nothing
+    network.apply_alerts(&closure(EntitySelector {
+        agency_id: Some(String::from("42")),
+        ..EntitySelector::default()
+    }));
+    assert!(connects(&network, "MA1", "MA2"));
+    network.apply_alerts(&closure(EntitySelector {
+        agency_id: Some(String::from("99")),
+        ..EntitySelector::default()
+    }));
+    assert!(!connects(&network, "MA1", "MA2"));
+    assert!(!connects(&network, "T1", "T4"));
+
+    // the trams do not run, the metro does
+    network.apply_alerts(&closure(EntitySelector {
+        route_type: Some(0),
+        ..EntitySelector::default()
+    }));
+    assert!(connects(&network, "MA1", "MA2"));
+    assert!(!connects(&network, "T1", "T4"));
+
+    // a selector without any field selects nothing
+    network.apply_alerts(&closure(EntitySelector::default()));
+    assert!(connects(&network, "MA1", "MA2"));
+    assert!(connects(&network, "T1", "T4"));
+
+    // closing a station closes its platforms
+    network.apply_alerts(&closure(EntitySelector {
+        stop_id: Some(String::from("MAL")),
+        ..EntitySelector::default()
+    }));
+    assert!(!connects(&network, "MA1", "MA3"));
+    assert!(!connects(&network, "T1", "T4"));
+    assert!(connects(&network, "MA1", "MA2"));
+}
@@ -0,0 +1,505 @@
+use crate::model::data_structures::*;
+#[cfg(test)]
+use crate::model::loader::load_transport_network;
+use chrono::{NaiveDate, Timelike, Utc};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+#[cfg(test)]
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use tiny_http::{Header, Response, Server};
+
+mod otp;
+
+// This file contains the HTTP interface answering the queries of the web and mobile apps in JSON.
+
+const DEFAULT_BOARD_LIMIT: usize = 10;
+// the most departures or arrivals listed on a board
+const MAX_BOARD_LIMIT: usize = 100;
+
+/// The network used by the worker threads. The network itself is never modified, a new snapshot
+/// replaces it as a whole, so that the searches in progress can finish with the old one.
+pub struct SharedNetwork {
+    current: RwLock<Arc<Network>>,
+}
+
+impl SharedNetwork {
+    pub fn new(network: Network) -> SharedNetwork {
+        SharedNetwork {
+            current: RwLock::new(Arc::new(network)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Network> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, network: Network) {
+        *self.current.write().unwrap() = Arc::new(network);
+    }
+}
+
+/// Splits the query part of an URL into decoded parameters
+fn parse_query(query: &str) -> HashMap<String, String> {
+    form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Returns the date and the time of the day in seconds from the parameters, the missing ones are
+/// taken from the current time in the timezone of the network
+fn get_date_and_time(
+    network: &Network,
+    params: &HashMap<String, String>,
+) -> Result<(NaiveDate, u32), (u16, String)> {
+    let now = Utc::now().with_timezone(&network.get_timezone());
+    // the OpenTripPlanner clients send dates as MM-DD-YYYY and times as 8:05am
+    let date = match params.get("date") {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(date, "%m-%d-%Y"))
+            .map_err(|_| (400, String::from("Unable to parse the date.")))?,
+        None => now.date_naive(),
+    };
+    let time = match params.get("time") {
+        Some(time) => {
+            otp::parse_otp_time(time).ok_or((400, String::from("Unable to parse the time.")))?
+        }
+        None => now.num_seconds_from_midnight(),
+    };
+    Ok((date, time))
+}
+
+fn get_param<'a>(
+    params: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<&'a String, (u16, String)> {
+    params
+        .get(name)
+        .ok_or((400, format!("Missing parameter {}.", name)))
+}
+
+/// Reads the restrictions of a search from the parameters, the lists of modes, routes and
+/// agencies are separated by commas
+fn get_search_options(params: &HashMap<String, String>) -> Result<SearchOptions, (u16, String)> {
+    let is_set = |name: &str| params.get(name).map_or(false, |value| value == "true");
+    let get_number = |name: &str| -> Result<Option<f32>, (u16, String)> {
+        params
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| (400, format!("Unable to parse {}.", name)))
+            })
+            .transpose()
+    };
+    let mut cycling = None;
+    if is_set("cycling") {
+        let mut settings = Cycling::default();
+        if let Some(speed) = get_number("cyclingSpeed")? {
+            if !(speed > 0.0 && speed.is_finite()) {
+                return Err((400, String::from("cyclingSpeed has to be positive.")));
+            }
+            // km/h in the query
+            settings.speed = speed / 3.6;
+        }
+        if let Some(max_distance) = get_number("cyclingDistance")? {
+            if !(0.0..=MAX_CYCLING_DISTANCE).contains(&max_distance) {
+                return Err((
+                    400,
+                    format!(
+                        "cyclingDistance has to be between 0 and {} meters.",
+                        MAX_CYCLING_DISTANCE
+                    ),
+                ));
+            }
+            settings.max_distance = max_distance;
+        }
+        cycling = Some(settings);
+    }
+    let get_list = |name: &str| -> Vec<String> {
+        params.get(name).map_or(Vec::new(), |value| {
+            value
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    };
+    let get_modes = |name: &str| -> Result<Vec<Mode>, (u16, String)> {
+        get_list(name)
+            .iter()
+            .map(|mode| Mode::from_name(mode).ok_or((400, format!("Unknown mode {}.", mode))))
+            .collect()
+    };
+    // a via stop may be followed by the dwell time in minutes, e.g. via=MA2:5,MAL
+    let via = get_list("via")
+        .iter()
+        .map(|via| {
+            let mut parts = via.splitn(2, ':');
+            let stop_id = parts.next().unwrap_or_default();
+            let min_dwell_time = match parts.next() {
+                Some(minutes) => {
+                    60 * minutes
+                        .parse::<u32>()
+                        .map_err(|_| (400, format!("Unable to parse the dwell time of {}.", via)))?
+                }
+                None => 0,
+            };
+            Ok(Via {
+                stop_id: String::from(stop_id),
+                min_dwell_time: min_dwell_time,
+            })
+        })
+        .collect::<Result<Vec<Via>, (u16, String)>>()?;
+    let night_lines = match params.get("nightLines").map(String::as_str) {
+        None | Some("any") => NightLines::Any,
+        Some("prefer") => NightLines::Prefer,
+        Some("avoid") => NightLines::Avoid,
+        Some("require") => NightLines::Require,
+        Some("exclude") => NightLines::Exclude,
+        Some(_) => return Err((400, String::from("Unable to parse nightLines."))),
+    };
+    Ok(SearchOptions {
+        wheelchair: is_set("wheelchair"),
+        bikes: is_set("bikes"),
+        cycling: cycling,
+        allowed_modes: get_modes("modes")?,
+        denied_modes: get_modes("bannedModes")?,
+        allowed_routes: get_list("routes"),
+        denied_routes: get_list("bannedRoutes"),
+        allowed_agencies: get_list("agencies"),
+        denied_agencies: get_list("bannedAgencies"),
+        night_lines: night_lines,
+        via: via,
+        avoided_stops: get_list("avoid"),
+        max_transfers: params
+            .get("maxTransfers")
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| (400, String::from("Unable to parse maxTransfers.")))
+            })
+            .transpose()?,
+        max_walking_distance: get_number("maxWalkDistance")?,
+        max_walking_leg_distance: get_number("maxLegWalkDistance")?,
+    })
+}
+
+/// Finds a connection, /plan?from=&to=&date=&time=&arriveBy=&format= and the search options,
+/// the places are stop ids or coordinates as latitude,longitude. The search options are
+/// wheelchair, bikes, cycling, cyclingSpeed, cyclingDistance, modes, bannedModes, routes,
+/// bannedRoutes, agencies, bannedAgencies, nightLines, via, avoid, maxTransfers, maxWalkDistance
+/// and maxLegWalkDistance
+/// With format=otp, or under the OpenTripPlanner path /otp/routers/default/plan taking fromPlace
+/// and toPlace, the response uses the OpenTripPlanner format.
+fn plan(
+    network: &Network,
+    params: &HashMap<String, String>,
+    otp_format: bool,
+) -> Result<Value, (u16, String)> {
+    let from = get_param(params, "from").or_else(|_| get_param(params, "fromPlace"))?;
+    let to = get_param(params, "to").or_else(|_| get_param(params, "toPlace"))?;
+    let (date, time) = get_date_and_time(network, params)?;
+    let options = get_search_options(params)?;
+    let arrive_by = params
+        .get("arriveBy")
+        .map_or(false, |value| value == "true");
+    let from_place = Place::parse(from).map_err(|message| (400, String::from(message)))?;
+    let to_place = Place::parse(to).map_err(|message| (400, String::from(message)))?;
+    let itinerary = if arrive_by {
+        network.find_connection_between_places_arriving_by(
+            &from_place,
+            &to_place,
+            date,
+            time,
+            &options,
+        )
+    } else {
+        network.find_connection_between_places(&from_place, &to_place, date, time, &options)
+    }
+    .map_err(|message| (404, String::from(message)))?;
+    if otp_format {
+        return Ok(otp::plan_response(
+            network,
+            from,
+            to,
+            date,
+            time,
+            itinerary.as_ref(),
+        ));
+    }
+    Ok(json!({
+        "itineraries": itinerary.iter().map(Itinerary::to_json).collect::<Vec<Value>>(),
+    }))
+}
+
+/// Searches the stops by their names, /stops?q=
+fn stops(network: &Network, params: &HashMap<String, String>) -> Result<Value, (u16, String)> {
+    let query = get_param(params, "q")?;
+    let stops: Vec<Value> = network
+        .find_stops(query)
+        .iter()
+        .map(|stop| {
+            json!({
+                "stop_id": stop.stop_id,
+                "stop_name": stop.stop_name,
+                "stop_lat": stop.stop_lat,
+                "stop_lon": stop.stop_lon,
+                "platform_code": stop.platform_code,
+            })
+        })
+        .collect();
+    Ok(json!({ "stops": stops }))
+}
+
+/// Returns the number of departures or arrivals requested, at most MAX_BOARD_LIMIT
+fn get_limit(params: &HashMap<String, String>) -> Result<usize, (u16, String)> {
+    match params.get("limit") {
+        Some(limit) => limit
+            .parse()
+            .map(|limit: usize| limit.min(MAX_BOARD_LIMIT))
+            .map_err(|_| (400, String::from("Unable to parse the limit."))),
+        None => Ok(DEFAULT_BOARD_LIMIT),
+    }
+}
+
+/// Lists the next departures from a stop, /stops/{id}/departures?date=&time=&limit=
+fn departures(
+    network: &Network,
+    stop_id: &str,
+    params: &HashMap<String, String>,
+) -> Result<Value, (u16, String)> {
+    let (date, time) = get_date_and_time(network, params)?;
+    let departures = network
+        .departures(
+            stop_id,
+            date,
+            time,
+            get_limit(params)?,
+            &get_search_options(params)?,
+        )
+        .map_err(|message| (404, String::from(message)))?;
+    Ok(json!({
+        "departures": departures.iter().map(Departure::to_json).collect::<Vec<Value>>(),
+    }))
+}
+
+/// Lists the next arrivals to a stop, /stops/{id}/arrivals?date=&time=&limit=
+fn arrivals(
+    network: &Network,
+    stop_id: &str,
+    params: &HashMap<String, String>,
+) -> Result<Value, (u16, String)> {
+    let (date, time) = get_date_and_time(network, params)?;
+    let arrivals = network
+        .arrivals(
+            stop_id,
+            date,
+            time,
+            get_limit(params)?,
+            &get_search_options(params)?,
+        )
+        .map_err(|message| (404, String::from(message)))?;
+    Ok(json!({
+        "arrivals": arrivals.iter().map(Arrival::to_json).collect::<Vec<Value>>(),
+    }))
+}
+
+/// Lists the stops of a trip, /trips/{id}?date=
+fn trip(
+    network: &Network,
+    trip_id: &str,
+    params: &HashMap<String, String>,
+) -> Result<Value, (u16, String)> {
+    let (date, _) = get_date_and_time(network, params)?;
+    network
+        .trip_detail(trip_id, date)
+        .map(|detail| detail.to_json())
+        .map_err(|message| (404, String::from(message)))
+}
+
+/// Answers a request for the given URL, returns the status code and the JSON body
+pub fn handle_request(network: &Network, url: &str) -> (u16, Value) {
+    let (path, query) = match url.find('?') {
+        Some(index) => (&url[..index], &url[index + 1..]),
+        None => (url, ""),
+    };
+    let params = parse_query(query);
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let result = match segments.as_slice() {
+        ["health"] => Ok(json!({ "status": "ok" })),
+        ["plan"] => {
+            let otp_format = params.get("format").map_or(false, |format| format == "otp");
+            plan(network, &params, otp_format)
+        }
+        ["otp", "routers", _, "plan"] => plan(network, &params, true),
+        ["stops"] => stops(network, &params),
+        ["stops", stop_id, "departures"] => departures(network, stop_id, &params),
+        ["stops", stop_id, "arrivals"] => arrivals(network, stop_id, &params),
+        ["trips", trip_id] => trip(network, trip_id, &params),
+        _ => Err((404, String::from("Not found."))),
+    };
+    match result {
+        Ok(body) => (200, body),
+        Err((status, message)) => (status, json!({ "error": message })),
+    }
+}
+
+#[test]
+fn test_request_handling() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let (status, body) = handle_request(&network, "/health");
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "ok");
+
+    let (status, body) = handle_request(
+        &network,
+        "/plan?from=MA1&to=T4&date=2020-01-27&time=08%3A00%3A00",
+    );
+    assert_eq!(status, 200);
+    let itinerary = &body["itineraries"][0];
+    assert_eq!(itinerary["arrival_time"], "08:16:00");
+    assert_eq!(itinerary["legs"][0]["trip_id"], "LA_1");
+    let (_, body) = handle_request(
+        &network,
+        "/plan?from=MA1&to=T4&date=2020-01-27&time=08:20:00&arriveBy=true",
+    );
+    assert_eq!(body["itineraries"][0]["legs"][0]["trip_id"], "LA_1");
+    assert_eq!(body["itineraries"][0]["departure_time"], "08:00:00");
+    let (_, body) = handle_request(
+        &network,
+        "/plan?from=MA1&to=T4&date=2020-01-27&time=08:00:00&via=MA2:4",
+    );
+    assert_eq!(body["itineraries"][0]["arrival_time"], "08:26:00");
+    let (_, body) = handle_request(
+        &network,
+        "/plan?from=50.0805,14.4005&to=T4&date=2020-01-27&time=07:59:00",
+    );
+    assert_eq!(body["itineraries"][0]["legs"][0]["to_stop_id"], "MA1");
+    assert_eq!(
+        handle_request(&network, "/plan?from=MA1&to=T4&via=MA2:later").0,
+        400
+    );
+    for cycling in [
+        "cyclingSpeed=0",
+        "cyclingSpeed=-5",
+        "cyclingSpeed=NaN",
+        "cyclingDistance=-1",
+        "cyclingDistance=2147483647",
+    ]
+    .iter()
+    {
+        let url = format!("/plan?from=MA1&to=MC2&cycling=true&{}", cycling);
+        assert_eq!(handle_request(&network, &url).0, 400);
+    }
+    let (status, body) = handle_request(
+        &network,
+        "/otp/routers/default/plan?fromPlace=MA1&toPlace=T4&date=01-27-2020&time=8:00am",
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["plan"]["itineraries"][0]["legs"][0]["mode"], "SUBWAY");
+    let (status, body) = handle_request(
+        &network,
+        "/otp/routers/default/plan?fromPlace=Dejvick%C3%A1%3A%3A50.0805%2C14.4005&toPlace=T4&date=01-27-2020&time=7:59am",
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["plan"]["itineraries"][0]["legs"][1]["mode"], "SUBWAY");
+    assert_eq!(handle_request(&network, "/plan?from=nan,nan&to=T4").0, 400);
+    assert_eq!(
+        handle_request(&network, "/plan?from=MA1&to=1000,1000").0,
+        400
+    );
+
+    for max_transfers in ["-1", "1.5", "many"].iter() {
+        let url = format!("/plan?from=MA1&to=T4&maxTransfers={}", max_transfers);
+        assert_eq!(handle_request(&network, &url).0, 400);
+    }
+    assert_eq!(
+        handle_request(&network, "/plan?from=MA1&to=T4&maxTransfers=0").0,
+        200
+    );
+
+    let (status, body) = handle_request(&network, "/stops?q=malostransk%C3%A1");
+    assert_eq!(status, 200);
+    let stop_ids: Vec<&str> = body["stops"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|stop| stop["stop_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(stop_ids, vec!["MA3", "MAL", "T1"]);
+
+    let (status, body) = handle_request(
+        &network,
+        "/stops/MA1/departures?date=2020-01-27&time=08:01:00&limit=5",
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["departures"].as_array().unwrap().len(), 1);
+    assert_eq!(body["departures"][0]["trip_id"], "LA_2");
+    let (_, body) = handle_request(
+        &network,
+        "/stops/MA1/departures?date=2020-01-27&time=08:00:00&bannedModes=SUBWAY",
+    );
+    assert!(body["departures"].as_array().unwrap().is_empty());
+    assert_eq!(
+        handle_request(&network, "/stops/MA1/departures?modes=HOVERCRAFT").0,
+        400
+    );
+    assert_eq!(
+        handle_request(&network, "/stops/MA1/departures?limit=-1").0,
+        400
+    );
+    assert_eq!(
+        get_limit(&parse_query("limit=1000000000")),
+        Ok(MAX_BOARD_LIMIT)
+    );
+    let (_, body) = handle_request(
+        &network,
+        "/stops/MAL/arrivals?date=2020-01-27&time=08:00:00",
+    );
+    assert_eq!(body["arrivals"][0]["trip_id"], "LA_1");
+    let (_, body) = handle_request(&network, "/trips/L22_1?date=2020-01-27");
+    assert_eq!(body["stops"].as_array().unwrap().len(), 4);
+    assert_eq!(body["stops"][1]["stop_name"], "Klárov");
+
+    assert_eq!(handle_request(&network, "/plan?from=MA1").0, 400);
+    assert_eq!(handle_request(&network, "/stops/XX/departures").0, 404);
+    assert_eq!(handle_request(&network, "/unknown").0, 404);
+}
+
+/// Serves the requests on the given address using a pool of worker threads, blocks forever
+pub fn serve(address: &str, network: Arc<SharedNetwork>, threads: usize) -> io::Result<()> {
+    let server = Arc::new(
+        Server::http(address).map_err(|error| io::Error::new(io::ErrorKind::Other, error))?,
+    );
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let server = server.clone();
+            let network = network.clone();
+            let content_type = content_type.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let (status, body) = handle_request(&network.get(), request.url());
+                    let response = Response::from_string(body.to_string())
+                        .with_status_code(status)
+                        .with_header(content_type.clone());
+                    // the client may have disconnected already
+                    let _ = request.respond(response);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    Ok(())
+}
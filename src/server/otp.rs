@@ -0,0 +1,233 @@
+use crate::model::data_structures::*;
+#[cfg(test)]
+use crate::model::loader::load_transport_network;
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+#[cfg(test)]
+use std::path::Path;
+
+// This file converts the found connections into the response format of the OpenTripPlanner REST
+// plan endpoint, so that the front-ends written for OpenTripPlanner can use prahadlo.
+
+/// Parses time of the day as sent by the OpenTripPlanner clients, e.g. 8:05am, into seconds
+pub fn parse_otp_time(time: &str) -> Option<u32> {
+    let lowercase = time.to_lowercase();
+    let (time, offset) = if let Some(time) = lowercase.strip_suffix("am") {
+        (time, 0)
+    } else if let Some(time) = lowercase.strip_suffix("pm") {
+        (time, 12 * 3600)
+    } else {
+        return parse_time(time);
+    };
+    let mut parts = time.trim().split(':');
+    let hours: u32 = parts.next()?.parse().ok()?;
+    let minutes: u32 = parts
+        .next()
+        .map_or(Some(0), |minutes| minutes.parse().ok())?;
+    if hours == 0 || hours > 12 || minutes >= 60 || parts.next().is_some() {
+        return None;
+    }
+    Some((hours % 12) * 3600 + offset + minutes * 60)
+}
+
+#[test]
+fn test_otp_time_parsing() {
+    assert_eq!(parse_otp_time("8:05am"), Some(8 * 3600 + 5 * 60));
+    assert_eq!(parse_otp_time("12:30am"), Some(30 * 60));
+    assert_eq!(parse_otp_time("1:00pm"), Some(13 * 3600));
+    assert_eq!(parse_otp_time("12:00pm"), Some(12 * 3600));
+    assert_eq!(parse_otp_time("08:05:00"), Some(8 * 3600 + 5 * 60));
+    assert_eq!(parse_otp_time("13:00pm"), None);
+}
+
+fn place(network: &Network, stop_id: &str) -> Value {
+    match network.get_stop(stop_id) {
+        Some(stop) => json!({
+            "name": stop.stop_name,
+            "stopId": stop.stop_id,
+            "lat": stop.stop_lat,
+            "lon": stop.stop_lon,
+            "platformCode": stop.platform_code,
+            "vertexType": "TRANSIT",
+        }),
+        None => match Place::parse(stop_id) {
+            Ok(Place::Coordinates(point)) => json!({
+                "name": stop_id,
+                "lat": point.y(),
+                "lon": point.x(),
+                "vertexType": "NORMAL",
+            }),
+            _ => json!({ "stopId": stop_id }),
+        },
+    }
+}
+
+fn leg(network: &Network, leg: &Leg, service_day_start: i64) -> Value {
+    // OpenTripPlanner uses milliseconds since the epoch
+    let start_time = (service_day_start + leg.departure_time as i64) * 1000;
+    let end_time = (service_day_start + leg.arrival_time as i64) * 1000;
+    let mut from = place(network, &leg.from_stop_id);
+    from["departure"] = json!(start_time);
+    let mut to = place(network, &leg.to_stop_id);
+    to["arrival"] = json!(end_time);
+    let alerts: Vec<Value> = leg
+        .alerts
+        .iter()
+        .map(|alert| {
+            json!({
+                "alertHeaderText": alert.header_text,
+                "alertDescriptionText": alert.description_text,
+                "alertUrl": alert.url,
+            })
+        })
+        .collect();
+    let mut json = json!({
+        "startTime": start_time,
+        "endTime": end_time,
+        "duration": leg.arrival_time - leg.departure_time,
+        "distance": leg.distance,
+        "mode": if leg.cycling { "BICYCLE" } else { "WALK" },
+        "transitLeg": false,
+        "realTime": leg.departure_delay.is_some() || leg.arrival_delay.is_some(),
+        "departureDelay": leg.departure_delay.unwrap_or(0),
+        "arrivalDelay": leg.arrival_delay.unwrap_or(0),
+        "from": from,
+        "to": to,
+        "legGeometry": {
+            "points": leg.geometry.as_ref().map_or(String::new(), encode_polyline),
+            "length": leg.geometry.as_ref().map_or(0, |geometry| geometry.0.len()),
+        },
+        "alerts": alerts,
+    });
+    let trip = leg
+        .trip_id
+        .as_ref()
+        .and_then(|trip_id| network.get_trip(trip_id));
+    if let Some(trip) = trip {
+        json["transitLeg"] = json!(true);
+        json["tripId"] = json!(trip.trip_id);
+        json["headsign"] = json!(trip.trip_headsign);
+        json["routeId"] = json!(trip.route_id);
+        json["headway"] = json!(leg.headway);
+        if let Some(route) = network.get_route(&trip.route_id) {
+            json["mode"] = json!(route.get_mode().get_name());
+            json["route"] = json!(route.route_short_name);
+            json["routeShortName"] = json!(route.route_short_name);
+            json["routeLongName"] = json!(route.route_long_name);
+            json["routeType"] = json!(route.route_type);
+            json["routeColor"] = json!(route.route_color);
+            json["routeTextColor"] = json!(route.route_text_color);
+            json["agencyId"] = json!(route.agency_id);
+        }
+    }
+    json
+}
+
+fn itinerary(network: &Network, itinerary: &Itinerary, service_day_start: i64) -> Value {
+    let start = itinerary.departure_time().unwrap_or(0);
+    let end = itinerary.arrival_time().unwrap_or(0);
+    let (mut walk_time, mut transit_time) = (0, 0);
+    for leg in &itinerary.legs {
+        if leg.is_walk() {
+            walk_time += leg.arrival_time - leg.departure_time;
+        } else {
+            transit_time += leg.arrival_time - leg.departure_time;
+        }
+    }
+    let mut json = json!({
+        "duration": end - start,
+        "startTime": (service_day_start + start as i64) * 1000,
+        "endTime": (service_day_start + end as i64) * 1000,
+        "walkTime": walk_time,
+        "transitTime": transit_time,
+        "waitingTime": (end - start).saturating_sub(walk_time + transit_time),
+        "walkDistance": itinerary.walking_distance(),
+        "transfers": itinerary.transfer_count(),
+        "legs": itinerary
+            .legs
+            .iter()
+            .map(|l| leg(network, l, service_day_start))
+            .collect::<Vec<Value>>(),
+    });
+    // the regular fare in cents, as reported by OpenTripPlanner
+    if let Some(fare) = &itinerary.fare {
+        json["fare"] = json!({
+            "fare": {
+                "regular": {
+                    "cents": (fare.price * 100.0).round() as i64,
+                    "currency": {
+                        "currency": fare.currency,
+                        "currencyCode": fare.currency,
+                        "defaultFractionDigits": 2,
+                    },
+                },
+            },
+        });
+    }
+    json
+}
+
+/// Builds the response of the OpenTripPlanner plan endpoint
+/// # Arguments
+/// * from, to - the stops of the search
+/// * date, time - the time of the search in the timezone of the network
+/// * found - the itinerary found, if any
+pub fn plan_response(
+    network: &Network,
+    from: &str,
+    to: &str,
+    date: NaiveDate,
+    time: u32,
+    found: Option<&Itinerary>,
+) -> Value {
+    let service_day_start = network.get_service_day_start(date);
+    let mut response = json!({
+        "plan": {
+            "date": (service_day_start + time as i64) * 1000,
+            "from": place(network, from),
+            "to": place(network, to),
+            "itineraries": found
+                .iter()
+                .map(|found| itinerary(network, found, service_day_start))
+                .collect::<Vec<Value>>(),
+        },
+    });
+    if found.is_none() {
+        response["error"] = json!({
+            "id": 404,
+            "msg": "No trip found.",
+            "message": "PATH_NOT_FOUND",
+        });
+    }
+    response
+}
+
+#[test]
+fn test_plan_response() {
+    let network = load_transport_network(Path::new("test_data/network/"));
+    let monday = NaiveDate::from_ymd(2020, 1, 27);
+    let found = network
+        .find_connection("MA1", "T4", monday, 8 * 3600, &SearchOptions::default())
+        .unwrap();
+    let response = plan_response(&network, "MA1", "T4", monday, 8 * 3600, found.as_ref());
+    assert_eq!(response["plan"]["from"]["name"], "Dejvická");
+    let itinerary = &response["plan"]["itineraries"][0];
+    // 2020-01-27 08:00:00 CET
+    assert_eq!(itinerary["startTime"], 1_580_108_400_000i64);
+    assert_eq!(itinerary["transfers"], 1);
+    assert_eq!(itinerary["fare"]["fare"]["regular"]["cents"], 4000);
+    let legs = itinerary["legs"].as_array().unwrap();
+    assert_eq!(legs[0]["mode"], "SUBWAY");
+    assert_eq!(legs[0]["routeShortName"], "A");
+    assert_eq!(legs[0]["from"]["stopId"], "MA1");
+    assert_eq!(legs[1]["mode"], "WALK");
+    assert_eq!(legs[2]["mode"], "TRAM");
+    assert_eq!(legs[2]["to"]["name"], "Právnická fakulta");
+    assert!(!legs[2]["legGeometry"]["points"]
+        .as_str()
+        .unwrap()
+        .is_empty());
+
+    let response = plan_response(&network, "MA1", "T4", monday, 20 * 3600, None);
+    assert_eq!(response["error"]["message"], "PATH_NOT_FOUND");
+}
@@ -0,0 +1,19 @@
+
+
+2.0|
+
+hradcanska*n
+**MA28R
+
+Hradčanská uzavřenacsZ5
+3
+-Vlaky stanicí projíždějí bez zastavení.csY
+tramvaje*M*L228B#
+!
+https://pid.cz/mimoradnosticsR
+
+Zpoždění tramvajícs>
+metro-c*3
+*LC8R
+
+Linka C mimo provozcs
\ No newline at end of file
@@ -0,0 +1,15 @@
+
+
+2.0$
+1
+
+LA_120200127Z"MA2
+2
+
+L22_220200127 "
+3
+
+L22_120200127"T2(#
+4
+
+LC_T08:10:0020200127(<
\ No newline at end of file